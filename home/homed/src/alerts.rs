@@ -1,51 +1,1047 @@
-use crate::config::AlertsConfig;
-use tracing::warn;
-
-pub async fn send_alert(
-    client: &reqwest::Client,
-    config: &AlertsConfig,
-    message: &str,
-) -> Result<(), reqwest::Error> {
-    client
-        .post(format!("{}/{}", config.url, config.topic))
-        .bearer_auth(&config.token)
-        .body(message.to_string())
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok(())
-}
-
-pub async fn send_batch_alert(
-    client: &reqwest::Client,
-    config: &AlertsConfig,
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::api;
+use crate::batch::Batch;
+use crate::config::{ActionKind, AlertAction, AlertKind, AlertTemplate, AlertsConfig};
+use crate::notify::{self, Notifier};
+use crate::outbox::Outbox;
+use crate::watcher::{FileEvent, Pipeline};
+
+/// Quarantine reasons listed in a summary, the rest are only counted.
+const SUMMARY_REASONS: usize = 3;
+const SUMMARY_REASON_LEN: usize = 60;
+/// `max_per_hour` counts alerts over this sliding window.
+const RATE_WINDOW: Duration = Duration::from_secs(3600);
+/// Alerts listed when a rate limit lets up, the rest are only counted.
+const HELD_LISTED: usize = 10;
+
+/// Something worth a notification, with the values its template can use.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub kind: AlertKind,
+    vars: Vec<(&'static str, String)>,
+}
+
+impl Alert {
+    pub fn summary(summary: String) -> Self {
+        Self {
+            kind: AlertKind::Summary,
+            vars: vec![("summary", summary)],
+        }
+    }
+
+    pub fn rescan(pipeline: Pipeline, reason: &str) -> Self {
+        Self {
+            kind: AlertKind::Rescan,
+            vars: vec![
+                ("pipeline", pipeline.to_string()),
+                ("reason", reason.to_string()),
+            ],
+        }
+    }
+
+    pub fn quarantine(pipeline: Pipeline, path: &Path, id: &str, reason: &str) -> Self {
+        Self {
+            kind: AlertKind::Quarantine,
+            vars: file_vars(pipeline, path, id, reason),
+        }
+    }
+
+    /// Malware that was quarantined as `id`, or is still in place when
+    /// `id` is None.
+    pub fn malware(pipeline: Pipeline, path: &Path, id: Option<&str>, reason: &str) -> Self {
+        let mut vars = file_vars(pipeline, path, id.unwrap_or(""), reason);
+        let status = match id {
+            Some(_) => "quarantined",
+            None => "NOT quarantined",
+        };
+        vars.push(("status", status.to_string()));
+        Self {
+            kind: AlertKind::Malware,
+            vars,
+        }
+    }
+
+    /// The immediate alert for an event, if it's security relevant.
+    pub fn for_event(event: &FileEvent) -> Option<Self> {
+        match event {
+            FileEvent::Rescan { pipeline, reason } => Some(Self::rescan(*pipeline, reason)),
+            FileEvent::Quarantined {
+                pipeline,
+                path,
+                id,
+                reason,
+                malware: true,
+            } => Some(Self::malware(*pipeline, path, Some(id), reason)),
+            FileEvent::Quarantined {
+                pipeline,
+                path,
+                id,
+                reason,
+                ..
+            } => Some(Self::quarantine(*pipeline, path, id, reason)),
+            FileEvent::Scanned {
+                pipeline,
+                path,
+                clean: false,
+            } => Some(Self::malware(
+                *pipeline,
+                path,
+                None,
+                "malware detected, quarantine failed",
+            )),
+            _ => None,
+        }
+    }
+}
+
+fn file_vars(
+    pipeline: Pipeline,
+    path: &Path,
+    id: &str,
+    reason: &str,
+) -> Vec<(&'static str, String)> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    vec![
+        ("pipeline", pipeline.to_string()),
+        ("name", name),
+        ("path", path.display().to_string()),
+        ("id", id.to_string()),
+        ("reason", reason.to_string()),
+    ]
+}
+
+/// What one pipeline did since the last summary.
+#[derive(Debug, Default)]
+struct Counts {
+    passed: usize,
     organized: usize,
     unsorted: usize,
+    quarantined: usize,
+    /// Distinct quarantine reasons, in order of first appearance
+    reasons: Vec<String>,
+    removed: usize,
     failed: usize,
-) {
-    if !config.enabled {
-        return;
+    rescans: usize,
+}
+
+impl Counts {
+    fn is_empty(&self) -> bool {
+        self.passed
+            + self.organized
+            + self.unsorted
+            + self.quarantined
+            + self.removed
+            + self.failed
+            + self.rescans
+            == 0
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.passed > 0 {
+            parts.push(format!("{} passed", self.passed));
+        }
+        if self.organized > 0 {
+            parts.push(format!("{} organized", self.organized));
+        }
+        if self.unsorted > 0 {
+            parts.push(format!("{} unsorted", self.unsorted));
+        }
+        if self.quarantined > 0 {
+            let mut reasons: Vec<String> = self
+                .reasons
+                .iter()
+                .take(SUMMARY_REASONS)
+                .map(|reason| shorten(reason))
+                .collect();
+            if self.reasons.len() > SUMMARY_REASONS {
+                reasons.push(format!("{} more", self.reasons.len() - SUMMARY_REASONS));
+            }
+            parts.push(format!(
+                "{} quarantined ({})",
+                self.quarantined,
+                reasons.join("; ")
+            ));
+        }
+        if self.removed > 0 {
+            parts.push(format!("{} junk files removed", self.removed));
+        }
+        if self.failed > 0 {
+            parts.push(format!("{} failed", self.failed));
+        }
+        if self.rescans > 0 {
+            parts.push(format!("{} watcher rescans", self.rescans));
+        }
+        f.write_str(&parts.join(", "))
+    }
+}
+
+fn shorten(reason: &str) -> String {
+    if reason.chars().count() <= SUMMARY_REASON_LEN {
+        return reason.to_string();
+    }
+    let mut short: String = reason.chars().take(SUMMARY_REASON_LEN - 1).collect();
+    short.push('…');
+    short
+}
+
+/// Per-pipeline counts of the events since the last batch alert.
+#[derive(Debug, Default)]
+pub struct Summary {
+    photos: Counts,
+    media: Counts,
+}
+
+impl Summary {
+    pub fn record(&mut self, event: &FileEvent) {
+        let counts = match event.pipeline() {
+            Pipeline::Photos => &mut self.photos,
+            Pipeline::Media => &mut self.media,
+        };
+        match event {
+            FileEvent::Scanned { clean: true, .. } => counts.passed += 1,
+            FileEvent::Scanned { clean: false, .. } => counts.failed += 1,
+            FileEvent::Organized { .. } => counts.organized += 1,
+            FileEvent::Unsorted { .. } => counts.unsorted += 1,
+            FileEvent::Quarantined { reason, .. } => {
+                counts.quarantined += 1;
+                if !counts.reasons.contains(reason) {
+                    counts.reasons.push(reason.clone());
+                }
+            }
+            FileEvent::Cleaned { .. } => counts.removed += 1,
+            FileEvent::Failed { .. } => counts.failed += 1,
+            FileEvent::Rescan { .. } => counts.rescans += 1,
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photos.is_empty() && self.media.is_empty()
     }
+}
 
-    if organized == 0 && unsorted == 0 && failed == 0 {
-        return;
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines: Vec<String> = [
+            (Pipeline::Photos, &self.photos),
+            (Pipeline::Media, &self.media),
+        ]
+        .into_iter()
+        .filter(|(_, counts)| !counts.is_empty())
+        .map(|(pipeline, counts)| format!("{pipeline}: {counts}"))
+        .collect();
+        f.write_str(&lines.join("\n"))
     }
+}
 
-    let mut parts = Vec::new();
-    if organized > 0 {
-        parts.push(format!("{} organized", organized));
+/// The built-in template of each kind. `api_url` and, for quarantined
+/// items, `restore_url` are only set when `alerts.api` is configured.
+fn default_template(kind: AlertKind, with_api: bool) -> AlertTemplate {
+    match kind {
+        AlertKind::Summary => AlertTemplate {
+            title: Some("homed".to_string()),
+            message: Some("{summary}".to_string()),
+            priority: Some(3),
+            tags: Some(vec!["file_folder".to_string()]),
+            ..Default::default()
+        },
+        AlertKind::Rescan => AlertTemplate {
+            title: Some("{pipeline} watcher rescan".to_string()),
+            message: Some("Watcher missed filesystem events ({reason}), rescanning".to_string()),
+            priority: Some(4),
+            tags: Some(vec!["warning".to_string()]),
+            ..Default::default()
+        },
+        AlertKind::Quarantine => AlertTemplate {
+            title: Some("Quarantined {name}".to_string()),
+            message: Some("{reason}\n{path}".to_string()),
+            priority: Some(4),
+            tags: Some(vec!["rotating_light".to_string()]),
+            actions: with_api.then(|| {
+                vec![AlertAction {
+                    action: ActionKind::Http,
+                    label: "Restore from quarantine".to_string(),
+                    url: "{restore_url}".to_string(),
+                    method: "POST".to_string(),
+                    headers: Default::default(),
+                }]
+            }),
+            ..Default::default()
+        },
+        // Restoring malware should take a deliberate `homed quarantine restore`
+        AlertKind::Malware => AlertTemplate {
+            title: Some("Malware {status}: {name}".to_string()),
+            message: Some("{reason}\n{path}".to_string()),
+            priority: Some(5),
+            tags: Some(vec!["skull".to_string()]),
+            ..Default::default()
+        },
     }
-    if unsorted > 0 {
-        parts.push(format!("{} unsorted", unsorted));
+}
+
+/// A rendered alert, the same for every notifier.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub kind: AlertKind,
+    pub title: String,
+    pub body: String,
+    /// 1 (min) to 5 (urgent)
+    pub priority: u8,
+    pub tags: Vec<String>,
+    pub click: Option<String>,
+    /// Buttons, for notifiers that support them
+    pub actions: Vec<AlertAction>,
+}
+
+/// Renders an alert from the kind's default template, overridden field by
+/// field from `alerts.templates`.
+pub fn render(config: &AlertsConfig, alert: &Alert) -> Message {
+    let defaults = default_template(alert.kind, config.api.is_some());
+    let custom = config
+        .templates
+        .get(&alert.kind)
+        .cloned()
+        .unwrap_or_default();
+
+    let mut vars = alert.vars.clone();
+    if let Some(api) = &config.api {
+        vars.push(("api_url", api.public_url()));
+        if let Some((_, id)) = alert
+            .vars
+            .iter()
+            .find(|(name, id)| *name == "id" && !id.is_empty())
+        {
+            vars.push(("restore_url", api::restore_url(api, id)));
+        }
     }
-    if failed > 0 {
-        parts.push(format!("{} failed", failed));
+    let fill = |text: &str| fill_placeholders(text, &vars);
+
+    Message {
+        kind: alert.kind,
+        title: fill(&custom.title.or(defaults.title).unwrap_or_default()),
+        body: fill(&custom.message.or(defaults.message).unwrap_or_default()),
+        priority: custom.priority.or(defaults.priority).unwrap_or(3),
+        tags: custom.tags.or(defaults.tags).unwrap_or_default(),
+        click: custom.click.or(defaults.click).map(|click| fill(&click)),
+        actions: custom
+            .actions
+            .or(defaults.actions)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|action| AlertAction {
+                label: fill(&action.label),
+                url: fill(&action.url),
+                headers: action
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), fill(value)))
+                    .collect(),
+                ..action
+            })
+            .collect(),
+    }
+}
+
+/// Replaces `{key}` with its value in one pass, so values that happen to
+/// contain placeholders (file names) are never expanded. Unknown
+/// placeholders are kept as they are.
+fn fill_placeholders(text: &str, vars: &[(&'static str, String)]) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            let key = &after[..end];
+            let (_, value) = vars.iter().find(|(k, _)| *k == key)?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                filled.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Alerts a notifier accepted in the last hour, and those held back
+/// because there were too many.
+#[derive(Debug, Default)]
+struct RateLimit {
+    max_per_hour: Option<u32>,
+    sent: VecDeque<Instant>,
+    held: Vec<Message>,
+}
+
+impl RateLimit {
+    /// Counts the alert against the limit, or returns false when the limit
+    /// is reached. Urgent alerts always pass, but still count.
+    fn allow(&mut self, urgent: bool) -> bool {
+        let now = Instant::now();
+        while self.sent.front().is_some_and(|&t| t + RATE_WINDOW <= now) {
+            self.sent.pop_front();
+        }
+
+        let full = self
+            .max_per_hour
+            .is_some_and(|max| self.sent.len() >= max as usize);
+        if full && !urgent {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+
+    /// When a slot frees up for the held alerts, if there are any.
+    fn next_free(&self) -> Option<Instant> {
+        if self.held.is_empty() {
+            return None;
+        }
+        Some(
+            self.sent
+                .front()
+                .map_or_else(Instant::now, |&t| t + RATE_WINDOW),
+        )
+    }
+}
+
+/// The configured notifiers, the routes that pick among them and the
+/// queue of alerts they haven't taken yet.
+pub struct Dispatcher {
+    config: AlertsConfig,
+    notifiers: Vec<(String, Box<dyn Notifier>)>,
+    limits: HashMap<String, RateLimit>,
+    outbox: Outbox,
+}
+
+impl Dispatcher {
+    /// Also loads the alerts left undelivered by the last run. Blocking.
+    pub fn new(config: AlertsConfig, client: reqwest::Client) -> Self {
+        let notifiers = config
+            .all_notifiers()
+            .into_iter()
+            .map(|notifier| {
+                (
+                    notifier.name,
+                    notify::build(notifier.backend, client.clone()),
+                )
+            })
+            .collect();
+        Self::with_notifiers(config, notifiers)
+    }
+
+    fn with_notifiers(config: AlertsConfig, notifiers: Vec<(String, Box<dyn Notifier>)>) -> Self {
+        let names: Vec<&str> = notifiers.iter().map(|(name, _)| name.as_str()).collect();
+        let outbox = Outbox::open(config.queue_file.clone(), &names);
+        let configured = config.all_notifiers();
+        let limits = names
+            .iter()
+            .map(|&name| {
+                let own = configured
+                    .iter()
+                    .find(|n| n.name == name)
+                    .and_then(|n| n.max_per_hour);
+                let limit = RateLimit {
+                    max_per_hour: own.or(config.max_per_hour),
+                    ..Default::default()
+                };
+                (name.to_string(), limit)
+            })
+            .collect();
+
+        Self {
+            config,
+            notifiers,
+            limits,
+            outbox,
+        }
+    }
+
+    /// Whether the alert is urgent enough to skip quiet hours, the digest
+    /// and rate limits.
+    pub fn is_urgent(&self, alert: &Alert) -> bool {
+        render(&self.config, alert).priority >= self.config.urgent_priority
+    }
+
+    /// Names of the notifiers a message goes to: those of every route that
+    /// matches its kind and priority, or all of them without routes.
+    fn targets(&self, message: &Message) -> Vec<String> {
+        let names = self.notifiers.iter().map(|(name, _)| name);
+        if self.config.routes.is_empty() {
+            return names.cloned().collect();
+        }
+
+        names
+            .filter(|name| {
+                self.config.routes.iter().any(|route| {
+                    (route.kinds.is_empty() || route.kinds.contains(&message.kind))
+                        && message.priority >= route.min_priority
+                        && route.notifiers.contains(name)
+                })
+            })
+            .cloned()
+            .collect()
     }
 
-    let message = format!("Photos: {}", parts.join(", "));
+    /// Queues the alert for its notifiers and delivers it to those that
+    /// aren't backing off after a failure.
+    pub async fn send(&mut self, alert: &Alert) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let message = render(&self.config, alert);
+        let urgent = message.priority >= self.config.urgent_priority;
+        let mut targets = self.targets(&message);
+        targets.retain(|name| {
+            let Some(limit) = self.limits.get_mut(name) else {
+                return true;
+            };
+            if limit.allow(urgent) {
+                return true;
+            }
+            info!(
+                notifier = name,
+                kind = alert.kind.name(),
+                "rate limit reached, holding alert back"
+            );
+            limit.held.push(message.clone());
+            false
+        });
+        for name in &targets {
+            if !self.outbox.push(name, message.clone()) {
+                info!(
+                    notifier = name,
+                    kind = alert.kind.name(),
+                    "same alert already queued"
+                );
+            }
+        }
+        for name in &targets {
+            if self.outbox.is_due(name) {
+                self.deliver(name).await;
+            }
+        }
+        self.outbox.save().await;
+    }
+
+    /// When the next retry is due, if any notifier is backing off or has
+    /// alerts held back by its rate limit.
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.limits
+            .values()
+            .filter_map(RateLimit::next_free)
+            .chain(self.outbox.next_retry())
+            .min()
+    }
+
+    /// Retries the notifiers whose backoff is over, and sends what the
+    /// rate limits held back as one message once they allow it.
+    pub async fn retry(&mut self) {
+        let now = Instant::now();
+        let mut due = self.outbox.due();
+        for (name, limit) in &mut self.limits {
+            if limit.next_free().is_none_or(|free| free > now) {
+                continue;
+            }
+            let held = std::mem::take(&mut limit.held);
+            limit.allow(true);
+            self.outbox.push(name, held_note(&held));
+            if !due.contains(name) && self.outbox.is_due(name) {
+                due.push(name.clone());
+            }
+        }
+
+        for name in due {
+            self.deliver(&name).await;
+        }
+        self.outbox.save().await;
+    }
+
+    /// Sends queued alerts in order until the notifier fails, then a note
+    /// about the delay once it has caught up.
+    async fn deliver(&mut self, name: &str) {
+        let Some((_, notifier)) = self.notifiers.iter().find(|(n, _)| n == name) else {
+            return;
+        };
+
+        while let Some(message) = self.outbox.front(name) {
+            if let Err(e) = notifier.send(&message).await {
+                let retry_in = self.outbox.failed(name);
+                warn!(
+                    notifier = name,
+                    kind = message.kind.name(),
+                    error = %e,
+                    retry_in = ?retry_in,
+                    "failed to send alert, queued for retry"
+                );
+                return;
+            }
+            self.outbox.delivered(name);
+        }
+
+        if let Some((count, longest)) = self.outbox.resumed(name) {
+            if let Err(e) = notifier.send(&delay_note(name, count, longest)).await {
+                let retry_in = self.outbox.failed(name);
+                warn!(
+                    notifier = name,
+                    error = %e,
+                    retry_in = ?retry_in,
+                    "failed to send delay note"
+                );
+                return;
+            }
+            info!(notifier = name, alerts = count, "delivered delayed alerts");
+        }
+        self.outbox.recovered(name);
+    }
+}
+
+/// Lists the alerts a rate limit held back in one message.
+fn held_note(held: &[Message]) -> Message {
+    let mut lines: Vec<String> = held
+        .iter()
+        .take(HELD_LISTED)
+        .map(|message| {
+            let first_line = message.body.lines().next().unwrap_or_default();
+            format!("{}: {}", message.title, shorten(first_line))
+        })
+        .collect();
+    if held.len() > HELD_LISTED {
+        lines.push(format!("and {} more", held.len() - HELD_LISTED));
+    }
+
+    Message {
+        kind: AlertKind::Summary,
+        title: format!("{} alerts held back by the rate limit", held.len()),
+        body: lines.join("\n"),
+        priority: held.iter().map(|m| m.priority).max().unwrap_or(3),
+        tags: vec!["mute".to_string()],
+        click: None,
+        actions: Vec::new(),
+    }
+}
+
+/// Tells a notifier's audience that what they just got is late.
+fn delay_note(notifier: &str, count: usize, longest: Duration) -> Message {
+    let minutes = longest.as_secs().div_ceil(60).max(1);
+    let longest = match minutes {
+        1 => "a minute".to_string(),
+        m if m < 120 => format!("{m} minutes"),
+        m => format!("{} hours", m.div_ceil(60)),
+    };
+    let alerts = if count == 1 {
+        "1 alert was".to_string()
+    } else {
+        format!("{count} alerts were")
+    };
+
+    Message {
+        kind: AlertKind::Summary,
+        title: "homed".to_string(),
+        body: format!("{alerts} delayed by up to {longest} while {notifier} was unreachable"),
+        priority: 3,
+        tags: vec!["hourglass".to_string()],
+        click: None,
+        actions: Vec::new(),
+    }
+}
+
+/// Sends a single alert right away, bypassing batching.
+pub async fn send_immediate_alert(dispatcher: &mut Dispatcher, alert: &Alert) {
+    dispatcher.send(alert).await;
+}
+
+/// Sends the summary of everything since the last batch, then the alerts
+/// held back meanwhile.
+pub async fn send_batch_alert(dispatcher: &mut Dispatcher, batch: &Batch) {
+    if !batch.summary.is_empty() {
+        dispatcher
+            .send(&Alert::summary(batch.summary.to_string()))
+            .await;
+    }
+    for alert in &batch.held {
+        dispatcher.send(alert).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiConfig, RouteConfig};
+    use crate::notify::{NotifyError, SendFuture};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn alerts_config() -> AlertsConfig {
+        AlertsConfig {
+            enabled: true,
+            url: "https://ntfy.example.com".to_string(),
+            topic: "homed".to_string(),
+            token: "ntfy-token".to_string(),
+            templates: HashMap::new(),
+            api: Some(ApiConfig {
+                listen: "127.0.0.1:8750".parse().unwrap(),
+                token: "api-token".to_string(),
+                public_url: Some("https://homed.example.com/".to_string()),
+            }),
+            notifiers: Vec::new(),
+            routes: Vec::new(),
+            queue_file: None,
+            urgent_priority: 5,
+            quiet_hours: None,
+            digest_at: None,
+            max_per_hour: None,
+        }
+    }
+
+    #[test]
+    fn test_quarantine_alert_has_restore_action() {
+        let config = alerts_config();
+        let alert = Alert::quarantine(
+            Pipeline::Media,
+            Path::new("/mnt/media/Movies/Film/Codec.exe"),
+            "20260101-120000-abcd1234",
+            "executable extension blocked: .exe",
+        );
+
+        let message = render(&config, &alert);
+        let url = &message.actions[0].url;
+        let signed = url
+            .strip_prefix(
+                "https://homed.example.com/quarantine/20260101-120000-abcd1234/restore?sig=",
+            )
+            .unwrap();
+        assert_eq!(signed.len(), 64);
+        assert!(
+            !serde_json::to_string(&message)
+                .unwrap()
+                .contains("api-token")
+        );
+        assert_eq!(
+            message,
+            Message {
+                kind: AlertKind::Quarantine,
+                title: "Quarantined Codec.exe".to_string(),
+                body: "executable extension blocked: .exe\n/mnt/media/Movies/Film/Codec.exe"
+                    .to_string(),
+                priority: 4,
+                tags: vec!["rotating_light".to_string()],
+                click: None,
+                actions: vec![AlertAction {
+                    action: ActionKind::Http,
+                    label: "Restore from quarantine".to_string(),
+                    url: url.clone(),
+                    method: "POST".to_string(),
+                    headers: Default::default(),
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn test_template_overrides_single_fields() {
+        let mut config = alerts_config();
+        config.api = None;
+        config.templates.insert(
+            AlertKind::Rescan,
+            AlertTemplate {
+                priority: Some(2),
+                click: Some("https://grafana.example.com".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let message = render(&config, &Alert::rescan(Pipeline::Photos, "queue overflow"));
+        assert_eq!(message.title, "Photos watcher rescan");
+        assert_eq!(
+            message.body,
+            "Watcher missed filesystem events (queue overflow), rescanning"
+        );
+        assert_eq!(message.priority, 2);
+        assert_eq!(
+            message.click.as_deref(),
+            Some("https://grafana.example.com")
+        );
+        assert!(message.actions.is_empty());
+    }
+
+    #[test]
+    fn test_summary_per_pipeline() {
+        let media = Pipeline::Media;
+        let mut summary = Summary::default();
+        for event in [
+            FileEvent::Organized {
+                pipeline: Pipeline::Photos,
+                old_path: PathBuf::from("/in/a.jpg"),
+                new_path: PathBuf::from("/out/a.jpg"),
+            },
+            FileEvent::Scanned {
+                pipeline: media,
+                path: PathBuf::from("/m/Film.mkv"),
+                clean: true,
+            },
+            FileEvent::Cleaned {
+                pipeline: media,
+                path: PathBuf::from("/m/Film.nfo"),
+                reason: "junk extension: .nfo".to_string(),
+            },
+            FileEvent::Cleaned {
+                pipeline: media,
+                path: PathBuf::from("/m/Film.txt"),
+                reason: "junk extension: .txt".to_string(),
+            },
+        ] {
+            summary.record(&event);
+        }
+        for name in ["Codec.exe", "Setup.exe"] {
+            summary.record(&FileEvent::Quarantined {
+                pipeline: media,
+                path: PathBuf::from("/m").join(name),
+                id: "x".to_string(),
+                reason: "executable extension blocked: .exe".to_string(),
+                malware: false,
+            });
+        }
+
+        assert_eq!(
+            summary.to_string(),
+            "Photos: 1 organized\n\
+             Media: 1 passed, 2 quarantined (executable extension blocked: .exe), 2 junk files removed"
+        );
+        assert!(Summary::default().is_empty());
+    }
+
+    #[test]
+    fn test_security_events_alert_immediately() {
+        let path = PathBuf::from("/m/Film.mkv");
+        let quarantined = |malware| FileEvent::Quarantined {
+            pipeline: Pipeline::Media,
+            path: path.clone(),
+            id: "x".to_string(),
+            reason: "malware detected: Eicar".to_string(),
+            malware,
+        };
+
+        let alert = Alert::for_event(&quarantined(true)).unwrap();
+        assert_eq!(alert.kind, AlertKind::Malware);
+        let message = render(&alerts_config(), &alert);
+        assert_eq!(message.title, "Malware quarantined: Film.mkv");
+        assert_eq!(message.priority, 5);
+        assert!(message.actions.is_empty());
+
+        let alert = Alert::for_event(&quarantined(false)).unwrap();
+        assert_eq!(alert.kind, AlertKind::Quarantine);
+
+        let cleaned = FileEvent::Cleaned {
+            pipeline: Pipeline::Media,
+            path,
+            reason: "junk extension: .nfo".to_string(),
+        };
+        assert!(Alert::for_event(&cleaned).is_none());
+    }
+
+    #[test]
+    fn test_placeholders_filled_once() {
+        let vars = vec![
+            ("name", "{api_token}.mkv".to_string()),
+            ("api_token", "secret".to_string()),
+        ];
+        assert_eq!(
+            fill_placeholders("{name} {unknown} {", &vars),
+            "{api_token}.mkv {unknown} {"
+        );
+    }
+
+    #[test]
+    fn test_routes_pick_notifiers() {
+        let mut config: AlertsConfig = toml::from_str(
+            r#"
+            enabled = true
+            url = "https://ntfy.example.com"
+            topic = "homed"
+
+            [[notifiers]]
+            name = "family"
+            type = "matrix"
+            homeserver = "https://matrix.example.com"
+            room_id = "!family:example.com"
+            access_token = "token"
+
+            [[notifiers]]
+            name = "mail"
+            type = "smtp"
+            from = "homed@example.com"
+            to = ["admin@example.com"]
+            "#,
+        )
+        .unwrap();
+        let targets = |config: &AlertsConfig, alert: &Alert| -> Vec<String> {
+            let dispatcher = Dispatcher::new(config.clone(), reqwest::Client::new());
+            let message = render(config, alert);
+            dispatcher.targets(&message)
+        };
+        let summary = Alert::summary("Photos: 1 organized".to_string());
+        let malware = Alert::malware(Pipeline::Media, Path::new("/m/a.exe"), None, "Eicar");
+
+        assert_eq!(targets(&config, &summary), ["ntfy", "family", "mail"]);
+
+        config.routes = vec![
+            RouteConfig {
+                kinds: Vec::new(),
+                min_priority: 1,
+                notifiers: vec!["ntfy".to_string()],
+            },
+            RouteConfig {
+                kinds: vec![AlertKind::Summary],
+                min_priority: 1,
+                notifiers: vec!["family".to_string()],
+            },
+            RouteConfig {
+                kinds: Vec::new(),
+                min_priority: 5,
+                notifiers: vec!["mail".to_string()],
+            },
+        ];
+        assert_eq!(targets(&config, &summary), ["ntfy", "family"]);
+        assert_eq!(targets(&config, &malware), ["ntfy", "mail"]);
+
+        // Templates decide the priority routes see
+        config.templates.insert(
+            AlertKind::Malware,
+            AlertTemplate {
+                priority: Some(4),
+                ..Default::default()
+            },
+        );
+        assert_eq!(targets(&config, &malware), ["ntfy"]);
+    }
+
+    /// Fails while `down` is set and records what it delivered.
+    #[derive(Default)]
+    struct Flaky {
+        down: AtomicBool,
+        sent: Mutex<Vec<Message>>,
+    }
+
+    impl Notifier for Arc<Flaky> {
+        fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a> {
+            Box::pin(async move {
+                if self.down.load(Ordering::SeqCst) {
+                    return Err(NotifyError::Timeout(Duration::from_secs(1)));
+                }
+                self.sent.lock().unwrap().push(message.clone());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_alerts_retried_with_delay_note() {
+        let flaky = Arc::new(Flaky::default());
+        let mut config = alerts_config();
+        config.url = String::new();
+        let mut dispatcher = Dispatcher::with_notifiers(
+            config,
+            vec![("phone".to_string(), Box::new(flaky.clone()))],
+        );
+
+        flaky.down.store(true, Ordering::SeqCst);
+        let summary = Alert::summary("Photos: 3 organized".to_string());
+        dispatcher.send(&summary).await;
+        dispatcher.send(&summary).await;
+        dispatcher
+            .send(&Alert::rescan(Pipeline::Media, "queue overflow"))
+            .await;
+        let retry_at = dispatcher.next_retry().unwrap();
+
+        // Still backing off: nothing is due yet
+        flaky.down.store(false, Ordering::SeqCst);
+        dispatcher.retry().await;
+        assert!(flaky.sent.lock().unwrap().is_empty());
+
+        tokio::time::sleep_until(retry_at).await;
+        dispatcher.retry().await;
+        let sent = flaky.sent.lock().unwrap().clone();
+        let bodies: Vec<&str> = sent.iter().map(|m| m.body.as_str()).collect();
+        assert_eq!(
+            bodies,
+            [
+                "Photos: 3 organized",
+                "Watcher missed filesystem events (queue overflow), rescanning",
+                "2 alerts were delayed by up to a minute while phone was unreachable",
+            ]
+        );
+        assert_eq!(dispatcher.next_retry(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limit_holds_back_all_but_urgent() {
+        let flaky = Arc::new(Flaky::default());
+        let mut config = alerts_config();
+        config.url = String::new();
+        config.max_per_hour = Some(1);
+        let mut dispatcher = Dispatcher::with_notifiers(
+            config,
+            vec![("phone".to_string(), Box::new(flaky.clone()))],
+        );
+
+        let path = Path::new("/m/Codec.exe");
+        for id in ["a", "b", "c"] {
+            dispatcher
+                .send(&Alert::quarantine(Pipeline::Media, path, id, "blocked"))
+                .await;
+        }
+        dispatcher
+            .send(&Alert::malware(Pipeline::Media, path, None, "Eicar"))
+            .await;
+        let titles = |flaky: &Flaky| -> Vec<String> {
+            let sent = flaky.sent.lock().unwrap();
+            sent.iter().map(|m| m.title.clone()).collect()
+        };
+        assert_eq!(
+            titles(&flaky),
+            [
+                "Quarantined Codec.exe",
+                "Malware NOT quarantined: Codec.exe"
+            ]
+        );
+
+        let free_at = dispatcher.next_retry().unwrap();
+        assert_eq!(free_at, Instant::now() + RATE_WINDOW);
+        tokio::time::sleep_until(free_at).await;
+        dispatcher.retry().await;
 
-    if let Err(e) = send_alert(client, config, &message).await {
-        warn!(error = %e, "failed to send ntfy alert");
+        let sent = flaky.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[2].title, "2 alerts held back by the rate limit");
+        assert_eq!(
+            sent[2].body,
+            "Quarantined Codec.exe: blocked\nQuarantined Codec.exe: blocked"
+        );
+        assert_eq!(dispatcher.next_retry(), None);
     }
 }
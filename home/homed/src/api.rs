@@ -0,0 +1,332 @@
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::config::ApiConfig;
+use crate::quarantine::{QuarantineError, QuarantineStore};
+
+/// Requests must arrive within this long; the API only serves buttons.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HEADER_LINES: usize = 64;
+const MAX_LINE_LEN: u64 = 8192;
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn new(status: &'static str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: body.into(),
+        }
+    }
+}
+
+/// Serves the endpoints behind alert action buttons until shutdown:
+///
+/// - `POST /quarantine/<id>/restore` restores an item to its original path
+///
+/// Every request needs `Authorization: Bearer <alerts.api.token>`, except
+/// restores carrying the signature from `restore_url`.
+pub async fn run_api(
+    config: ApiConfig,
+    store: QuarantineStore,
+    mut shutdown: broadcast::Receiver<()>,
+) -> Result<(), ApiError> {
+    let listener = TcpListener::bind(config.listen).await?;
+    info!(listen = %config.listen, "API listening");
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (conn, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!(error = %e, "failed to accept API connection");
+                        continue;
+                    }
+                };
+                let token = config.token.clone();
+                let store = store.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(conn, &token, store).await {
+                        warn!(peer = %peer, error = %e, "API request failed");
+                    }
+                });
+            }
+            _ = shutdown.recv() => {
+                info!("API shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn handle_connection(
+    conn: TcpStream,
+    token: &str,
+    store: QuarantineStore,
+) -> Result<(), ApiError> {
+    let mut reader = BufReader::new(conn);
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader)).await {
+        Ok(Ok(Some((method, target, auth)))) => {
+            route(&method, &target, auth.as_deref(), token, store).await
+        }
+        Ok(Ok(None)) => Response::new("400 Bad Request", "malformed request"),
+        Ok(Err(e)) => return Err(e.into()),
+        Err(_) => Response::new("408 Request Timeout", "request timed out"),
+    };
+
+    let mut conn = reader.into_inner();
+    conn.write_all(
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status,
+            response.body.len(),
+            response.body
+        )
+        .as_bytes(),
+    )
+    .await?;
+    conn.shutdown().await?;
+
+    Ok(())
+}
+
+/// Reads the request line and headers, returning the method, target and
+/// Authorization header. None for anything that isn't plausible HTTP.
+async fn read_request(
+    reader: &mut BufReader<TcpStream>,
+) -> std::io::Result<Option<(String, String, Option<String>)>> {
+    let Some(request_line) = read_line(reader).await? else {
+        return Ok(None);
+    };
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let mut auth = None;
+    for _ in 0..MAX_HEADER_LINES {
+        let Some(line) = read_line(reader).await? else {
+            return Ok(None);
+        };
+        if line.is_empty() {
+            return Ok(Some((method, target, auth)));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization") {
+                auth = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+async fn read_line(reader: &mut BufReader<TcpStream>) -> std::io::Result<Option<String>> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_LINE_LEN)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.last() != Some(&b'\n') {
+        return Ok(None);
+    }
+    Ok(String::from_utf8(line)
+        .ok()
+        .map(|line| line.trim_end().to_string()))
+}
+
+async fn route(
+    method: &str,
+    target: &str,
+    auth: Option<&str>,
+    token: &str,
+    store: QuarantineStore,
+) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let authorized = auth
+        .and_then(|auth| auth.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()));
+    let signed = match segments.as_slice() {
+        ["quarantine", id, "restore"] => query
+            .split('&')
+            .filter_map(|param| param.strip_prefix("sig="))
+            .any(|sig| constant_time_eq(sig.as_bytes(), restore_signature(token, id).as_bytes())),
+        _ => false,
+    };
+    if !authorized && !signed {
+        return Response::new("401 Unauthorized", "missing or wrong token");
+    }
+
+    match (method, segments.as_slice()) {
+        ("POST", ["quarantine", id, "restore"]) => restore(store, id).await,
+        (_, ["quarantine", _, "restore"]) => Response::new("405 Method Not Allowed", "use POST"),
+        _ => Response::new("404 Not Found", "no such endpoint"),
+    }
+}
+
+async fn restore(store: QuarantineStore, id: &str) -> Response {
+    // Ids name files in the quarantine directory
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+        return Response::new("400 Bad Request", "invalid id");
+    }
+
+    let owned_id = id.to_string();
+    let result = tokio::task::spawn_blocking(move || store.restore(&owned_id, None))
+        .await
+        .expect("restore task panicked");
+
+    match result {
+        Ok(target) => {
+            info!(id, target = %target.display(), "restored from quarantine via API");
+            Response::new("200 OK", format!("restored to {}", target.display()))
+        }
+        Err(e @ QuarantineError::NotFound(_)) => Response::new("404 Not Found", e.to_string()),
+        Err(e @ QuarantineError::TargetExists(_)) => Response::new("409 Conflict", e.to_string()),
+        Err(e) => {
+            warn!(id, error = %e, "failed to restore from quarantine via API");
+            Response::new("500 Internal Server Error", e.to_string())
+        }
+    }
+}
+
+/// The link behind a "Restore from quarantine" button. It's signed for this
+/// one id, so the API token never leaves the host, and stops working once
+/// the item is restored since ids aren't reused.
+pub fn restore_url(config: &ApiConfig, id: &str) -> String {
+    format!(
+        "{}/quarantine/{id}/restore?sig={}",
+        config.public_url(),
+        restore_signature(&config.token, id)
+    )
+}
+
+/// HMAC-SHA256 of the quarantine id, keyed by the API token.
+fn restore_signature(token: &str, id: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(b"restore:");
+    mac.update(id.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn request(addr: std::net::SocketAddr, request: &str) -> String {
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+        conn.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        conn.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_restore_endpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = QuarantineStore::open(&dir.path().join("quarantine")).unwrap();
+        let file = dir.path().join("movie.mkv");
+        std::fs::write(&file, b"video").unwrap();
+        let record = store.quarantine(&file, "test").unwrap();
+
+        // Bind first to learn a free port, then let the API take it over
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = ApiConfig {
+            listen: addr,
+            token: "secret".to_string(),
+            public_url: None,
+        };
+        let api_config = config.clone();
+        let store_again = store.clone();
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let server = tokio::spawn(run_api(config, store, shutdown_rx));
+        for _ in 0..50 {
+            if TcpStream::connect(addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let restore = format!("POST /quarantine/{}/restore HTTP/1.1\r\n", record.id);
+        let response = request(
+            addr,
+            &format!("{restore}Authorization: Bearer wrong\r\n\r\n"),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401 "), "{response}");
+        assert!(!file.exists());
+
+        let response = request(
+            addr,
+            &format!("{restore}Authorization: Bearer secret\r\n\r\n"),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+        assert_eq!(std::fs::read(&file).unwrap(), b"video");
+
+        let response = request(
+            addr,
+            &format!("{restore}Authorization: Bearer secret\r\n\r\n"),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 404 "), "{response}");
+
+        // Signed links work without the token, only for their own id
+        let record = store_again.quarantine(&file, "test").unwrap();
+        let other = {
+            let file = dir.path().join("other.mkv");
+            std::fs::write(&file, b"other").unwrap();
+            store_again.quarantine(&file, "test").unwrap()
+        };
+        let url = restore_url(&api_config, &record.id);
+        let signed = url.strip_prefix(&format!("http://{addr}")).unwrap();
+        let forged = signed.replace(&record.id, &other.id);
+        let response = request(addr, &format!("POST {forged} HTTP/1.1\r\n\r\n")).await;
+        assert!(response.starts_with("HTTP/1.1 401 "), "{response}");
+
+        let response = request(addr, &format!("POST {signed} HTTP/1.1\r\n\r\n")).await;
+        assert!(response.starts_with("HTTP/1.1 200 "), "{response}");
+        assert_eq!(std::fs::read(&file).unwrap(), b"video");
+
+        let response = request(addr, &format!("POST {signed} HTTP/1.1\r\n\r\n")).await;
+        assert!(response.starts_with("HTTP/1.1 404 "), "{response}");
+
+        let response = request(
+            addr,
+            "POST /quarantine/..%2F..%2Fetc/restore HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400 "), "{response}");
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
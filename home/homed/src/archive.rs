@@ -0,0 +1,680 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use sevenz_rust2::{Archive, ArchiveReader, EncoderMethod, Password};
+use thiserror::Error;
+use tracing::debug;
+
+use crate::checks::{self, SNIFF_LEN, ScanRejection};
+use crate::config::ArchiveConfig;
+
+const RAR4_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x00";
+const RAR5_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x01\x00";
+
+/// Upper bound for a single RAR header, anything larger is garbage.
+const MAX_RAR_HEADER: u64 = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("zip: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error("7z: {0}")]
+    SevenZip(#[from] sevenz_rust2::Error),
+
+    #[error("malformed RAR header at offset {0}")]
+    MalformedRar(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Rar,
+    SevenZip,
+}
+
+impl ArchiveKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "zip" => Some(Self::Zip),
+            "rar" => Some(Self::Rar),
+            "7z" => Some(Self::SevenZip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Member {
+    pub name: String,
+    pub encrypted: bool,
+    /// Leading bytes of the member when they could be decompressed.
+    pub head: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub members: Vec<Member>,
+    /// The member list itself is encrypted, so nothing can be checked.
+    pub encrypted_headers: bool,
+}
+
+/// Lists an archive and checks every member's extension and, where the
+/// content could be sniffed, its magic bytes. Executables are rejected even
+/// when `block_executables` is off, nobody ships those inside media
+/// archives. Blocking.
+pub fn inspect(
+    path: &Path,
+    kind: ArchiveKind,
+    size: u64,
+    config: &ArchiveConfig,
+    allowed: &[String],
+    junk: &[String],
+) -> Result<(), ScanRejection> {
+    let sniff = size <= config.max_inspect_mb * 1024 * 1024;
+    let listing =
+        list(path, kind, sniff).map_err(|e| ScanRejection::ArchiveUnreadable(e.to_string()))?;
+
+    if listing.encrypted_headers || listing.members.iter().any(|m| m.encrypted) {
+        return Err(ScanRejection::ArchivePasswordProtected);
+    }
+
+    for member in &listing.members {
+        check_member(member, allowed, junk).map_err(|reason| {
+            ScanRejection::ArchiveMemberRejected {
+                member: member.name.clone(),
+                reason: Box::new(reason),
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+fn check_member(member: &Member, allowed: &[String], junk: &[String]) -> Result<(), ScanRejection> {
+    let path = Path::new(&member.name);
+    checks::check_executable_extension(path)?;
+
+    // Junk is fine inside an archive, the extracted copy gets cleaned up
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if !junk.iter().any(|j| j.eq_ignore_ascii_case(ext)) {
+        checks::check_extension(path, allowed)?;
+    }
+
+    if let Some(head) = &member.head {
+        checks::check_content_type(&ext.to_ascii_lowercase(), head)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the member list without extracting anything. With `sniff` set,
+/// the first bytes of each member are decompressed too (zip and 7z only,
+/// there is no pure Rust RAR decompressor). Blocking.
+pub fn list(path: &Path, kind: ArchiveKind, sniff: bool) -> Result<Listing, ArchiveError> {
+    match kind {
+        ArchiveKind::Zip => list_zip(path, sniff),
+        ArchiveKind::Rar => list_rar(&mut File::open(path)?),
+        ArchiveKind::SevenZip => list_7z(path, sniff),
+    }
+}
+
+fn list_zip(path: &Path, sniff: bool) -> Result<Listing, ArchiveError> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let mut listing = Listing::default();
+
+    for i in 0..archive.len() {
+        let (name, encrypted, is_dir) = {
+            let entry = archive.by_index_raw(i)?;
+            (entry.name().to_string(), entry.encrypted(), entry.is_dir())
+        };
+        if is_dir {
+            continue;
+        }
+
+        let head = if sniff && !encrypted {
+            // Unsupported compression methods just skip the magic check
+            archive
+                .by_index(i)
+                .ok()
+                .and_then(|entry| read_head(entry).ok())
+        } else {
+            None
+        };
+
+        listing.members.push(Member {
+            name,
+            encrypted,
+            head,
+        });
+    }
+
+    Ok(listing)
+}
+
+fn list_7z(path: &Path, sniff: bool) -> Result<Listing, ArchiveError> {
+    let archive = match Archive::open(path) {
+        Ok(archive) => archive,
+        Err(sevenz_rust2::Error::PasswordRequired | sevenz_rust2::Error::MaybeBadPassword(_)) => {
+            return Ok(Listing {
+                members: Vec::new(),
+                encrypted_headers: true,
+            });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let encrypted_blocks: Vec<bool> = archive
+        .blocks
+        .iter()
+        .map(|block| {
+            block
+                .coders
+                .iter()
+                .any(|c| c.encoder_method_id() == EncoderMethod::ID_AES256_SHA256)
+        })
+        .collect();
+
+    let mut listing = Listing::default();
+    for (i, entry) in archive.files.iter().enumerate() {
+        if entry.is_directory {
+            continue;
+        }
+        let encrypted = archive.stream_map.file_block_index[i].is_some_and(|b| encrypted_blocks[b]);
+        listing.members.push(Member {
+            name: entry.name.clone(),
+            encrypted,
+            head: None,
+        });
+    }
+
+    if sniff && !encrypted_blocks.contains(&true) {
+        let mut heads = HashMap::new();
+        let mut reader = ArchiveReader::from_archive(archive, File::open(path)?, Password::empty());
+        let result = reader.for_each_entries(|entry, data| {
+            if !entry.is_directory {
+                heads.insert(entry.name.clone(), read_head(data)?);
+            }
+            Ok(true)
+        });
+
+        match result {
+            Ok(()) => {
+                for member in &mut listing.members {
+                    member.head = heads.remove(&member.name);
+                }
+            }
+            Err(e) => debug!(path = %path.display(), error = %e, "could not decompress 7z members"),
+        }
+    }
+
+    Ok(listing)
+}
+
+fn read_head(reader: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// Walks the block headers of a RAR 4.x or 5.x archive.
+fn list_rar<R: Read + Seek>(reader: &mut R) -> Result<Listing, ArchiveError> {
+    let mut signature = [0u8; 8];
+    reader.read_exact(&mut signature[..7])?;
+
+    if &signature[..7] == RAR4_SIGNATURE {
+        return list_rar4(reader);
+    }
+
+    reader.read_exact(&mut signature[7..])?;
+    if signature == RAR5_SIGNATURE {
+        list_rar5(reader)
+    } else {
+        Err(ArchiveError::MalformedRar(0))
+    }
+}
+
+fn list_rar4<R: Read + Seek>(reader: &mut R) -> Result<Listing, ArchiveError> {
+    const MAIN_HEAD: u8 = 0x73;
+    const FILE_HEAD: u8 = 0x74;
+    const END_HEAD: u8 = 0x7b;
+    const MHD_PASSWORD: u16 = 0x0080;
+    const LHD_PASSWORD: u16 = 0x0004;
+    const LHD_DIRECTORY: u16 = 0x00e0;
+    const LHD_LARGE: u16 = 0x0100;
+    const LONG_BLOCK: u16 = 0x8000;
+
+    let mut listing = Listing::default();
+
+    loop {
+        let offset = reader.stream_position()?;
+        let mut fixed = [0u8; 7];
+        match reader.read_exact(&mut fixed) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        let kind = fixed[2];
+        let flags = u16::from_le_bytes([fixed[3], fixed[4]]);
+        let head_size = u16::from_le_bytes([fixed[5], fixed[6]]) as u64;
+        if head_size < 7 {
+            return Err(ArchiveError::MalformedRar(offset));
+        }
+
+        let mut header = vec![0u8; head_size as usize - 7];
+        reader.read_exact(&mut header)?;
+
+        let mut data_size = if flags & LONG_BLOCK != 0 {
+            le_u32(&header, 0).ok_or(ArchiveError::MalformedRar(offset))? as u64
+        } else {
+            0
+        };
+
+        match kind {
+            MAIN_HEAD if flags & MHD_PASSWORD != 0 => {
+                listing.encrypted_headers = true;
+                break;
+            }
+            FILE_HEAD => {
+                let name_size = le_u16(&header, 19).ok_or(ArchiveError::MalformedRar(offset))?;
+                let mut name_start = 25;
+                if flags & LHD_LARGE != 0 {
+                    let high = le_u32(&header, 25).ok_or(ArchiveError::MalformedRar(offset))?;
+                    data_size += (high as u64) << 32;
+                    name_start += 8;
+                }
+                let name = header
+                    .get(name_start..name_start + name_size as usize)
+                    .ok_or(ArchiveError::MalformedRar(offset))?;
+                // Unicode names follow the ASCII one after a NUL
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+
+                if flags & LHD_DIRECTORY != LHD_DIRECTORY {
+                    listing.members.push(Member {
+                        name: String::from_utf8_lossy(name).into_owned(),
+                        encrypted: flags & LHD_PASSWORD != 0,
+                        head: None,
+                    });
+                }
+            }
+            END_HEAD => break,
+            _ => {}
+        }
+
+        // Sizes come from the archive, so a crafted one mustn't wrap the
+        // next header back onto this one
+        let next = offset
+            .checked_add(head_size)
+            .and_then(|end| end.checked_add(data_size))
+            .filter(|&next| next > offset)
+            .ok_or(ArchiveError::MalformedRar(offset))?;
+        reader.seek(SeekFrom::Start(next))?;
+    }
+
+    Ok(listing)
+}
+
+fn list_rar5<R: Read + Seek>(reader: &mut R) -> Result<Listing, ArchiveError> {
+    const FILE_HEAD: u64 = 2;
+    const ENCRYPTION_HEAD: u64 = 4;
+    const END_HEAD: u64 = 5;
+    const HFL_EXTRA: u64 = 0x01;
+    const HFL_DATA: u64 = 0x02;
+    const FHFL_DIRECTORY: u64 = 0x01;
+    const FHFL_UTIME: u64 = 0x02;
+    const FHFL_CRC32: u64 = 0x04;
+    const EXTRA_CRYPT: u64 = 0x01;
+
+    let mut listing = Listing::default();
+
+    loop {
+        let offset = reader.stream_position()?;
+        let mut crc = [0u8; 4];
+        match reader.read_exact(&mut crc) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        let head_size = read_vint(reader)?.ok_or(ArchiveError::MalformedRar(offset))?;
+        if head_size == 0 || head_size > MAX_RAR_HEADER {
+            return Err(ArchiveError::MalformedRar(offset));
+        }
+        let header_start = reader.stream_position()?;
+        let mut header = vec![0u8; head_size as usize];
+        reader.read_exact(&mut header)?;
+
+        let malformed = || ArchiveError::MalformedRar(offset);
+        let mut cursor = &header[..];
+        let kind = read_vint(&mut cursor)?.ok_or_else(malformed)?;
+        let flags = read_vint(&mut cursor)?.ok_or_else(malformed)?;
+        let extra_size = if flags & HFL_EXTRA != 0 {
+            read_vint(&mut cursor)?.ok_or_else(malformed)?
+        } else {
+            0
+        };
+        let data_size = if flags & HFL_DATA != 0 {
+            read_vint(&mut cursor)?.ok_or_else(malformed)?
+        } else {
+            0
+        };
+
+        match kind {
+            ENCRYPTION_HEAD => {
+                listing.encrypted_headers = true;
+                break;
+            }
+            FILE_HEAD => {
+                let file_flags = read_vint(&mut cursor)?.ok_or_else(malformed)?;
+                let _unpacked_size = read_vint(&mut cursor)?.ok_or_else(malformed)?;
+                let _attributes = read_vint(&mut cursor)?.ok_or_else(malformed)?;
+                let mut skip = 0;
+                if file_flags & FHFL_UTIME != 0 {
+                    skip += 4;
+                }
+                if file_flags & FHFL_CRC32 != 0 {
+                    skip += 4;
+                }
+                cursor = cursor.get(skip..).ok_or_else(malformed)?;
+                let _compression = read_vint(&mut cursor)?.ok_or_else(malformed)?;
+                let _host_os = read_vint(&mut cursor)?.ok_or_else(malformed)?;
+                let name_len = read_vint(&mut cursor)?.ok_or_else(malformed)? as usize;
+                let name = cursor.get(..name_len).ok_or_else(malformed)?;
+
+                let extra = header
+                    .len()
+                    .checked_sub(extra_size as usize)
+                    .and_then(|start| header.get(start..))
+                    .ok_or_else(malformed)?;
+
+                if file_flags & FHFL_DIRECTORY == 0 {
+                    listing.members.push(Member {
+                        name: String::from_utf8_lossy(name).into_owned(),
+                        encrypted: has_extra_record(extra, EXTRA_CRYPT),
+                        head: None,
+                    });
+                }
+            }
+            END_HEAD => break,
+            _ => {}
+        }
+
+        let next = header_start
+            .checked_add(head_size)
+            .and_then(|end| end.checked_add(data_size))
+            .filter(|&next| next > header_start)
+            .ok_or_else(malformed)?;
+        reader.seek(SeekFrom::Start(next))?;
+    }
+
+    Ok(listing)
+}
+
+/// RAR5 extra area: a sequence of `size, type, data` records.
+fn has_extra_record(mut extra: &[u8], wanted: u64) -> bool {
+    while !extra.is_empty() {
+        let Ok(Some(size)) = read_vint(&mut extra) else {
+            return false;
+        };
+        let Some(record) = extra.get(..size as usize) else {
+            return false;
+        };
+        if let Ok(Some(kind)) = read_vint(&mut &record[..]) {
+            if kind == wanted {
+                return true;
+            }
+        }
+        extra = &extra[size as usize..];
+    }
+    false
+}
+
+/// RAR5 variable-length integer: 7 bits per byte, high bit continues.
+/// `None` when the value doesn't fit in 64 bits.
+fn read_vint(reader: &mut impl Read) -> std::io::Result<Option<u64>> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+fn le_u16(buf: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(buf.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u32(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(at..at + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    fn allowed() -> Vec<String> {
+        vec!["mkv".to_string(), "srt".to_string()]
+    }
+
+    fn junk() -> Vec<String> {
+        vec!["nfo".to_string()]
+    }
+
+    fn config() -> ArchiveConfig {
+        ArchiveConfig { max_inspect_mb: 1 }
+    }
+
+    fn write_zip(path: &Path, members: &[(&str, &[u8])]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+        let options =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in members {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    /// Sets the "encrypted" general purpose flag in the local and central
+    /// headers of a single-member zip.
+    fn mark_encrypted(path: &Path) {
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[6] |= 1;
+        let central = bytes.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+        bytes[central + 8] |= 1;
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    fn inspect_zip(path: &Path) -> Result<(), ScanRejection> {
+        let size = std::fs::metadata(path).unwrap().len();
+        inspect(path, ArchiveKind::Zip, size, &config(), &allowed(), &junk())
+    }
+
+    #[test]
+    fn test_clean_zip_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("release.zip");
+        write_zip(
+            &path,
+            &[
+                ("Movie/movie.mkv", b"\x1a\x45\xdf\xa3\x01\x00\x00\x00webm"),
+                ("Movie/movie.nfo", b"release notes"),
+            ],
+        );
+
+        assert!(inspect_zip(&path).is_ok());
+    }
+
+    #[test]
+    fn test_zip_with_executable_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("release.zip");
+        write_zip(&path, &[("movie.mkv", b"data"), ("codec.exe", b"MZ")]);
+
+        let result = inspect_zip(&path);
+        assert!(matches!(
+            result,
+            Err(ScanRejection::ArchiveMemberRejected { member, reason })
+                if member == "codec.exe"
+                    && matches!(*reason, ScanRejection::ExecutableExtension(_))
+        ));
+    }
+
+    #[test]
+    fn test_zip_with_disguised_member_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("release.zip");
+        write_zip(&path, &[("movie.mkv", b"MZ\x90\x00fake_pe_content")]);
+
+        let result = inspect_zip(&path);
+        assert!(matches!(
+            result,
+            Err(ScanRejection::ArchiveMemberRejected { reason, .. })
+                if matches!(*reason, ScanRejection::TypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_password_protected_zip_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("release.zip");
+        write_zip(&path, &[("movie.mkv", b"data")]);
+        mark_encrypted(&path);
+
+        let result = inspect_zip(&path);
+        assert!(matches!(
+            result,
+            Err(ScanRejection::ArchivePasswordProtected)
+        ));
+    }
+
+    fn rar4_file_header(name: &str, flags: u16) -> Vec<u8> {
+        let mut header = vec![0, 0, 0x74];
+        header.extend_from_slice(&(flags | 0x8000).to_le_bytes());
+        header.extend_from_slice(&((32 + name.len()) as u16).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // packed size
+        header.extend_from_slice(&[0; 4 + 1 + 4 + 4 + 1 + 1]);
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(name.as_bytes());
+        header
+    }
+
+    #[test]
+    fn test_rar4_listing() {
+        let mut archive = RAR4_SIGNATURE.to_vec();
+        archive.extend_from_slice(&[0, 0, 0x73, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0]);
+        archive.extend(rar4_file_header("movie.mkv", 0));
+        archive.extend(rar4_file_header("setup.exe", 0x0004));
+        archive.extend_from_slice(&[0, 0, 0x7b, 0, 0, 7, 0]);
+
+        let listing = list_rar(&mut Cursor::new(archive)).unwrap();
+        let names: Vec<_> = listing.members.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["movie.mkv", "setup.exe"]);
+        assert!(!listing.members[0].encrypted);
+        assert!(listing.members[1].encrypted);
+    }
+
+    #[test]
+    fn test_rar4_encrypted_headers() {
+        let mut archive = RAR4_SIGNATURE.to_vec();
+        archive.extend_from_slice(&[0, 0, 0x73, 0x80, 0, 13, 0, 0, 0, 0, 0, 0, 0]);
+
+        let listing = list_rar(&mut Cursor::new(archive)).unwrap();
+        assert!(listing.encrypted_headers);
+    }
+
+    #[test]
+    fn test_rar4_wrapping_data_size_rejected() {
+        let mut archive = RAR4_SIGNATURE.to_vec();
+        archive.extend_from_slice(&[0, 0, 0x73, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0]);
+        let offset = archive.len() as u64;
+
+        // LHD_LARGE with a high size that wraps back to this header
+        let mut header = rar4_file_header("movie.mkv", 0x0100);
+        let head_size = header.len() as u64 + 8;
+        header[5..7].copy_from_slice(&(head_size as u16).to_le_bytes());
+        header[7..11].copy_from_slice(&((1u64 << 32) - head_size).to_le_bytes()[..4]);
+        let name = header.split_off(32);
+        header.extend_from_slice(&0xffff_ffffu32.to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend(name);
+        archive.extend(header);
+
+        let result = list_rar(&mut Cursor::new(archive));
+        assert!(
+            matches!(result, Err(ArchiveError::MalformedRar(at)) if at == offset),
+            "{result:?}"
+        );
+    }
+
+    fn rar5_block(body: &[u8]) -> Vec<u8> {
+        let mut block = vec![0, 0, 0, 0, body.len() as u8];
+        block.extend_from_slice(body);
+        block
+    }
+
+    #[test]
+    fn test_rar5_listing() {
+        let mut archive = RAR5_SIGNATURE.to_vec();
+        archive.extend(rar5_block(&[1, 0, 0, 0])); // main header
+
+        // type, flags (extra area), extra size, file flags, size, attrs,
+        // compression, host os, name length, name, extra: size 2, crypt
+        let mut file = vec![2, 0x01, 2, 0, 0, 0, 0, 0, 9];
+        file.extend_from_slice(b"movie.mkv");
+        file.extend_from_slice(&[1, 1]);
+        archive.extend(rar5_block(&file));
+        archive.extend(rar5_block(&[5, 0, 0]));
+
+        let listing = list_rar(&mut Cursor::new(archive)).unwrap();
+        assert_eq!(listing.members.len(), 1);
+        assert_eq!(listing.members[0].name, "movie.mkv");
+        assert!(listing.members[0].encrypted);
+    }
+
+    #[test]
+    fn test_rar5_huge_data_size_rejected() {
+        let mut archive = RAR5_SIGNATURE.to_vec();
+        archive.extend(rar5_block(&[1, 0, 0, 0])); // main header
+        let offset = archive.len() as u64;
+
+        // type, flags (data area), then a 10-byte vint data size chosen to
+        // wrap back to this header
+        let header_start = offset + 5;
+        let head_size = 12;
+        let mut data_size = offset.wrapping_sub(header_start + head_size);
+        let mut block = vec![3, 0x02];
+        for _ in 0..9 {
+            block.push(data_size as u8 | 0x80);
+            data_size >>= 7;
+        }
+        block.push(data_size as u8);
+        archive.extend(rar5_block(&block));
+
+        let result = list_rar(&mut Cursor::new(archive));
+        assert!(
+            matches!(result, Err(ArchiveError::MalformedRar(at)) if at == offset),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_not_a_rar() {
+        let result = list_rar(&mut Cursor::new(b"PK\x03\x04 not a rar".to_vec()));
+        assert!(matches!(result, Err(ArchiveError::MalformedRar(0))));
+    }
+}
@@ -0,0 +1,322 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::config::{ArrConfig, ArrKind};
+
+#[derive(Debug, Error)]
+pub enum ArrError {
+    #[error("request failed: {0}")]
+    HttpError(#[from] reqwest::Error),
+}
+
+/// A series or a movie. Both carry an id and the folder they live in.
+#[derive(Debug, Deserialize)]
+struct Item {
+    id: u64,
+    path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryRecord {
+    id: u64,
+    event_type: String,
+    #[serde(default)]
+    download_id: Option<String>,
+    #[serde(default)]
+    data: HistoryData,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryData {
+    #[serde(default)]
+    imported_path: Option<PathBuf>,
+}
+
+/// Talks to the v3 API of one Sonarr or Radarr instance.
+#[derive(Clone)]
+pub struct ArrClient {
+    client: reqwest::Client,
+    config: ArrConfig,
+}
+
+impl ArrClient {
+    pub fn new(client: reqwest::Client, config: ArrConfig) -> Self {
+        Self { client, config }
+    }
+
+    /// Rescans the series or movie that owned `path`, marking its grab as
+    /// failed first if configured. Returns false if nothing owns the path.
+    pub async fn file_removed(&self, path: &Path) -> Result<bool, ArrError> {
+        let remote = self.remote_path(path);
+        let (items_endpoint, id_field, rescan) = match self.config.kind {
+            ArrKind::Sonarr => ("series", "seriesId", "RescanSeries"),
+            ArrKind::Radarr => ("movie", "movieId", "RescanMovie"),
+        };
+
+        let items: Vec<Item> = self.get(items_endpoint).await?;
+        let Some(item) = items
+            .into_iter()
+            .filter(|i| remote.starts_with(&i.path))
+            .max_by_key(|i| i.path.as_os_str().len())
+        else {
+            return Ok(false);
+        };
+
+        if self.config.mark_failed {
+            self.mark_failed(item.id, &remote).await?;
+        }
+
+        self.post("command", json!({ "name": rescan, id_field: item.id }))
+            .await?;
+        info!(url = self.config.url, id = item.id, "requested {rescan}");
+
+        Ok(true)
+    }
+
+    /// Finds the grab that produced the import of `remote` and marks it
+    /// failed, which blocklists the release and triggers a new search.
+    async fn mark_failed(&self, item_id: u64, remote: &Path) -> Result<(), ArrError> {
+        let history_endpoint = match self.config.kind {
+            ArrKind::Sonarr => format!("history/series?seriesId={item_id}"),
+            ArrKind::Radarr => format!("history/movie?movieId={item_id}"),
+        };
+        let history: Vec<HistoryRecord> = self.get(&history_endpoint).await?;
+
+        let download_id = history
+            .iter()
+            .filter(|r| r.event_type == "downloadFolderImported")
+            .find(|r| {
+                r.data
+                    .imported_path
+                    .as_deref()
+                    .is_some_and(|p| p.starts_with(remote))
+            })
+            .and_then(|r| r.download_id.as_deref());
+        let grab = download_id.and_then(|id| {
+            history
+                .iter()
+                .find(|r| r.event_type == "grabbed" && r.download_id.as_deref() == Some(id))
+        });
+
+        match grab {
+            Some(grab) => {
+                self.post(&format!("history/failed/{}", grab.id), json!({}))
+                    .await?;
+                info!(
+                    url = self.config.url,
+                    history_id = grab.id,
+                    "marked grab as failed"
+                );
+            }
+            None => warn!(
+                url = self.config.url,
+                path = %remote.display(),
+                "no grab found for removed file, only rescanning"
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Maps a path as seen by homed to the path Sonarr/Radarr knows it by.
+    fn remote_path(&self, path: &Path) -> PathBuf {
+        let (Some(local), Some(remote)) = (&self.config.local_path, &self.config.remote_path)
+        else {
+            return path.to_path_buf();
+        };
+        match path.strip_prefix(local) {
+            Ok(rest) => remote.join(rest),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    fn url(&self, endpoint: &str) -> String {
+        format!(
+            "{}/api/v3/{}",
+            self.config.url.trim_end_matches('/'),
+            endpoint
+        )
+    }
+
+    async fn get<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T, ArrError> {
+        let response = self
+            .client
+            .get(self.url(endpoint))
+            .header("X-Api-Key", &self.config.api_key)
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json().await?)
+    }
+
+    async fn post(&self, endpoint: &str, body: serde_json::Value) -> Result<(), ArrError> {
+        self.client
+            .post(self.url(endpoint))
+            .header("X-Api-Key", &self.config.api_key)
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Tells every configured instance that the scanner removed `path`.
+/// Failures are logged, the scanner's decision stands either way.
+pub async fn notify_removed(clients: &[ArrClient], path: &Path) {
+    for client in clients {
+        if let Err(e) = client.file_removed(path).await {
+            warn!(
+                url = client.config.url,
+                path = %path.display(),
+                error = %e,
+                "failed to notify Sonarr/Radarr"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub_http;
+
+    /// Minimal Sonarr/Radarr stand-in: answers GETs from `routes` by path
+    /// and query and acknowledges every POST.
+    async fn spawn_stub_arr(
+        routes: Vec<(&'static str, serde_json::Value)>,
+    ) -> (String, stub_http::Requests) {
+        stub_http::spawn(move |request| {
+            assert_eq!(request.headers["x-api-key"], "secret");
+            if !request.line.starts_with("GET ") {
+                return Some("{}".to_string());
+            }
+            routes
+                .iter()
+                .find(|(route, _)| request.target() == format!("/api/v3/{route}"))
+                .map(|(_, json)| json.to_string())
+        })
+        .await
+    }
+
+    fn arr_config(kind: ArrKind, url: String, mark_failed: bool) -> ArrConfig {
+        ArrConfig {
+            kind,
+            url,
+            api_key: "secret".to_string(),
+            mark_failed,
+            remote_path: Some(PathBuf::from("/tv")),
+            local_path: Some(PathBuf::from("/srv/media/tv")),
+            timeout_secs: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sonarr_rescans_owning_series() {
+        let (url, requests) = spawn_stub_arr(vec![(
+            "series",
+            json!([
+                { "id": 1, "path": "/tv/Other Show" },
+                { "id": 7, "path": "/tv/The Show" },
+            ]),
+        )])
+        .await;
+        let client = ArrClient::new(
+            reqwest::Client::new(),
+            arr_config(ArrKind::Sonarr, url, false),
+        );
+
+        let owned = client
+            .file_removed(Path::new("/srv/media/tv/The Show/Season 1/e01.mkv"))
+            .await
+            .unwrap();
+        assert!(owned);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].line.starts_with("POST /api/v3/command "));
+        assert_eq!(
+            requests[1].json(),
+            json!({ "name": "RescanSeries", "seriesId": 7 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_radarr_marks_grab_failed() {
+        let (url, requests) = spawn_stub_arr(vec![
+            ("movie", json!([{ "id": 3, "path": "/movies/Film (2024)" }])),
+            (
+                "history/movie?movieId=3",
+                json!([
+                    {
+                        "id": 41,
+                        "eventType": "downloadFolderImported",
+                        "downloadId": "ABC",
+                        "data": { "importedPath": "/movies/Film (2024)/Film.mkv" }
+                    },
+                    { "id": 40, "eventType": "grabbed", "downloadId": "ABC", "data": {} },
+                    { "id": 12, "eventType": "grabbed", "downloadId": "OLD", "data": {} },
+                ]),
+            ),
+        ])
+        .await;
+        let mut config = arr_config(ArrKind::Radarr, url, true);
+        config.remote_path = None;
+        config.local_path = None;
+        let client = ArrClient::new(reqwest::Client::new(), config);
+
+        let owned = client
+            .file_removed(Path::new("/movies/Film (2024)/Film.mkv"))
+            .await
+            .unwrap();
+        assert!(owned);
+
+        let lines: Vec<String> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request.line.clone())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "GET /api/v3/movie HTTP/1.1",
+                "GET /api/v3/history/movie?movieId=3 HTTP/1.1",
+                "POST /api/v3/history/failed/40 HTTP/1.1",
+                "POST /api/v3/command HTTP/1.1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unowned_path_is_left_alone() {
+        let (url, requests) = spawn_stub_arr(vec![(
+            "series",
+            json!([{ "id": 7, "path": "/tv/The Show" }]),
+        )])
+        .await;
+        let client = ArrClient::new(
+            reqwest::Client::new(),
+            arr_config(ArrKind::Sonarr, url, true),
+        );
+
+        let owned = client
+            .file_removed(Path::new("/srv/media/tv/Another/e01.mkv"))
+            .await
+            .unwrap();
+        assert!(!owned);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,328 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::checks::ScanRejection;
+
+const FLAC_MAGIC: &[u8] = b"fLaC";
+const FLAC_STREAMINFO: u8 = 0;
+const FLAC_INVALID_BLOCK: u8 = 127;
+
+/// How far past the tags the first MPEG frame may start.
+const MP3_SYNC_WINDOW: usize = 64 * 1024;
+/// Consecutive frames that must chain up for a file to count as MP3.
+const MP3_FRAMES_TO_CHECK: usize = 4;
+
+/// Header and frame sanity for FLAC and MP3. Other extensions pass. Blocking.
+pub fn check_audio(path: &Path) -> Result<(), ScanRejection> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    let mut file = File::open(path)?;
+    let result = match extension.as_str() {
+        "flac" => check_flac(&mut file),
+        "mp3" => check_mp3(&mut file),
+        _ => return Ok(()),
+    };
+
+    result.map_err(|e| match e {
+        AudioFault::Corrupt(details) => ScanRejection::CorruptAudio(details),
+        AudioFault::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            ScanRejection::CorruptAudio("unexpected end of file".to_string())
+        }
+        AudioFault::Io(e) => ScanRejection::IoError(e),
+    })
+}
+
+enum AudioFault {
+    Corrupt(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for AudioFault {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn corrupt<T>(details: impl Into<String>) -> Result<T, AudioFault> {
+    Err(AudioFault::Corrupt(details.into()))
+}
+
+/// Skips an ID3v2 tag if the file starts with one, returning where the
+/// audio data begins.
+fn skip_id3v2<R: Read + Seek>(reader: &mut R) -> std::io::Result<u64> {
+    let mut header = [0u8; 10];
+    reader.seek(SeekFrom::Start(0))?;
+    if reader.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        return Ok(0);
+    }
+
+    // Syncsafe integer: 7 bits per byte
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |acc, &b| (acc << 7) | (b & 0x7f) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Ok(10 + size + footer)
+}
+
+fn check_flac<R: Read + Seek>(reader: &mut R) -> Result<(), AudioFault> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let start = skip_id3v2(reader)?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != FLAC_MAGIC {
+        return corrupt("missing fLaC marker");
+    }
+
+    let mut first = true;
+    loop {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+        let last = header[0] & 0x80 != 0;
+        let kind = header[0] & 0x7f;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+
+        if kind == FLAC_INVALID_BLOCK {
+            return corrupt("invalid metadata block type");
+        }
+        if first {
+            if kind != FLAC_STREAMINFO || len != 34 {
+                return corrupt("first metadata block is not STREAMINFO");
+            }
+            let mut info = [0u8; 34];
+            reader.read_exact(&mut info)?;
+            check_streaminfo(&info)?;
+            first = false;
+        } else {
+            let next = reader.stream_position()? + len;
+            if next > file_len {
+                return corrupt(format!("metadata block type {kind} runs past end of file"));
+            }
+            reader.seek(SeekFrom::Start(next))?;
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    // Audio frames start with a 14-bit sync code
+    let mut sync = [0u8; 2];
+    if reader.read_exact(&mut sync).is_err() || sync[0] != 0xff || sync[1] & 0xfe != 0xf8 {
+        return corrupt("no audio frame after metadata");
+    }
+
+    Ok(())
+}
+
+fn check_streaminfo(info: &[u8; 34]) -> Result<(), AudioFault> {
+    let min_block = u16::from_be_bytes([info[0], info[1]]);
+    let max_block = u16::from_be_bytes([info[2], info[3]]);
+    let sample_rate = (info[10] as u32) << 12 | (info[11] as u32) << 4 | (info[12] as u32) >> 4;
+    let bits_per_sample = (((info[12] & 0x01) << 4) | (info[13] >> 4)) + 1;
+
+    if min_block < 16 || max_block < min_block {
+        return corrupt(format!("invalid block sizes {min_block}..{max_block}"));
+    }
+    if sample_rate == 0 || sample_rate > 655_350 {
+        return corrupt(format!("invalid sample rate {sample_rate}"));
+    }
+    if bits_per_sample < 4 {
+        return corrupt(format!("invalid bits per sample {bits_per_sample}"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct MpegFrame {
+    len: usize,
+}
+
+/// Parses a 4-byte MPEG audio frame header.
+fn parse_mpeg_header(header: &[u8]) -> Option<MpegFrame> {
+    const BITRATES_V1: [[u32; 15]; 3] = [
+        [
+            0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+        ],
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+        ],
+        [
+            0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+        ],
+    ];
+    const BITRATES_V2: [[u32; 15]; 2] = [
+        [
+            0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+        ],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ];
+    const SAMPLE_RATES: [u32; 3] = [44_100, 48_000, 32_000];
+
+    if header.len() < 4 || header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+
+    // 0 = MPEG 2.5, 2 = MPEG 2, 3 = MPEG 1
+    let version = (header[1] >> 3) & 0x03;
+    // 1 = Layer III, 2 = Layer II, 3 = Layer I
+    let layer = (header[1] >> 1) & 0x03;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let rate_index = ((header[2] >> 2) & 0x03) as usize;
+    let padding = ((header[2] >> 1) & 0x01) as u32;
+
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+
+    let layer_row = (3 - layer) as usize;
+    let bitrate = 1000
+        * if version == 3 {
+            BITRATES_V1[layer_row][bitrate_index]
+        } else {
+            BITRATES_V2[layer_row.min(1)][bitrate_index]
+        };
+    let sample_rate = SAMPLE_RATES[rate_index]
+        >> match version {
+            3 => 0,
+            2 => 1,
+            _ => 2,
+        };
+
+    let len = match layer {
+        3 => (12 * bitrate / sample_rate + padding) * 4,
+        1 if version != 3 => 72 * bitrate / sample_rate + padding,
+        _ => 144 * bitrate / sample_rate + padding,
+    };
+
+    Some(MpegFrame { len: len as usize })
+}
+
+fn check_mp3<R: Read + Seek>(reader: &mut R) -> Result<(), AudioFault> {
+    let start = skip_id3v2(reader)?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    let mut buf = Vec::new();
+    reader
+        .take((MP3_SYNC_WINDOW + 8 * 1024) as u64)
+        .read_to_end(&mut buf)?;
+
+    let window = buf.len().min(MP3_SYNC_WINDOW);
+    let chained = (0..window).find(|&offset| frames_chain(&buf, offset));
+    if chained.is_none() {
+        return corrupt(format!(
+            "no run of {MP3_FRAMES_TO_CHECK} valid MPEG frames in the first {} KB",
+            MP3_SYNC_WINDOW / 1024
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether consecutive valid frame headers follow each other from `offset`.
+/// Short files count if their frames run cleanly to the end of the buffer.
+fn frames_chain(buf: &[u8], mut offset: usize) -> bool {
+    for i in 0..MP3_FRAMES_TO_CHECK {
+        let Some(frame) = buf.get(offset..).and_then(parse_mpeg_header) else {
+            return i > 0 && offset == buf.len();
+        };
+        offset += frame.len;
+        if offset > buf.len() {
+            return i > 0;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flac(streaminfo_ok: bool, with_frame: bool) -> Vec<u8> {
+        let mut file = b"fLaC".to_vec();
+        file.extend_from_slice(&[0x00, 0, 0, 34]);
+        let mut info = [0u8; 34];
+        info[0..2].copy_from_slice(&4096u16.to_be_bytes());
+        info[2..4].copy_from_slice(&4096u16.to_be_bytes());
+        if streaminfo_ok {
+            // 44100 Hz, 2 channels, 16 bits per sample
+            info[10] = 0x0a;
+            info[11] = 0xc4;
+            info[12] = 0x42;
+            info[13] = 0xf0;
+        }
+        file.extend_from_slice(&info);
+        // Last block: padding
+        file.extend_from_slice(&[0x81, 0, 0, 8]);
+        file.extend_from_slice(&[0; 8]);
+        if with_frame {
+            file.extend_from_slice(&[0xff, 0xf8, 0x69, 0x08]);
+        }
+        file
+    }
+
+    /// MPEG-1 Layer III, 128 kbps, 44.1 kHz: 417-byte frames.
+    fn mp3(frames: usize) -> Vec<u8> {
+        let mut file = b"ID3\x04\x00\x00\x00\x00\x00\x0a".to_vec();
+        file.extend_from_slice(&[0; 10]);
+        for _ in 0..frames {
+            let mut frame = vec![0u8; 417];
+            frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+            file.extend(frame);
+        }
+        file
+    }
+
+    fn check(name: &str, contents: &[u8]) -> Result<(), ScanRejection> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        check_audio(&path)
+    }
+
+    #[test]
+    fn test_valid_flac_passes() {
+        assert!(check("song.flac", &flac(true, true)).is_ok());
+    }
+
+    #[test]
+    fn test_flac_bad_streaminfo_rejected() {
+        assert!(matches!(
+            check("song.flac", &flac(false, true)),
+            Err(ScanRejection::CorruptAudio(_))
+        ));
+    }
+
+    #[test]
+    fn test_flac_without_frames_rejected() {
+        assert!(matches!(
+            check("song.flac", &flac(true, false)),
+            Err(ScanRejection::CorruptAudio(_))
+        ));
+    }
+
+    #[test]
+    fn test_valid_mp3_passes() {
+        assert_eq!(
+            parse_mpeg_header(&[0xff, 0xfb, 0x90, 0x00]),
+            Some(MpegFrame { len: 417 })
+        );
+        assert!(check("song.mp3", &mp3(10)).is_ok());
+    }
+
+    #[test]
+    fn test_mp3_junk_rejected() {
+        let mut junk = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+        junk.extend_from_slice(b"MZ\x90\x00 definitely not audio");
+        assert!(matches!(
+            check("song.mp3", &junk),
+            Err(ScanRejection::CorruptAudio(_))
+        ));
+    }
+}
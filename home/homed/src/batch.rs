@@ -0,0 +1,274 @@
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, NaiveTime};
+use tokio::time::Instant;
+
+use crate::alerts::{Alert, Summary};
+use crate::config::{AlertKind, AlertsConfig};
+use crate::schedule;
+use crate::watcher::FileEvent;
+
+/// The summary goes out once no events arrived for this long.
+pub const QUIET_PERIOD: Duration = Duration::from_secs(5);
+
+/// What goes out together: the summary, then the alerts held back while
+/// it was collected.
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub summary: Summary,
+    pub held: Vec<Alert>,
+}
+
+/// Collects events into the summary and decides when it goes out: after a
+/// quiet period, at the end of quiet hours, or once a day in digest mode.
+///
+/// The `_at` variants take the local time, the others read the clock.
+#[derive(Debug)]
+pub struct Batcher {
+    summary: Summary,
+    held: Vec<Alert>,
+    last_event: Option<Instant>,
+    quiet_hours: Option<(NaiveTime, NaiveTime)>,
+    digest_at: Option<NaiveTime>,
+    next_digest: Option<Instant>,
+    /// Set when the summary came due during quiet hours
+    held_until: Option<Instant>,
+}
+
+impl Batcher {
+    pub fn new(config: &AlertsConfig) -> Self {
+        Self::new_at(config, schedule::local_now())
+    }
+
+    pub fn new_at(config: &AlertsConfig, local: NaiveDateTime) -> Self {
+        let quiet_hours = config.quiet_hours.as_ref().and_then(|quiet_hours| {
+            Some((
+                schedule::parse_time_of_day(&quiet_hours.start)?,
+                schedule::parse_time_of_day(&quiet_hours.end)?,
+            ))
+        });
+        let digest_at = config
+            .digest_at
+            .as_deref()
+            .and_then(schedule::parse_time_of_day);
+
+        Self {
+            summary: Summary::default(),
+            held: Vec::new(),
+            last_event: None,
+            quiet_hours,
+            digest_at,
+            next_digest: digest_at
+                .map(|t| Instant::now() + schedule::duration_until_from(local, t)),
+            held_until: None,
+        }
+    }
+
+    pub fn record(&mut self, event: &FileEvent) {
+        self.summary.record(event);
+        self.last_event = Some(Instant::now());
+    }
+
+    /// Keeps an alert that isn't urgent until the summary goes out. Rescans
+    /// are only counted in the summary; the rest follow it, since they may
+    /// carry an action such as the restore button.
+    pub fn hold(&mut self, alert: Alert) {
+        if alert.kind != AlertKind::Rescan {
+            self.held.push(alert);
+        }
+    }
+
+    /// Whether alerts that aren't urgent should be held for the summary
+    /// rather than go out right away.
+    pub fn holds_alerts(&self) -> bool {
+        self.holds_alerts_at(schedule::local_now())
+    }
+
+    pub fn holds_alerts_at(&self, local: NaiveDateTime) -> bool {
+        self.digest_at.is_some() || self.in_quiet_hours(local)
+    }
+
+    fn in_quiet_hours(&self, local: NaiveDateTime) -> bool {
+        self.quiet_hours
+            .is_some_and(|(start, end)| schedule::is_within_at(local.time(), start, end))
+    }
+
+    /// When `flush` should be called next, if there's anything to send.
+    pub fn next_flush(&self) -> Option<Instant> {
+        if self.next_digest.is_some() {
+            return self.next_digest;
+        }
+
+        let due = self.last_event? + QUIET_PERIOD;
+        Some(self.held_until.map_or(due, |held| held.max(due)))
+    }
+
+    /// Takes the batch if it's due. During quiet hours it's held until
+    /// they end.
+    pub fn flush(&mut self) -> Option<Batch> {
+        self.flush_at(schedule::local_now())
+    }
+
+    pub fn flush_at(&mut self, local: NaiveDateTime) -> Option<Batch> {
+        let now = Instant::now();
+        if let (Some(time), Some(next)) = (self.digest_at, self.next_digest) {
+            if now < next {
+                return None;
+            }
+            self.next_digest = Some(now + schedule::duration_until_from(local, time));
+            return Some(self.take());
+        }
+
+        let due = self.next_flush()?;
+        if now < due {
+            return None;
+        }
+        if let Some((_, end)) = self.quiet_hours.filter(|_| self.in_quiet_hours(local)) {
+            self.held_until = Some(now + schedule::duration_until_from(local, end));
+            return None;
+        }
+
+        Some(self.take())
+    }
+
+    /// Takes everything collected so far, e.g. on shutdown.
+    pub fn take(&mut self) -> Batch {
+        self.last_event = None;
+        self.held_until = None;
+        Batch {
+            summary: std::mem::take(&mut self.summary),
+            held: std::mem::take(&mut self.held),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QuietHoursConfig;
+    use crate::watcher::Pipeline;
+    use chrono::NaiveDate;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    fn alerts_config(digest_at: Option<&str>) -> AlertsConfig {
+        AlertsConfig {
+            enabled: true,
+            url: String::new(),
+            topic: String::new(),
+            token: String::new(),
+            templates: HashMap::new(),
+            api: None,
+            notifiers: Vec::new(),
+            routes: Vec::new(),
+            queue_file: None,
+            urgent_priority: 5,
+            quiet_hours: None,
+            digest_at: digest_at.map(String::from),
+            max_per_hour: None,
+        }
+    }
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn organized() -> FileEvent {
+        FileEvent::Organized {
+            pipeline: Pipeline::Photos,
+            old_path: PathBuf::from("/in/a.jpg"),
+            new_path: PathBuf::from("/out/a.jpg"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_summary_after_quiet_period() {
+        let mut batcher = Batcher::new(&alerts_config(None));
+        assert!(!batcher.holds_alerts());
+        assert_eq!(batcher.next_flush(), None);
+
+        batcher.record(&organized());
+        tokio::time::sleep(QUIET_PERIOD / 2).await;
+        batcher.record(&organized());
+        assert!(batcher.flush().is_none());
+
+        tokio::time::sleep_until(batcher.next_flush().unwrap()).await;
+        let batch = batcher.flush().unwrap();
+        assert_eq!(batch.summary.to_string(), "Photos: 2 organized");
+        assert!(batch.held.is_empty());
+        assert_eq!(batcher.next_flush(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_quiet_hours_hold_until_they_end() {
+        let mut config = alerts_config(None);
+        config.quiet_hours = Some(QuietHoursConfig {
+            start: "22:00".to_string(),
+            end: "07:00".to_string(),
+        });
+        let mut batcher = Batcher::new_at(&config, at(23, 0));
+        assert!(batcher.holds_alerts_at(at(23, 0)));
+        assert!(!batcher.holds_alerts_at(at(12, 0)));
+
+        let event = FileEvent::Quarantined {
+            pipeline: Pipeline::Media,
+            path: PathBuf::from("/media/Codec.exe"),
+            id: "20260301-230000-abcd1234".to_string(),
+            reason: "executable".to_string(),
+            malware: false,
+        };
+        batcher.record(&event);
+        batcher.hold(Alert::for_event(&event).unwrap());
+        batcher.hold(Alert::rescan(Pipeline::Media, "overflow"));
+
+        tokio::time::sleep(QUIET_PERIOD).await;
+        assert!(batcher.flush_at(at(23, 0)).is_none());
+        let start = Instant::now();
+        assert_eq!(
+            batcher.next_flush(),
+            Some(start + Duration::from_secs(8 * 3600))
+        );
+
+        tokio::time::sleep_until(batcher.next_flush().unwrap()).await;
+        let batch = batcher.flush_at(at(7, 0)).unwrap();
+        assert_eq!(
+            batch.summary.to_string(),
+            "Media: 1 quarantined (executable)"
+        );
+        assert_eq!(batch.held.len(), 1);
+        assert_eq!(batch.held[0].kind, AlertKind::Quarantine);
+        assert_eq!(batcher.next_flush(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_digest_collects_until_its_time() {
+        let start = Instant::now();
+        let mut batcher = Batcher::new_at(&alerts_config(Some("08:00")), at(6, 0));
+        assert!(batcher.holds_alerts_at(at(12, 0)));
+        let digest_at = start + Duration::from_secs(2 * 3600);
+        assert_eq!(batcher.next_flush(), Some(digest_at));
+
+        batcher.record(&organized());
+        batcher.hold(Alert::quarantine(
+            Pipeline::Photos,
+            Path::new("/in/b.jpg"),
+            "20260301-060000-abcd1234",
+            "bad magic",
+        ));
+        tokio::time::sleep(QUIET_PERIOD * 2).await;
+        assert!(batcher.flush_at(at(6, 0)).is_none());
+
+        tokio::time::sleep_until(digest_at).await;
+        let batch = batcher.flush_at(at(8, 0)).unwrap();
+        assert_eq!(batch.summary.to_string(), "Photos: 1 organized");
+        assert_eq!(batch.held.len(), 1);
+        assert_eq!(
+            batcher.next_flush(),
+            Some(digest_at + Duration::from_secs(24 * 3600))
+        );
+        assert!(batcher.flush_at(at(8, 0)).is_none());
+    }
+}
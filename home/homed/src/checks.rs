@@ -1,37 +1,198 @@
-use std::path::Path;
+use std::future::Future;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
 
 use thiserror::Error;
 use tokio::io::AsyncReadExt;
 
-const VIDEO_EXTS: &[&str] = &["mkv", "mp4", "mov", "webm"];
-const SUBTITLE_EXTS: &[&str] = &["srt", "ass"];
-const MIN_VIDEO_SIZE: u64 = 1024;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::archive::{self, ArchiveKind};
+use crate::config::{
+    ArchiveConfig, CheckAction, ClamavConfig, CommandConfig, HashListConfig, ScannerConfig,
+    SizeRule, SubtitleConfig,
+};
+use crate::hook::{self, HookError, Outcome};
+use crate::reputation::{HashLists, ReputationError};
+use crate::yara::{YaraError, YaraRules};
+use crate::{audio, clamav, container, document, subtitle};
+
+pub const SUBTITLE_EXTS: &[&str] = &["srt", "ass", "ssa", "vtt"];
+
+/// How many leading bytes the magic byte check looks at.
+pub const SNIFF_LEN: usize = 8192;
 
 const EXECUTABLE_EXTS: &[&str] = &[
     "exe", "bat", "cmd", "com", "sh", "bash", "zsh", "py", "pyc", "pl", "rb", "jar", "app", "run",
+    "scr", "pif", "lnk", "msi", "vbs", "ps1", "hta", "cpl",
 ];
 
 #[derive(Debug, Error)]
 pub enum ScanRejection {
+    #[error("path resolves outside the watched paths: {0}")]
+    OutsideWatchedPaths(PathBuf),
+
+    #[error("symlink points outside the watched paths: {0}")]
+    SymlinkEscapesRoot(PathBuf),
+
+    #[error("not a regular file: {0}")]
+    SpecialFile(&'static str),
+
+    #[error("unsafe permissions: {0}")]
+    UnsafePermissions(&'static str),
+
+    #[error("junk extension: .{0}")]
+    JunkFile(String),
+
     #[error("extension not allowed: .{0}")]
     ExtensionBlocked(String),
 
     #[error("executable extension blocked: .{0}")]
     ExecutableExtension(String),
 
-    #[error("video file suspiciously small ({0} bytes)")]
-    FileTooSmall(u64),
+    #[error("file suspiciously small ({size} bytes, minimum {minimum})")]
+    FileTooSmall { size: u64, minimum: u64 },
+
+    #[error("file suspiciously large ({size} bytes, maximum {maximum})")]
+    FileTooLarge { size: u64, maximum: u64 },
+
+    #[error("bitrate too low for its runtime ({bytes_per_second} B/s, minimum {minimum})")]
+    BitrateTooLow { bytes_per_second: u64, minimum: u64 },
+
+    #[error("executable content: {0}")]
+    ExecutableContent(String),
 
     #[error("file type mismatch: expected .{expected}, detected .{actual}")]
     TypeMismatch { expected: String, actual: String },
 
-    #[error("subtitle file is not valid UTF-8")]
+    #[error("corrupt container: {0}")]
+    CorruptContainer(String),
+
+    #[error("corrupt audio: {0}")]
+    CorruptAudio(String),
+
+    #[error("invalid EPUB: {0}")]
+    InvalidEpub(String),
+
+    #[error("EPUB contains scripts: {member}")]
+    EpubScript { member: String },
+
+    #[error("invalid PDF: {0}")]
+    InvalidPdf(String),
+
+    #[error("PDF contains JavaScript")]
+    PdfJavaScript,
+
+    #[error("PDF contains a launch action")]
+    PdfLaunchAction,
+
+    #[error("PDF contains embedded files")]
+    PdfEmbeddedFile,
+
+    #[error("subtitle file is not valid text")]
     InvalidSubtitleEncoding,
 
+    #[error("malformed subtitle: {0}")]
+    MalformedSubtitle(String),
+
+    #[error("subtitle contains blocked pattern \"{0}\"")]
+    SubtitleBlockedPattern(String),
+
+    #[error("malware detected: {signature}")]
+    MalwareDetected { signature: String },
+
+    #[error("virus scan unavailable: {0}")]
+    ScanUnavailable(String),
+
+    #[error("known bad file (SHA-256 {sha256}): {label}")]
+    KnownBadHash { sha256: String, label: String },
+
+    #[error("rejected by {name}: {reason}")]
+    CommandRejected { name: String, reason: String },
+
+    #[error("check command {name} failed: {error}")]
+    CommandFailed { name: String, error: String },
+
+    #[error("matched YARA rules: {}", rules.join(", "))]
+    YaraMatch { rules: Vec<String> },
+
+    #[error("password-protected archive")]
+    ArchivePasswordProtected,
+
+    #[error("archive member {member}: {reason}")]
+    ArchiveMemberRejected {
+        member: String,
+        reason: Box<ScanRejection>,
+    },
+
+    #[error("unreadable archive: {0}")]
+    ArchiveUnreadable(String),
+
     #[error("failed to read file: {0}")]
     IoError(#[from] std::io::Error),
 }
 
+/// Makes sure `path` really lives under one of the (canonical) `roots`,
+/// after resolving every symlink on the way.
+pub async fn check_within_roots(path: &Path, roots: &[PathBuf]) -> Result<(), ScanRejection> {
+    let real = tokio::fs::canonicalize(path).await?;
+    if roots.iter().any(|root| real.starts_with(root)) {
+        Ok(())
+    } else {
+        Err(ScanRejection::OutsideWatchedPaths(real))
+    }
+}
+
+/// lstat-based sanity check run before anything else touches a file. Only
+/// regular files, or symlinks to regular files inside `roots`, without
+/// setuid/setgid or world-writable bits get through.
+pub async fn check_file_kind(path: &Path, roots: &[PathBuf]) -> Result<(), ScanRejection> {
+    // A symlinked directory on the way would make every later rename or
+    // delete act on whatever it points to
+    check_within_roots(path.parent().unwrap_or(path), roots).await?;
+
+    let mut metadata = tokio::fs::symlink_metadata(path).await?;
+    if metadata.file_type().is_symlink() {
+        let target = tokio::fs::read_link(path).await?;
+        match tokio::fs::canonicalize(path).await {
+            Ok(real) if roots.iter().any(|root| real.starts_with(root)) => {
+                metadata = tokio::fs::metadata(&real).await?;
+            }
+            _ => return Err(ScanRejection::SymlinkEscapesRoot(target)),
+        }
+    }
+
+    let file_type = metadata.file_type();
+    let special = if file_type.is_fifo() {
+        Some("FIFO")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        Some("device")
+    } else if !file_type.is_file() {
+        Some("directory or unknown type")
+    } else {
+        None
+    };
+    if let Some(kind) = special {
+        return Err(ScanRejection::SpecialFile(kind));
+    }
+
+    let mode = metadata.permissions().mode();
+    if mode & 0o4000 != 0 {
+        Err(ScanRejection::UnsafePermissions("setuid"))
+    } else if mode & 0o2000 != 0 {
+        Err(ScanRejection::UnsafePermissions("setgid"))
+    } else if mode & 0o002 != 0 {
+        Err(ScanRejection::UnsafePermissions("world-writable"))
+    } else {
+        Ok(())
+    }
+}
+
 pub fn check_extension(path: &Path, allowed: &[String]) -> Result<(), ScanRejection> {
     let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
 
@@ -59,12 +220,43 @@ pub fn check_executable_extension(path: &Path) -> Result<(), ScanRejection> {
     }
 }
 
-pub fn check_file_size(path: &Path, size: u64) -> Result<(), ScanRejection> {
+pub fn check_file_size(path: &Path, size: u64, rules: &[SizeRule]) -> Result<(), ScanRejection> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let lower = extension.to_ascii_lowercase();
+    let Some(rule) = SizeRule::for_extension(rules, &lower) else {
+        return Ok(());
+    };
+
+    if let Some(minimum) = rule.min_bytes.filter(|&min| size < min) {
+        return Err(ScanRejection::FileTooSmall { size, minimum });
+    }
+    if let Some(maximum) = rule.max_bytes.filter(|&max| size > max) {
+        return Err(ScanRejection::FileTooLarge { size, maximum });
+    }
+    Ok(())
+}
+
+/// Catches re-encodes that are far too small for their runtime, e.g. a
+/// two-hour "4K movie" of 40 MB.
+pub fn check_bitrate(
+    path: &Path,
+    size: u64,
+    duration_secs: f64,
+    rules: &[SizeRule],
+) -> Result<(), ScanRejection> {
     let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
     let lower = extension.to_ascii_lowercase();
+    let Some(minimum) = SizeRule::for_extension(rules, &lower).and_then(|r| r.min_bytes_per_second)
+    else {
+        return Ok(());
+    };
 
-    if VIDEO_EXTS.contains(&lower.as_str()) && size < MIN_VIDEO_SIZE {
-        Err(ScanRejection::FileTooSmall(size))
+    let bytes_per_second = (size as f64 / duration_secs) as u64;
+    if bytes_per_second < minimum {
+        Err(ScanRejection::BitrateTooLow {
+            bytes_per_second,
+            minimum,
+        })
     } else {
         Ok(())
     }
@@ -78,9 +270,16 @@ pub async fn check_file_type(path: &Path) -> Result<(), ScanRejection> {
         .to_ascii_lowercase();
 
     let mut file = tokio::fs::File::open(path).await?;
-    let mut buf = [0u8; 8192];
+    let mut buf = [0u8; SNIFF_LEN];
     let n = file.read(&mut buf).await?;
-    let bytes = &buf[..n];
+
+    check_content_type(&extension, &buf[..n])
+}
+
+/// Magic byte check on the first bytes of a file, keyed by its lowercase
+/// extension. Shared with archive inspection, which sniffs members.
+pub fn check_content_type(extension: &str, bytes: &[u8]) -> Result<(), ScanRejection> {
+    let extension = extension.to_string();
 
     match infer::get(bytes) {
         Some(kind) if is_compatible(&extension, kind.extension()) => Ok(()),
@@ -89,7 +288,7 @@ pub async fn check_file_type(path: &Path) -> Result<(), ScanRejection> {
             actual: kind.extension().to_string(),
         }),
         None if SUBTITLE_EXTS.contains(&extension.as_str()) => {
-            if std::str::from_utf8(bytes).is_ok() {
+            if subtitle::decode(bytes, true).is_some() {
                 Ok(())
             } else {
                 Err(ScanRejection::InvalidSubtitleEncoding)
@@ -114,9 +313,843 @@ fn is_compatible(claimed: &str, detected: &str) -> bool {
         .any(|group| group.contains(&claimed) && group.contains(&detected))
 }
 
+impl ScanRejection {
+    /// Detections by an antivirus engine or a known-bad hash, as opposed
+    /// to files that are merely suspicious or broken.
+    pub fn is_malware(&self) -> bool {
+        matches!(
+            self,
+            Self::MalwareDetected { .. } | Self::KnownBadHash { .. }
+        )
+    }
+}
+
+/// What the scanner does with a file after a check looked at it.
+#[derive(Debug)]
+pub enum Verdict {
+    Pass,
+    Delete(ScanRejection),
+    Quarantine(ScanRejection),
+    /// Logged, the remaining checks still run
+    Warn(ScanRejection),
+    /// Stop checking and leave the file where it is
+    Skip(ScanRejection),
+}
+
+impl Verdict {
+    /// The default for checks whose rejections all mean "quarantine".
+    fn quarantine_on_err(result: Result<(), ScanRejection>) -> Self {
+        match result {
+            Ok(()) => Self::Pass,
+            Err(rejection) => Self::Quarantine(rejection),
+        }
+    }
+
+    fn with_action(self, action: CheckAction) -> Self {
+        let rejection = match self {
+            Self::Pass => return Self::Pass,
+            Self::Delete(r) | Self::Quarantine(r) | Self::Warn(r) | Self::Skip(r) => r,
+        };
+        match action {
+            CheckAction::Delete => Self::Delete(rejection),
+            CheckAction::Quarantine => Self::Quarantine(rejection),
+            CheckAction::Warn => Self::Warn(rejection),
+            CheckAction::Skip => Self::Skip(rejection),
+        }
+    }
+}
+
+/// The file under scan, as every check sees it.
+pub struct ScanTarget {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Lowercase, without the dot
+    pub extension: String,
+    /// Allowed extensions after path overrides
+    pub allowed: Vec<String>,
+}
+
+pub type CheckFuture<'a> = Pin<Box<dyn Future<Output = Verdict> + Send + 'a>>;
+
+/// One step of the scanner's per-file pipeline.
+pub trait Check: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a>;
+}
+
+#[derive(Debug, Error)]
+pub enum CheckError {
+    #[error("invalid parameters for check \"{name}\": {source}")]
+    InvalidParams {
+        name: &'static str,
+        source: toml::de::Error,
+    },
+
+    #[error("failed to load YARA rules: {0}")]
+    YaraError(#[from] YaraError),
+
+    #[error("failed to load hash lists: {0}")]
+    ReputationError(#[from] ReputationError),
+
+    #[error(
+        "command \"{name}\" can't run sandboxed ({source}); \
+         this needs CAP_SYS_ADMIN and Linux 5.12, or set sandbox = false"
+    )]
+    SandboxError { name: String, source: HookError },
+}
+
+/// Builds a check from the scanner config and its `[[media.scanner.checks]]`
+/// params, or None when it has nothing to do with this config.
+type Factory = fn(&ScannerConfig, &toml::Table) -> Result<Option<Box<dyn Check>>, CheckError>;
+
+/// Every check in its default order. Adding a check only takes an entry here.
+const REGISTRY: &[(&str, Factory)] = &[
+    ("junk", JunkCheck::build),
+    ("executable", ExecutableCheck::build),
+    ("extension", ExtensionCheck::build),
+    ("size", SizeCheck::build),
+    ("magic", MagicCheck::build),
+    ("container", ContainerCheck::build),
+    ("audio", AudioCheck::build),
+    ("document", DocumentCheck::build),
+    ("subtitle", SubtitleCheck::build),
+    ("hash", HashCheck::build),
+    ("yara", YaraCheck::build),
+    ("clamav", ClamavCheck::build),
+    ("command", CommandCheck::build),
+];
+
+pub fn check_names() -> impl Iterator<Item = &'static str> {
+    REGISTRY.iter().map(|(name, _)| *name)
+}
+
+/// Builds the pipeline: configured checks first in their configured order,
+/// then the remaining ones in default order. Blocking, YARA rules are
+/// compiled here.
+pub fn build_checks(config: &ScannerConfig) -> Result<Vec<Box<dyn Check>>, CheckError> {
+    let configured = config.checks.iter().filter_map(|entry| {
+        REGISTRY
+            .iter()
+            .find(|(name, _)| *name == entry.name)
+            .map(|&(_, factory)| (factory, Some(entry)))
+    });
+    let remaining = REGISTRY
+        .iter()
+        .filter(|(name, _)| !config.checks.iter().any(|c| c.name == *name))
+        .map(|&(_, factory)| (factory, None));
+
+    let no_params = toml::Table::new();
+    let mut pipeline = Vec::new();
+    for (factory, entry) in configured.chain(remaining) {
+        if entry.is_some_and(|e| !e.enabled) {
+            continue;
+        }
+        let params = entry.map_or(&no_params, |e| &e.params);
+        let Some(check) = factory(config, params)? else {
+            continue;
+        };
+        pipeline.push(match entry.and_then(|e| e.action) {
+            Some(action) => Box::new(WithAction {
+                inner: check,
+                action,
+            }),
+            None => check,
+        });
+    }
+
+    Ok(pipeline)
+}
+
+fn parse_params<T: DeserializeOwned>(
+    name: &'static str,
+    params: &toml::Table,
+) -> Result<T, CheckError> {
+    params
+        .clone()
+        .try_into()
+        .map_err(|source| CheckError::InvalidParams { name, source })
+}
+
+/// For checks that take no params, so typos don't pass silently.
+fn no_params(name: &'static str, params: &toml::Table) -> Result<(), CheckError> {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct NoParams {}
+
+    parse_params::<NoParams>(name, params).map(|_| ())
+}
+
+/// Params replace the check's `media.scanner` section when given.
+fn section_or_params<T: DeserializeOwned + Clone>(
+    name: &'static str,
+    params: &toml::Table,
+    section: Option<&T>,
+) -> Result<Option<T>, CheckError> {
+    if params.is_empty() {
+        Ok(section.cloned())
+    } else {
+        parse_params(name, params).map(Some)
+    }
+}
+
+async fn blocking<T: Send + 'static>(task: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(task)
+        .await
+        .expect("check task panicked")
+}
+
+/// A check whose rejections are handled as configured instead of its default.
+struct WithAction {
+    inner: Box<dyn Check>,
+    action: CheckAction,
+}
+
+impl Check for WithAction {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move { self.inner.run(target).await.with_action(self.action) })
+    }
+}
+
+struct JunkCheck {
+    extensions: Vec<String>,
+}
+
+impl JunkCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct JunkParams {
+            extensions: Option<Vec<String>>,
+        }
+
+        let params: JunkParams = parse_params("junk", params)?;
+        if !config.delete_junk {
+            return Ok(None);
+        }
+        let extensions = params
+            .extensions
+            .unwrap_or_else(|| config.junk_extensions.clone());
+        Ok(Some(Box::new(Self { extensions })))
+    }
+}
+
+impl Check for JunkCheck {
+    fn name(&self) -> &'static str {
+        "junk"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            if self
+                .extensions
+                .iter()
+                .any(|j| j.eq_ignore_ascii_case(&target.extension))
+            {
+                Verdict::Delete(ScanRejection::JunkFile(target.extension.clone()))
+            } else {
+                Verdict::Pass
+            }
+        })
+    }
+}
+
+struct ExecutableCheck;
+
+impl ExecutableCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        no_params("executable", params)?;
+        Ok(config
+            .block_executables
+            .then(|| Box::new(Self) as Box<dyn Check>))
+    }
+}
+
+impl Check for ExecutableCheck {
+    fn name(&self) -> &'static str {
+        "executable"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(
+            async move { Verdict::quarantine_on_err(check_executable_extension(&target.path)) },
+        )
+    }
+}
+
+/// The extension allow-list, or member-by-member inspection for archives
+/// when `archives` is configured. Disallowed files are deleted with
+/// `delete_junk` and left alone otherwise.
+struct ExtensionCheck {
+    archives: Option<ArchiveConfig>,
+    junk: Vec<String>,
+    delete_disallowed: bool,
+}
+
+impl ExtensionCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        no_params("extension", params)?;
+        Ok(Some(Box::new(Self {
+            archives: config.archives.clone(),
+            junk: config.junk_extensions.clone(),
+            delete_disallowed: config.delete_junk,
+        })))
+    }
+
+    fn disallowed(&self, rejection: ScanRejection) -> Verdict {
+        if self.delete_disallowed {
+            Verdict::Delete(rejection)
+        } else {
+            Verdict::Skip(rejection)
+        }
+    }
+}
+
+impl Check for ExtensionCheck {
+    fn name(&self) -> &'static str {
+        "extension"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let archive = self
+                .archives
+                .clone()
+                .zip(ArchiveKind::from_path(&target.path));
+
+            let result = match archive {
+                Some((archives, kind)) => {
+                    let path = target.path.clone();
+                    let size = target.size;
+                    let allowed = target.allowed.clone();
+                    let junk = self.junk.clone();
+                    blocking(move || {
+                        archive::inspect(&path, kind, size, &archives, &allowed, &junk)
+                    })
+                    .await
+                }
+                None => check_extension(&target.path, &target.allowed),
+            };
+
+            match result {
+                Ok(()) => Verdict::Pass,
+                // An archive of otherwise disallowed files is handled like one
+                Err(ScanRejection::ArchiveMemberRejected { reason, .. })
+                    if matches!(*reason, ScanRejection::ExtensionBlocked(_)) =>
+                {
+                    self.disallowed(*reason)
+                }
+                Err(rejection @ ScanRejection::ExtensionBlocked(_)) => self.disallowed(rejection),
+                Err(rejection) => Verdict::Quarantine(rejection),
+            }
+        })
+    }
+}
+
+struct SizeCheck {
+    rules: Vec<SizeRule>,
+}
+
+impl SizeCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        no_params("size", params)?;
+        Ok(Some(Box::new(Self {
+            rules: config.size_rules.clone(),
+        })))
+    }
+}
+
+impl Check for SizeCheck {
+    fn name(&self) -> &'static str {
+        "size"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            Verdict::quarantine_on_err(check_file_size(&target.path, target.size, &self.rules))
+        })
+    }
+}
+
+struct MagicCheck;
+
+impl MagicCheck {
+    fn build(
+        _config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        no_params("magic", params)?;
+        Ok(Some(Box::new(Self)))
+    }
+}
+
+impl Check for MagicCheck {
+    fn name(&self) -> &'static str {
+        "magic"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move { Verdict::quarantine_on_err(check_file_type(&target.path).await) })
+    }
+}
+
+/// Container structure, plus the bitrate rules for containers that
+/// declare their duration.
+struct ContainerCheck {
+    rules: Vec<SizeRule>,
+}
+
+impl ContainerCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        no_params("container", params)?;
+        Ok(Some(Box::new(Self {
+            rules: config.size_rules.clone(),
+        })))
+    }
+}
+
+impl Check for ContainerCheck {
+    fn name(&self) -> &'static str {
+        "container"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let path = target.path.clone();
+            let result = match blocking(move || container::check_container(&path)).await {
+                Ok(Some(duration)) => {
+                    check_bitrate(&target.path, target.size, duration, &self.rules)
+                }
+                Ok(None) => Ok(()),
+                Err(rejection) => Err(rejection),
+            };
+            Verdict::quarantine_on_err(result)
+        })
+    }
+}
+
+struct AudioCheck;
+
+impl AudioCheck {
+    fn build(
+        _config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        no_params("audio", params)?;
+        Ok(Some(Box::new(Self)))
+    }
+}
+
+impl Check for AudioCheck {
+    fn name(&self) -> &'static str {
+        "audio"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let path = target.path.clone();
+            Verdict::quarantine_on_err(blocking(move || audio::check_audio(&path)).await)
+        })
+    }
+}
+
+struct DocumentCheck;
+
+impl DocumentCheck {
+    fn build(
+        _config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        no_params("document", params)?;
+        Ok(Some(Box::new(Self)))
+    }
+}
+
+impl Check for DocumentCheck {
+    fn name(&self) -> &'static str {
+        "document"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let path = target.path.clone();
+            let size = target.size;
+            Verdict::quarantine_on_err(
+                blocking(move || document::check_document(&path, size)).await,
+            )
+        })
+    }
+}
+
+struct SubtitleCheck {
+    config: SubtitleConfig,
+}
+
+impl SubtitleCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        let config =
+            section_or_params("subtitle", params, Some(&config.subtitles))?.unwrap_or_default();
+        Ok(Some(Box::new(Self { config })))
+    }
+}
+
+impl Check for SubtitleCheck {
+    fn name(&self) -> &'static str {
+        "subtitle"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            if !SUBTITLE_EXTS.contains(&target.extension.as_str()) {
+                return Verdict::Pass;
+            }
+            let path = target.path.clone();
+            let config = self.config.clone();
+            Verdict::quarantine_on_err(
+                blocking(move || subtitle::check_subtitle(&path, &config)).await,
+            )
+        })
+    }
+}
+
+struct HashCheck {
+    lists: Arc<HashLists>,
+}
+
+impl HashCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        let Some(hashes) =
+            section_or_params::<HashListConfig>("hash", params, config.hashes.as_ref())?
+        else {
+            return Ok(None);
+        };
+        let lists = Arc::new(HashLists::load(&hashes)?);
+        Ok(Some(Box::new(Self { lists })))
+    }
+}
+
+impl Check for HashCheck {
+    fn name(&self) -> &'static str {
+        "hash"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let lists = self.lists.clone();
+            let path = target.path.clone();
+            Verdict::quarantine_on_err(blocking(move || lists.check(&path)).await)
+        })
+    }
+}
+
+struct YaraCheck {
+    rules: YaraRules,
+}
+
+impl YaraCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        let Some(yara) = section_or_params("yara", params, config.yara.as_ref())? else {
+            return Ok(None);
+        };
+        let rules = YaraRules::load(&yara)?;
+        Ok(Some(Box::new(Self { rules })))
+    }
+}
+
+impl Check for YaraCheck {
+    fn name(&self) -> &'static str {
+        "yara"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let rules = self.rules.clone();
+            let path = target.path.clone();
+            let size = target.size;
+            Verdict::quarantine_on_err(blocking(move || rules.check(&path, size)).await)
+        })
+    }
+}
+
+struct ClamavCheck {
+    config: ClamavConfig,
+}
+
+impl ClamavCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        let clamav = section_or_params("clamav", params, config.clamav.as_ref())?;
+        Ok(clamav.map(|config| Box::new(Self { config }) as Box<dyn Check>))
+    }
+}
+
+impl Check for ClamavCheck {
+    fn name(&self) -> &'static str {
+        "clamav"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            Verdict::quarantine_on_err(
+                clamav::check_clamav(&self.config, &target.path, target.size).await,
+            )
+        })
+    }
+}
+
+/// Runs `media.scanner.commands` in order, stopping at the first command
+/// that quarantines or deletes the file.
+struct CommandCheck {
+    commands: Vec<CommandConfig>,
+}
+
+impl CommandCheck {
+    fn build(
+        config: &ScannerConfig,
+        params: &toml::Table,
+    ) -> Result<Option<Box<dyn Check>>, CheckError> {
+        no_params("command", params)?;
+        if config.commands.is_empty() {
+            return Ok(None);
+        }
+        // Without this a command that can't start would only show up per
+        // file, and fail_policy = "open" would let everything through
+        if let Some(command) = config.commands.iter().find(|c| c.sandbox) {
+            hook::probe_sandbox().map_err(|source| CheckError::SandboxError {
+                name: command.name.clone(),
+                source,
+            })?;
+        }
+        Ok(Some(Box::new(Self {
+            commands: config.commands.clone(),
+        })))
+    }
+}
+
+impl Check for CommandCheck {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn run<'a>(&'a self, target: &'a ScanTarget) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let mut verdict = Verdict::Pass;
+            for command in &self.commands {
+                let rejected = |reason| ScanRejection::CommandRejected {
+                    name: command.name.clone(),
+                    reason,
+                };
+                match hook::check_command(command, &target.path).await {
+                    Ok(Outcome::Pass) => {}
+                    Ok(Outcome::Quarantine(reason)) => {
+                        return Verdict::Quarantine(rejected(reason));
+                    }
+                    Ok(Outcome::Delete(reason)) => return Verdict::Delete(rejected(reason)),
+                    // Later commands still run, the first warning is reported
+                    Ok(Outcome::Warn(reason)) => {
+                        if matches!(verdict, Verdict::Pass) {
+                            verdict = Verdict::Warn(rejected(reason));
+                        }
+                    }
+                    Err(rejection) => return Verdict::Quarantine(rejection),
+                }
+            }
+            verdict
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::MediaClass;
+
+    fn scanner_config(extra: &str) -> ScannerConfig {
+        toml::from_str(&format!(
+            r#"
+            quarantine_dir = "/tmp/quarantine"
+            allowed_extensions = ["mkv"]
+            block_executables = true
+            delete_junk = true
+            junk_extensions = ["nfo"]
+            {extra}
+            "#
+        ))
+        .unwrap()
+    }
+
+    fn names(checks: &[Box<dyn Check>]) -> Vec<&'static str> {
+        checks.iter().map(|c| c.name()).collect()
+    }
+
+    #[test]
+    fn test_default_pipeline_order() {
+        let checks = build_checks(&scanner_config("")).unwrap();
+        assert_eq!(
+            names(&checks),
+            vec![
+                "junk",
+                "executable",
+                "extension",
+                "size",
+                "magic",
+                "container",
+                "audio",
+                "document",
+                "subtitle",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_configured_checks_reorder_and_disable() {
+        let config = scanner_config(
+            r#"
+            [[checks]]
+            name = "magic"
+
+            [[checks]]
+            name = "junk"
+            enabled = false
+
+            [[checks]]
+            name = "clamav"
+            socket = "/run/clamav/clamd.ctl"
+            "#,
+        );
+        let checks = build_checks(&config).unwrap();
+        assert_eq!(&names(&checks)[..3], ["magic", "clamav", "executable"]);
+        assert!(!names(&checks).contains(&"junk"));
+
+        let typo = scanner_config("[[checks]]\nname = \"size\"\nmax = 1");
+        assert!(matches!(
+            build_checks(&typo),
+            Err(CheckError::InvalidParams { name: "size", .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_action_override_downgrades_verdict() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.mkv");
+        std::fs::write(&path, b"tiny").unwrap();
+        let target = ScanTarget {
+            path,
+            size: 4,
+            extension: "mkv".to_string(),
+            allowed: vec!["mkv".to_string()],
+        };
+
+        let size = |extra: &str| {
+            let config = scanner_config(&format!("[[checks]]\nname = \"size\"\n{extra}"));
+            build_checks(&config).unwrap().remove(0)
+        };
+        assert!(matches!(
+            size("").run(&target).await,
+            Verdict::Quarantine(ScanRejection::FileTooSmall { .. })
+        ));
+        assert!(matches!(
+            size("action = \"warn\"").run(&target).await,
+            Verdict::Warn(ScanRejection::FileTooSmall { .. })
+        ));
+    }
+
+    async fn canonical_root() -> (tempfile::TempDir, Vec<PathBuf>) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = tokio::fs::canonicalize(dir.path()).await.unwrap();
+        (dir, vec![root])
+    }
+
+    #[tokio::test]
+    async fn test_regular_file_passes_kind_check() {
+        let (dir, roots) = canonical_root().await;
+        let file = dir.path().join("movie.mkv");
+        std::fs::write(&file, b"data").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(check_file_kind(&file, &roots).await.is_ok());
+
+        let link = dir.path().join("link.mkv");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        assert!(check_file_kind(&link, &roots).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_escaping_symlinks_rejected() {
+        let (dir, roots) = canonical_root().await;
+        let link = dir.path().join("movie.mkv");
+        std::os::unix::fs::symlink("/etc/passwd", &link).unwrap();
+        assert!(matches!(
+            check_file_kind(&link, &roots).await,
+            Err(ScanRejection::SymlinkEscapesRoot(target)) if target == Path::new("/etc/passwd")
+        ));
+
+        let release = dir.path().join("Release");
+        std::os::unix::fs::symlink("/etc", &release).unwrap();
+        assert!(matches!(
+            check_file_kind(&release.join("passwd"), &roots).await,
+            Err(ScanRejection::OutsideWatchedPaths(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fifo_rejected() {
+        let (dir, roots) = canonical_root().await;
+        let fifo = dir.path().join("movie.mkv");
+        let c_path = std::ffi::CString::new(fifo.as_os_str().as_encoded_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) }, 0);
+        assert!(matches!(
+            check_file_kind(&fifo, &roots).await,
+            Err(ScanRejection::SpecialFile("FIFO"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsafe_permissions_rejected() {
+        let (dir, roots) = canonical_root().await;
+        let file = dir.path().join("movie.mkv");
+        std::fs::write(&file, b"data").unwrap();
+
+        for (mode, expected) in [
+            (0o4755, "setuid"),
+            (0o2755, "setgid"),
+            (0o666, "world-writable"),
+        ] {
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(mode)).unwrap();
+            assert!(matches!(
+                check_file_kind(&file, &roots).await,
+                Err(ScanRejection::UnsafePermissions(kind)) if kind == expected
+            ));
+        }
+    }
 
     #[test]
     fn test_allowed_extension() {
@@ -161,20 +1194,70 @@ mod tests {
         );
     }
 
+    fn size_rules() -> Vec<SizeRule> {
+        vec![
+            SizeRule {
+                class: Some(MediaClass::Video),
+                min_bytes: Some(1024),
+                min_bytes_per_second: Some(100_000),
+                ..Default::default()
+            },
+            SizeRule {
+                extensions: vec!["srt".to_string()],
+                max_bytes: Some(1024 * 1024),
+                ..Default::default()
+            },
+        ]
+    }
+
     #[test]
     fn test_small_video_rejected() {
-        let result = check_file_size(Path::new("tiny.mkv"), 500);
-        assert!(matches!(result, Err(ScanRejection::FileTooSmall(500))));
+        let result = check_file_size(Path::new("tiny.mkv"), 500, &size_rules());
+        assert!(matches!(
+            result,
+            Err(ScanRejection::FileTooSmall {
+                size: 500,
+                minimum: 1024
+            })
+        ));
     }
 
     #[test]
     fn test_normal_video_passes() {
-        assert!(check_file_size(Path::new("movie.mkv"), 1_000_000).is_ok());
+        assert!(check_file_size(Path::new("movie.mkv"), 1_000_000, &size_rules()).is_ok());
     }
 
     #[test]
     fn test_small_subtitle_passes() {
-        assert!(check_file_size(Path::new("subs.srt"), 100).is_ok());
+        assert!(check_file_size(Path::new("subs.srt"), 100, &size_rules()).is_ok());
+    }
+
+    #[test]
+    fn test_huge_subtitle_rejected() {
+        let result = check_file_size(Path::new("subs.srt"), 50_000_000, &size_rules());
+        assert!(matches!(result, Err(ScanRejection::FileTooLarge { .. })));
+    }
+
+    #[test]
+    fn test_low_bitrate_video_rejected() {
+        let two_hours = 7200.0;
+        let result = check_bitrate(Path::new("movie.mkv"), 40_000_000, two_hours, &size_rules());
+        assert!(matches!(
+            result,
+            Err(ScanRejection::BitrateTooLow {
+                bytes_per_second: 5555,
+                ..
+            })
+        ));
+        assert!(
+            check_bitrate(
+                Path::new("movie.mkv"),
+                4_000_000_000,
+                two_hours,
+                &size_rules()
+            )
+            .is_ok()
+        );
     }
 
     #[tokio::test]
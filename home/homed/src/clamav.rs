@@ -0,0 +1,297 @@
+use std::path::Path;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tracing::{debug, warn};
+
+use crate::checks::ScanRejection;
+use crate::config::{ClamavConfig, FailPolicy};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum ClamavError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("clamd did not answer within {0:?}")]
+    Timeout(Duration),
+
+    #[error("file exceeds clamd's StreamMaxLength")]
+    SizeLimitExceeded,
+
+    #[error("unexpected clamd reply: {0}")]
+    ProtocolError(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    Infected(String),
+}
+
+/// Scans a file with clamd and maps the result to a rejection.
+///
+/// Files over `max_scan_size_mb` are skipped. When clamd can't be reached
+/// the configured policy decides: `open` lets the file through with a
+/// warning, `closed` rejects it.
+pub async fn check_clamav(
+    config: &ClamavConfig,
+    path: &Path,
+    size: u64,
+) -> Result<(), ScanRejection> {
+    if size > config.max_scan_size_mb * 1024 * 1024 {
+        debug!(path = %path.display(), size, "file too large for clamd scan, skipping");
+        return Ok(());
+    }
+    let timeout = Duration::from_secs(config.timeout_secs);
+
+    match scan_file(&config.socket, path, timeout).await {
+        Ok(Verdict::Clean) => Ok(()),
+        Ok(Verdict::Infected(signature)) => Err(ScanRejection::MalwareDetected { signature }),
+        // clamd works, it's only configured for smaller files than we are
+        Err(ClamavError::SizeLimitExceeded) => {
+            warn!(
+                path = %path.display(),
+                size,
+                "file exceeds clamd's StreamMaxLength, not scanned; \
+                 lower clamav.max_scan_size_mb or raise StreamMaxLength"
+            );
+            Ok(())
+        }
+        Err(e) => match config.fail_policy {
+            FailPolicy::Open => {
+                warn!(path = %path.display(), error = %e, "clamd unavailable, letting file through");
+                Ok(())
+            }
+            FailPolicy::Closed => Err(ScanRejection::ScanUnavailable(e.to_string())),
+        },
+    }
+}
+
+/// Streams a file to clamd using the `INSTREAM` command.
+pub async fn scan_file(
+    socket: &Path,
+    path: &Path,
+    timeout: Duration,
+) -> Result<Verdict, ClamavError> {
+    tokio::time::timeout(timeout, scan_file_inner(socket, path))
+        .await
+        .map_err(|_| ClamavError::Timeout(timeout))?
+}
+
+async fn scan_file_inner(socket: &Path, path: &Path) -> Result<Verdict, ClamavError> {
+    let mut stream = UnixStream::connect(socket).await?;
+    let mut file = tokio::fs::File::open(path).await?;
+
+    let sent = send_stream(&mut stream, &mut file).await;
+
+    // Over StreamMaxLength clamd replies and hangs up mid-stream, so the
+    // reply is worth reading even when sending failed
+    let mut reply = Vec::new();
+    let received = stream.read_to_end(&mut reply).await;
+    match (sent, parse_reply(&reply)) {
+        (_, Err(ClamavError::SizeLimitExceeded)) => Err(ClamavError::SizeLimitExceeded),
+        (Err(e), _) => Err(e.into()),
+        (Ok(()), result) => {
+            received?;
+            result
+        }
+    }
+}
+
+async fn send_stream(stream: &mut UnixStream, file: &mut tokio::fs::File) -> std::io::Result<()> {
+    stream.write_all(b"zINSTREAM\0").await?;
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&(n as u32).to_be_bytes()).await?;
+        stream.write_all(&buf[..n]).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await
+}
+
+/// clamd replies `stream: OK`, `stream: <signature> FOUND` or `<message> ERROR`.
+fn parse_reply(reply: &[u8]) -> Result<Verdict, ClamavError> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let body = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if body == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(signature) = body.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(signature.to_string()))
+    } else if body.starts_with("INSTREAM size limit exceeded") {
+        Err(ClamavError::SizeLimitExceeded)
+    } else {
+        Err(ClamavError::ProtocolError(reply.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tokio::net::UnixListener;
+
+    /// Minimal clamd stand-in: consumes one INSTREAM request and answers
+    /// FOUND if the payload contains "EICAR", OK otherwise.
+    fn spawn_stub_clamd(socket: PathBuf) {
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut command = [0u8; 10];
+                conn.read_exact(&mut command).await.unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+
+                let mut payload = Vec::new();
+                loop {
+                    let len = conn.read_u32().await.unwrap() as usize;
+                    if len == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0u8; len];
+                    conn.read_exact(&mut chunk).await.unwrap();
+                    payload.extend_from_slice(&chunk);
+                }
+
+                let found = payload.windows(5).any(|w| w == b"EICAR");
+                let reply: &[u8] = if found {
+                    b"stream: Eicar-Test-Signature FOUND\0"
+                } else {
+                    b"stream: OK\0"
+                };
+                conn.write_all(reply).await.unwrap();
+            }
+        });
+    }
+
+    fn clamav_config(socket: PathBuf, fail_policy: FailPolicy) -> ClamavConfig {
+        ClamavConfig {
+            socket,
+            fail_policy,
+            timeout_secs: 5,
+            max_scan_size_mb: 1,
+        }
+    }
+
+    /// clamd with a StreamMaxLength of one chunk: answers the size limit
+    /// error after the first chunk and hangs up.
+    fn spawn_size_limited_clamd(socket: PathBuf) {
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut command = [0u8; 10];
+                conn.read_exact(&mut command).await.unwrap();
+                let len = conn.read_u32().await.unwrap() as usize;
+                let mut chunk = vec![0u8; len];
+                conn.read_exact(&mut chunk).await.unwrap();
+                conn.write_all(b"INSTREAM size limit exceeded. ERROR\0")
+                    .await
+                    .unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn test_parse_replies() {
+        assert_eq!(parse_reply(b"stream: OK\0").unwrap(), Verdict::Clean);
+        assert_eq!(
+            parse_reply(b"stream: Win.Trojan.Agent-1 FOUND\0").unwrap(),
+            Verdict::Infected("Win.Trojan.Agent-1".to_string())
+        );
+        assert!(matches!(
+            parse_reply(b"INSTREAM size limit exceeded. ERROR\0"),
+            Err(ClamavError::SizeLimitExceeded)
+        ));
+        assert!(matches!(
+            parse_reply(b"stream: Can't allocate memory ERROR\0"),
+            Err(ClamavError::ProtocolError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_clean_file_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("clamd.sock");
+        spawn_stub_clamd(socket.clone());
+        let file = dir.path().join("movie.mkv");
+        tokio::fs::write(&file, vec![7u8; 200_000]).await.unwrap();
+
+        let config = clamav_config(socket, FailPolicy::Closed);
+        assert!(check_clamav(&config, &file, 200_000).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_size_limit_is_not_unavailable() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("clamd.sock");
+        spawn_size_limited_clamd(socket.clone());
+        let file = dir.path().join("movie.mkv");
+        tokio::fs::write(&file, vec![7u8; 900_000]).await.unwrap();
+
+        let timeout = Duration::from_secs(5);
+        assert!(matches!(
+            scan_file(&socket, &file, timeout).await,
+            Err(ClamavError::SizeLimitExceeded)
+        ));
+        let config = clamav_config(socket, FailPolicy::Closed);
+        assert!(check_clamav(&config, &file, 900_000).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_large_file_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("movie.mkv");
+        tokio::fs::write(&file, b"data").await.unwrap();
+
+        // Never connects, so clamd being down doesn't matter
+        let config = clamav_config(dir.path().join("missing.sock"), FailPolicy::Closed);
+        assert!(check_clamav(&config, &file, 2 * 1024 * 1024).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_infected_file_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("clamd.sock");
+        spawn_stub_clamd(socket.clone());
+        let file = dir.path().join("movie.mkv");
+        tokio::fs::write(&file, b"X5O!P%@AP EICAR payload")
+            .await
+            .unwrap();
+
+        let config = clamav_config(socket, FailPolicy::Open);
+        let result = check_clamav(&config, &file, 4).await;
+        assert!(matches!(
+            result,
+            Err(ScanRejection::MalwareDetected { signature }) if signature == "Eicar-Test-Signature"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_clamd_down_fail_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("movie.mkv");
+        tokio::fs::write(&file, b"data").await.unwrap();
+
+        let config = clamav_config(dir.path().join("missing.sock"), FailPolicy::Open);
+        assert!(check_clamav(&config, &file, 4).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_clamd_down_fail_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("movie.mkv");
+        tokio::fs::write(&file, b"data").await.unwrap();
+
+        let config = clamav_config(dir.path().join("missing.sock"), FailPolicy::Closed);
+        let result = check_clamav(&config, &file, 4).await;
+        assert!(matches!(result, Err(ScanRejection::ScanUnavailable(_))));
+    }
+}
@@ -1,8 +1,14 @@
-use serde::Deserialize;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+use crate::checks;
+use crate::schedule;
+
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum ConfigError {
     #[error("Failed to read config file: {0}")]
     ReadError(#[from] std::io::Error),
@@ -26,12 +32,38 @@ pub struct PhotosConfig {
     pub watcher: WatcherConfig,
     pub organizer: OrganizerConfig,
     pub nextcloud: NextcloudConfig,
+    #[serde(default)]
+    pub overrides: Vec<PathOverride>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct MediaConfig {
     pub watcher: WatcherConfig,
     pub scanner: ScannerConfig,
+    #[serde(default)]
+    pub overrides: Vec<PathOverride>,
+}
+
+/// Settings that apply only to files under `path`, taking precedence over
+/// the section defaults. Unset fields fall back to the section value.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PathOverride {
+    pub path: PathBuf,
+    pub debounce_ms: Option<u64>,
+    pub ignore_extensions: Option<Vec<String>>,
+    pub allowed_extensions: Option<Vec<String>>,
+    pub photo_prefix: Option<String>,
+    pub video_prefix: Option<String>,
+}
+
+impl PathOverride {
+    /// Returns the most specific override whose path contains `path`.
+    pub fn for_path<'a>(overrides: &'a [PathOverride], path: &Path) -> Option<&'a PathOverride> {
+        overrides
+            .iter()
+            .filter(|o| path.starts_with(&o.path))
+            .max_by_key(|o| o.path.components().count())
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -40,16 +72,316 @@ pub struct WatcherConfig {
     pub debounce_ms: u64,
     #[serde(default)]
     pub ignore_extensions: Vec<String>,
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+    #[serde(default = "default_startup_scan_rate")]
+    pub startup_scan_rate: u32,
+    #[serde(default)]
+    pub reconcile_at: Option<String>,
+    #[serde(default)]
+    pub batch_quiet_ms: Option<u64>,
+    #[serde(default)]
+    pub backend: WatchBackend,
+}
+
+/// Kernel interface used to watch for changes. `fanotify` needs
+/// CAP_SYS_ADMIN and falls back to `inotify` when it can't be set up.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchBackend {
+    #[default]
+    Inotify,
+    Fanotify,
+}
+
+fn default_startup_scan_rate() -> u32 {
+    50
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ScannerConfig {
     pub quarantine_dir: PathBuf,
+    /// Quarantined items older than this are purged automatically.
+    #[serde(default)]
+    pub quarantine_expiry_days: Option<u64>,
     pub allowed_extensions: Vec<String>,
     pub block_executables: bool,
     pub delete_junk: bool,
+    /// Clear the execute bits of every file that passes the scan.
+    #[serde(default)]
+    pub strip_exec_bits: bool,
     #[serde(default)]
     pub junk_extensions: Vec<String>,
+    #[serde(default)]
+    pub clamav: Option<ClamavConfig>,
+    #[serde(default)]
+    pub yara: Option<YaraConfig>,
+    #[serde(default)]
+    pub hashes: Option<HashListConfig>,
+    #[serde(default)]
+    pub archives: Option<ArchiveConfig>,
+    /// External programs run on every allowed file, in order.
+    #[serde(default)]
+    pub commands: Vec<CommandConfig>,
+    #[serde(default = "default_size_rules")]
+    pub size_rules: Vec<SizeRule>,
+    #[serde(default)]
+    pub subtitles: SubtitleConfig,
+    #[serde(default)]
+    pub release: ReleaseConfig,
+    /// Sonarr/Radarr instances told about files the scanner removes.
+    #[serde(default)]
+    pub arr: Vec<ArrConfig>,
+    /// Order and settings of the per-file checks. Listed checks run first,
+    /// in the given order; the rest follow in their default order.
+    #[serde(default)]
+    pub checks: Vec<CheckConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CheckConfig {
+    pub name: String,
+    #[serde(default = "default_check_enabled")]
+    pub enabled: bool,
+    /// Replaces what the check does with files it rejects.
+    #[serde(default)]
+    pub action: Option<CheckAction>,
+    /// Check-specific settings. When empty, the check uses its section
+    /// under `media.scanner` (e.g. `clamav`, `yara`, `subtitles`).
+    #[serde(flatten)]
+    pub params: toml::Table,
+}
+
+fn default_check_enabled() -> bool {
+    true
+}
+
+/// What the scanner does with a file a check rejected.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckAction {
+    Delete,
+    Quarantine,
+    /// Log the rejection and keep checking
+    Warn,
+    /// Stop checking and leave the file where it is
+    Skip,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrKind {
+    Sonarr,
+    Radarr,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ArrConfig {
+    pub kind: ArrKind,
+    /// e.g. `http://localhost:8989`
+    pub url: String,
+    pub api_key: String,
+    /// Mark the grab as failed so the release is blocklisted and searched
+    /// again, instead of only rescanning.
+    #[serde(default)]
+    pub mark_failed: bool,
+    /// Where the library lives inside the Sonarr/Radarr container, if it
+    /// differs from `local_path` as seen by homed.
+    #[serde(default)]
+    pub remote_path: Option<PathBuf>,
+    #[serde(default)]
+    pub local_path: Option<PathBuf>,
+    #[serde(default = "default_arr_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_arr_timeout_secs() -> u64 {
+    10
+}
+
+/// Heuristics applied to a release directory as a whole.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReleaseConfig {
+    /// Quarantine releases whose only videos are samples.
+    #[serde(default = "default_reject_sample_only")]
+    pub reject_sample_only: bool,
+    /// Case-insensitive substrings of file names only fake releases ship.
+    /// Media files never match. Empty unless configured.
+    #[serde(default)]
+    pub lure_patterns: Vec<String>,
+}
+
+impl Default for ReleaseConfig {
+    fn default() -> Self {
+        Self {
+            reject_sample_only: default_reject_sample_only(),
+            lure_patterns: Vec::new(),
+        }
+    }
+}
+
+fn default_reject_sample_only() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SubtitleConfig {
+    /// Rewrite UTF-16 and Windows-1252 subtitles as UTF-8.
+    #[serde(default)]
+    pub convert_to_utf8: bool,
+    /// Case-insensitive substrings that get a subtitle rejected. Empty
+    /// unless configured.
+    #[serde(default)]
+    pub reject_patterns: Vec<String>,
+}
+
+/// Size limits for files matching a list of extensions or a media class.
+/// Extension rules take precedence over class rules.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SizeRule {
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub class: Option<MediaClass>,
+    pub min_bytes: Option<u64>,
+    pub max_bytes: Option<u64>,
+    /// Minimum size per second of runtime, for containers that declare
+    /// their duration.
+    pub min_bytes_per_second: Option<u64>,
+}
+
+impl SizeRule {
+    /// The rule for a lowercase extension, preferring an extension match.
+    pub fn for_extension<'a>(rules: &'a [SizeRule], ext: &str) -> Option<&'a SizeRule> {
+        rules
+            .iter()
+            .find(|r| r.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+            .or_else(|| {
+                let class = MediaClass::of(ext)?;
+                rules.iter().find(|r| r.class == Some(class))
+            })
+    }
+}
+
+fn default_size_rules() -> Vec<SizeRule> {
+    vec![SizeRule {
+        class: Some(MediaClass::Video),
+        min_bytes: Some(1024),
+        ..Default::default()
+    }]
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaClass {
+    Video,
+    Audio,
+    Subtitle,
+    Ebook,
+}
+
+impl MediaClass {
+    pub fn of(ext: &str) -> Option<Self> {
+        match ext {
+            "mkv" | "mp4" | "m4v" | "mov" | "webm" | "avi" | "ts" => Some(Self::Video),
+            "flac" | "mp3" | "m4a" | "ogg" | "opus" | "wav" => Some(Self::Audio),
+            "srt" | "ass" | "ssa" | "vtt" | "sub" => Some(Self::Subtitle),
+            "epub" | "pdf" | "mobi" | "azw3" => Some(Self::Ebook),
+            _ => None,
+        }
+    }
+}
+
+/// Inspects .zip/.rar/.7z members instead of treating archives as
+/// disallowed extensions.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ArchiveConfig {
+    /// Archives above this size are only listed, not decompressed for
+    /// magic byte checks.
+    #[serde(default = "default_archive_max_inspect_mb")]
+    pub max_inspect_mb: u64,
+}
+
+fn default_archive_max_inspect_mb() -> u64 {
+    1024
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct YaraConfig {
+    pub rules_dir: PathBuf,
+    /// Larger files are scanned only in their first and last half of this.
+    #[serde(default = "default_yara_max_file_size_mb")]
+    pub max_file_size_mb: u64,
+}
+
+fn default_yara_max_file_size_mb() -> u64 {
+    100
+}
+
+/// SHA-256 lists of known-bad files, one hash per line optionally followed
+/// by a name or comment.
+#[derive(Debug, Deserialize, Clone)]
+pub struct HashListConfig {
+    pub blocklists: Vec<PathBuf>,
+    /// Hashes that pass even when blocklisted, for false positives.
+    #[serde(default)]
+    pub allowlists: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ClamavConfig {
+    pub socket: PathBuf,
+    #[serde(default)]
+    pub fail_policy: FailPolicy,
+    #[serde(default = "default_clamav_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Larger files aren't sent to clamd. Keep it at or below clamd's
+    /// `StreamMaxLength`.
+    #[serde(default = "default_clamav_max_scan_size_mb")]
+    pub max_scan_size_mb: u64,
+}
+
+fn default_clamav_timeout_secs() -> u64 {
+    300
+}
+
+/// clamd's own default `StreamMaxLength`
+fn default_clamav_max_scan_size_mb() -> u64 {
+    25
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CommandConfig {
+    /// Shown in logs and quarantine reasons.
+    pub name: String,
+    /// Program and arguments; the file path is appended.
+    pub command: Vec<String>,
+    #[serde(default = "default_command_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Run without network and with the whole filesystem read-only.
+    #[serde(default = "default_command_sandbox")]
+    pub sandbox: bool,
+    #[serde(default)]
+    pub fail_policy: FailPolicy,
+}
+
+fn default_command_timeout_secs() -> u64 {
+    60
+}
+
+fn default_command_sandbox() -> bool {
+    true
+}
+
+/// What to do with a file when an external scanner can't give a verdict.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailPolicy {
+    /// Let the file through and log a warning
+    #[default]
+    Open,
+    /// Quarantine the file
+    Closed,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -84,11 +416,258 @@ pub struct NextcloudConfig {
 #[derive(Debug, Deserialize, Clone)]
 pub struct AlertsConfig {
     pub enabled: bool,
+    /// The ntfy server of the notifier named `ntfy`. Leave empty to only
+    /// use `notifiers`.
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub topic: String,
+    #[serde(default)]
+    pub token: String,
+    /// Replace parts of the built-in template of an alert kind.
+    #[serde(default)]
+    pub templates: HashMap<AlertKind, AlertTemplate>,
+    /// HTTP endpoint behind alert buttons like "Restore from quarantine".
+    #[serde(default)]
+    pub api: Option<ApiConfig>,
+    /// Backends besides the ntfy server above.
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+    /// Which notifiers get which alerts. Without routes, every notifier
+    /// gets every alert.
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Alerts that couldn't be delivered yet are kept here across
+    /// restarts. Without it they only live in memory.
+    #[serde(default)]
+    pub queue_file: Option<PathBuf>,
+    /// Alerts at least this urgent (1-5) skip quiet hours and the digest.
+    #[serde(default = "default_urgent_priority")]
+    pub urgent_priority: u8,
+    /// Local times during which only urgent alerts go out; the rest wait
+    /// for the summary at the end.
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHoursConfig>,
+    /// Local time (`HH:MM`) of a daily summary that replaces the summary
+    /// after each quiet period and holds back alerts that aren't urgent.
+    #[serde(default)]
+    pub digest_at: Option<String>,
+    /// Alerts per hour for each notifier without its own limit.
+    #[serde(default)]
+    pub max_per_hour: Option<u32>,
+}
+
+fn default_urgent_priority() -> u8 {
+    5
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct QuietHoursConfig {
+    /// `HH:MM`, local time
+    pub start: String,
+    /// `HH:MM`, local time; before `start` to span midnight
+    pub end: String,
+}
+
+impl AlertsConfig {
+    /// Every notifier, including `ntfy` from the top level fields.
+    pub fn all_notifiers(&self) -> Vec<NotifierConfig> {
+        let ntfy = (!self.url.is_empty()).then(|| NotifierConfig {
+            name: "ntfy".to_string(),
+            max_per_hour: None,
+            backend: NotifierBackend::Ntfy(NtfyConfig {
+                url: self.url.clone(),
+                topic: self.topic.clone(),
+                token: self.token.clone(),
+            }),
+        });
+        ntfy.into_iter().chain(self.notifiers.clone()).collect()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct NotifierConfig {
+    /// Referenced by `routes`
+    pub name: String,
+    /// Overrides `alerts.max_per_hour`
+    #[serde(default)]
+    pub max_per_hour: Option<u32>,
+    #[serde(flatten)]
+    pub backend: NotifierBackend,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierBackend {
+    Ntfy(NtfyConfig),
+    Gotify(GotifyConfig),
+    Matrix(MatrixConfig),
+    Smtp(SmtpConfig),
+    Webhook(WebhookConfig),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct NtfyConfig {
     pub url: String,
     pub topic: String,
+    #[serde(default)]
     pub token: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct GotifyConfig {
+    pub url: String,
+    /// Application token
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MatrixConfig {
+    /// e.g. `https://matrix.example.com`
+    pub homeserver: String,
+    /// e.g. `!AbCdEf:example.com`; the account must have joined it
+    pub room_id: String,
+    pub access_token: String,
+}
+
+/// Plain SMTP without authentication, meant for a local relay.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SmtpConfig {
+    #[serde(default = "default_smtp_host")]
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default = "default_smtp_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_smtp_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+fn default_smtp_timeout() -> u64 {
+    30
+}
+
+/// POSTs every alert as a JSON object.
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RouteConfig {
+    /// Alert kinds this route applies to, all of them when empty
+    #[serde(default)]
+    pub kinds: Vec<AlertKind>,
+    /// Only alerts at least this urgent (1-5)
+    #[serde(default = "default_min_priority")]
+    pub min_priority: u8,
+    /// Names of the notifiers that get matching alerts
+    pub notifiers: Vec<String>,
+}
+
+fn default_min_priority() -> u8 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertKind {
+    /// Counts of what happened since the last quiet period
+    Summary,
+    /// The watcher lost events and is rescanning
+    Rescan,
+    /// A file or release was moved to quarantine
+    Quarantine,
+    /// Malware was found, quarantined or not
+    Malware,
+}
+
+impl AlertKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Summary => "summary",
+            Self::Rescan => "rescan",
+            Self::Quarantine => "quarantine",
+            Self::Malware => "malware",
+        }
+    }
+}
+
+/// Alert message fields. Unset fields keep the kind's default; `{name}`
+/// style placeholders are filled in from the alert.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AlertTemplate {
+    pub title: Option<String>,
+    pub message: Option<String>,
+    /// 1 (min) to 5 (urgent)
+    pub priority: Option<u8>,
+    /// Emoji shortcodes or plain tags
+    pub tags: Option<Vec<String>>,
+    /// Opened when the notification is tapped
+    pub click: Option<String>,
+    pub actions: Option<Vec<AlertAction>>,
+}
+
+/// An action button, shown by ntfy.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AlertAction {
+    #[serde(default)]
+    pub action: ActionKind,
+    pub label: String,
+    pub url: String,
+    /// For `http` actions
+    #[serde(default = "default_action_method")]
+    pub method: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+fn default_action_method() -> String {
+    "POST".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionKind {
+    /// Open the URL on the device
+    View,
+    /// Send a request to the URL in the background
+    #[default]
+    Http,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiConfig {
+    /// e.g. `127.0.0.1:8750`
+    pub listen: SocketAddr,
+    /// Bearer token required on every request, and the key that signs
+    /// the restore URLs in alerts.
+    pub token: String,
+    /// How phones reach the API, e.g. through a reverse proxy. Defaults
+    /// to `http://<listen>`.
+    #[serde(default)]
+    pub public_url: Option<String>,
+}
+
+impl ApiConfig {
+    pub fn public_url(&self) -> String {
+        match &self.public_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("http://{}", self.listen),
+        }
+    }
+}
+
 impl Config {
     pub fn load(path: &str) -> Result<Self, ConfigError> {
         let content = std::fs::read_to_string(path)?;
@@ -101,6 +680,264 @@ impl Config {
     fn validate(&self) -> Result<(), ConfigError> {
         Self::validate_watcher(&self.photos.watcher, "photos")?;
         Self::validate_watcher(&self.media.watcher, "media")?;
+        Self::validate_overrides(&self.photos.overrides, &self.photos.watcher, "photos")?;
+        Self::validate_overrides(&self.media.overrides, &self.media.watcher, "media")?;
+
+        Self::validate_size_rules(&self.media.scanner.size_rules)?;
+
+        if self.media.scanner.quarantine_expiry_days == Some(0) {
+            return Err(ConfigError::ValidationError(
+                "media.scanner.quarantine_expiry_days must be greater than 0".to_string(),
+            ));
+        }
+
+        Self::validate_checks(&self.media.scanner.checks)?;
+        Self::validate_commands(&self.media.scanner.commands)?;
+
+        if let Some(hashes) = &self.media.scanner.hashes {
+            if hashes.blocklists.is_empty() {
+                return Err(ConfigError::ValidationError(
+                    "media.scanner.hashes.blocklists must not be empty".to_string(),
+                ));
+            }
+        }
+
+        for arr in &self.media.scanner.arr {
+            if arr.remote_path.is_some() != arr.local_path.is_some() {
+                return Err(ConfigError::ValidationError(format!(
+                    "media.scanner.arr {} needs both remote_path and local_path, or neither",
+                    arr.url
+                )));
+            }
+        }
+
+        Self::validate_alerts(&self.alerts)?;
+
+        if self.photos.watcher.batch_quiet_ms.is_some() {
+            return Err(ConfigError::ValidationError(
+                "photos.watcher.batch_quiet_ms is only supported for the media pipeline"
+                    .to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_alerts(alerts: &AlertsConfig) -> Result<(), ConfigError> {
+        for (kind, template) in &alerts.templates {
+            if template.priority.is_some_and(|p| !(1..=5).contains(&p)) {
+                return Err(ConfigError::ValidationError(format!(
+                    "alerts.templates.{}: priority must be between 1 and 5",
+                    kind.name()
+                )));
+            }
+        }
+
+        if alerts.api.as_ref().is_some_and(|api| api.token.is_empty()) {
+            return Err(ConfigError::ValidationError(
+                "alerts.api.token must not be empty".to_string(),
+            ));
+        }
+
+        if !(1..=5).contains(&alerts.urgent_priority) {
+            return Err(ConfigError::ValidationError(
+                "alerts.urgent_priority must be between 1 and 5".to_string(),
+            ));
+        }
+
+        if let Some(quiet_hours) = &alerts.quiet_hours {
+            let start = schedule::parse_time_of_day(&quiet_hours.start);
+            let end = schedule::parse_time_of_day(&quiet_hours.end);
+            if start.is_none() || end.is_none() {
+                return Err(ConfigError::ValidationError(format!(
+                    "alerts.quiet_hours start and end must be HH:MM, got {:?} and {:?}",
+                    quiet_hours.start, quiet_hours.end
+                )));
+            }
+            if start == end {
+                return Err(ConfigError::ValidationError(
+                    "alerts.quiet_hours start and end must differ".to_string(),
+                ));
+            }
+        }
+
+        if let Some(digest_at) = &alerts.digest_at {
+            if schedule::parse_time_of_day(digest_at).is_none() {
+                return Err(ConfigError::ValidationError(format!(
+                    "alerts.digest_at must be HH:MM, got {:?}",
+                    digest_at
+                )));
+            }
+        }
+
+        if alerts.max_per_hour == Some(0) {
+            return Err(ConfigError::ValidationError(
+                "alerts.max_per_hour must be greater than 0".to_string(),
+            ));
+        }
+
+        let notifiers = alerts.all_notifiers();
+        for (i, notifier) in notifiers.iter().enumerate() {
+            if notifier.name.is_empty() {
+                return Err(ConfigError::ValidationError(
+                    "alerts.notifiers: name must not be empty".to_string(),
+                ));
+            }
+            if notifier.max_per_hour == Some(0) {
+                return Err(ConfigError::ValidationError(format!(
+                    "alerts.notifiers.{}: max_per_hour must be greater than 0",
+                    notifier.name
+                )));
+            }
+            if notifiers[..i].iter().any(|n| n.name == notifier.name) {
+                return Err(ConfigError::ValidationError(format!(
+                    "alerts.notifiers: \"{}\" is defined more than once",
+                    notifier.name
+                )));
+            }
+            if let NotifierBackend::Smtp(smtp) = &notifier.backend {
+                if smtp.to.is_empty() {
+                    return Err(ConfigError::ValidationError(format!(
+                        "alerts.notifiers.{}: to must not be empty",
+                        notifier.name
+                    )));
+                }
+            }
+        }
+
+        for route in &alerts.routes {
+            if !(1..=5).contains(&route.min_priority) {
+                return Err(ConfigError::ValidationError(
+                    "alerts.routes: min_priority must be between 1 and 5".to_string(),
+                ));
+            }
+            if route.notifiers.is_empty() {
+                return Err(ConfigError::ValidationError(
+                    "alerts.routes: notifiers must not be empty".to_string(),
+                ));
+            }
+            if let Some(name) = route
+                .notifiers
+                .iter()
+                .find(|name| !notifiers.iter().any(|n| &n.name == *name))
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "alerts.routes: unknown notifier \"{name}\""
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_checks(checks: &[CheckConfig]) -> Result<(), ConfigError> {
+        for (i, check) in checks.iter().enumerate() {
+            if !checks::check_names().any(|name| name == check.name) {
+                return Err(ConfigError::ValidationError(format!(
+                    "media.scanner.checks: unknown check \"{}\", expected one of {}",
+                    check.name,
+                    checks::check_names().collect::<Vec<_>>().join(", ")
+                )));
+            }
+
+            if checks[..i].iter().any(|c| c.name == check.name) {
+                return Err(ConfigError::ValidationError(format!(
+                    "media.scanner.checks: \"{}\" is listed more than once",
+                    check.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_commands(commands: &[CommandConfig]) -> Result<(), ConfigError> {
+        for (i, command) in commands.iter().enumerate() {
+            if command
+                .command
+                .first()
+                .is_none_or(|program| program.is_empty())
+            {
+                return Err(ConfigError::ValidationError(format!(
+                    "media.scanner.commands: \"{}\" needs a program to run",
+                    command.name
+                )));
+            }
+
+            if command.timeout_secs == 0 {
+                return Err(ConfigError::ValidationError(format!(
+                    "media.scanner.commands: \"{}\" timeout_secs must be greater than 0",
+                    command.name
+                )));
+            }
+
+            if commands[..i].iter().any(|c| c.name == command.name) {
+                return Err(ConfigError::ValidationError(format!(
+                    "media.scanner.commands: \"{}\" is listed more than once",
+                    command.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_size_rules(rules: &[SizeRule]) -> Result<(), ConfigError> {
+        for rule in rules {
+            if rule.extensions.is_empty() == rule.class.is_none() {
+                return Err(ConfigError::ValidationError(
+                    "media.scanner.size_rules entries need exactly one of extensions or class"
+                        .to_string(),
+                ));
+            }
+
+            if let (Some(min), Some(max)) = (rule.min_bytes, rule.max_bytes) {
+                if min > max {
+                    return Err(ConfigError::ValidationError(format!(
+                        "media.scanner.size_rules min_bytes ({min}) is larger than max_bytes ({max})"
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_overrides(
+        overrides: &[PathOverride],
+        watcher: &WatcherConfig,
+        name: &str,
+    ) -> Result<(), ConfigError> {
+        for o in overrides {
+            if !watcher.paths.iter().any(|p| o.path.starts_with(p)) {
+                return Err(ConfigError::ValidationError(format!(
+                    "{}.overrides path {} is not under any watched path",
+                    name,
+                    o.path.display()
+                )));
+            }
+
+            if let Some(debounce) = o.debounce_ms {
+                if !(100..=60_000).contains(&debounce) {
+                    return Err(ConfigError::ValidationError(format!(
+                        "{}.overrides debounce_ms must be between 100 and 60000, got {}",
+                        name, debounce
+                    )));
+                }
+            }
+
+            let misplaced = match name {
+                "photos" => o.allowed_extensions.is_some(),
+                _ => o.photo_prefix.is_some() || o.video_prefix.is_some(),
+            };
+            if misplaced {
+                return Err(ConfigError::ValidationError(format!(
+                    "{}.overrides for {} sets a field that does not apply to this pipeline",
+                    name,
+                    o.path.display()
+                )));
+            }
+        }
 
         Ok(())
     }
@@ -120,6 +957,37 @@ impl Config {
             )));
         }
 
+        if watcher.startup_scan_rate == 0 {
+            return Err(ConfigError::ValidationError(format!(
+                "{}.watcher.startup_scan_rate must be greater than 0",
+                name
+            )));
+        }
+
+        if let Some(quiet) = watcher.batch_quiet_ms {
+            if quiet < watcher.debounce_ms || quiet > 600_000 {
+                return Err(ConfigError::ValidationError(format!(
+                    "{}.watcher.batch_quiet_ms must be between debounce_ms and 600000, got {}",
+                    name, quiet
+                )));
+            }
+        }
+
+        if let Some(reconcile_at) = &watcher.reconcile_at {
+            if schedule::parse_time_of_day(reconcile_at).is_none() {
+                return Err(ConfigError::ValidationError(format!(
+                    "{}.watcher.reconcile_at must be HH:MM, got {:?}",
+                    name, reconcile_at
+                )));
+            }
+            if watcher.state_file.is_none() {
+                return Err(ConfigError::ValidationError(format!(
+                    "{}.watcher.reconcile_at requires state_file to be set",
+                    name
+                )));
+            }
+        }
+
         Ok(())
     }
 }
@@ -135,6 +1003,11 @@ mod tests {
                     paths: vec![PathBuf::from("/tmp/photos")],
                     debounce_ms: 5000,
                     ignore_extensions: vec![],
+                    state_file: None,
+                    startup_scan_rate: 50,
+                    reconcile_at: None,
+                    batch_quiet_ms: None,
+                    backend: WatchBackend::Inotify,
                 },
                 organizer: OrganizerConfig {
                     enabled: false,
@@ -155,26 +1028,54 @@ mod tests {
                     data_dir: Default::default(),
                     internal_prefix: "/admin/files".to_string(),
                 },
+                overrides: vec![],
             },
             media: MediaConfig {
                 watcher: WatcherConfig {
                     paths: vec![PathBuf::from("/tmp/media")],
                     debounce_ms: 5000,
                     ignore_extensions: vec![],
+                    state_file: None,
+                    startup_scan_rate: 50,
+                    reconcile_at: None,
+                    batch_quiet_ms: None,
+                    backend: WatchBackend::Inotify,
                 },
                 scanner: ScannerConfig {
                     quarantine_dir: Default::default(),
+                    quarantine_expiry_days: None,
                     allowed_extensions: vec![],
                     block_executables: false,
+                    strip_exec_bits: false,
                     delete_junk: false,
                     junk_extensions: vec![],
+                    clamav: None,
+                    yara: None,
+                    archives: None,
+                    hashes: None,
+                    commands: Vec::new(),
+                    size_rules: vec![],
+                    subtitles: SubtitleConfig::default(),
+                    release: ReleaseConfig::default(),
+                    arr: vec![],
+                    checks: vec![],
                 },
+                overrides: vec![],
             },
             alerts: AlertsConfig {
                 enabled: false,
                 url: "https://ntfy.example.com".to_string(),
                 topic: "test".to_string(),
                 token: "test-token".to_string(),
+                templates: HashMap::new(),
+                api: None,
+                notifiers: vec![],
+                routes: vec![],
+                queue_file: None,
+                urgent_priority: 5,
+                quiet_hours: None,
+                digest_at: None,
+                max_per_hour: None,
             },
         }
     }
@@ -204,4 +1105,260 @@ mod tests {
         config.media.watcher.debounce_ms = 50;
         assert!(config.validate().is_err());
     }
+
+    #[test]
+    fn test_reconcile_without_state_file_fails() {
+        let mut config = test_config();
+        config.media.watcher.reconcile_at = Some("03:00".to_string());
+        assert!(config.validate().is_err());
+
+        config.media.watcher.state_file = Some(PathBuf::from("/var/lib/homed/media.journal"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_bad_reconcile_time_fails() {
+        let mut config = test_config();
+        config.media.watcher.state_file = Some(PathBuf::from("/var/lib/homed/media.journal"));
+        config.media.watcher.reconcile_at = Some("3am".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_photos_batching_fails() {
+        let mut config = test_config();
+        config.photos.watcher.batch_quiet_ms = Some(30_000);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_batch_quiet_shorter_than_debounce_fails() {
+        let mut config = test_config();
+        config.media.watcher.batch_quiet_ms = Some(1000);
+        assert!(config.validate().is_err());
+
+        config.media.watcher.batch_quiet_ms = Some(30_000);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_most_specific_override_wins() {
+        let overrides = vec![
+            PathOverride {
+                path: PathBuf::from("/media"),
+                debounce_ms: Some(1000),
+                ..Default::default()
+            },
+            PathOverride {
+                path: PathBuf::from("/media/TV"),
+                debounce_ms: Some(2000),
+                ..Default::default()
+            },
+        ];
+
+        let tv = PathOverride::for_path(&overrides, Path::new("/media/TV/Show/e01.mkv"));
+        assert_eq!(tv.and_then(|o| o.debounce_ms), Some(2000));
+        let movie = PathOverride::for_path(&overrides, Path::new("/media/Movies/film.mkv"));
+        assert_eq!(movie.and_then(|o| o.debounce_ms), Some(1000));
+        assert!(PathOverride::for_path(&overrides, Path::new("/other/file.mkv")).is_none());
+    }
+
+    #[test]
+    fn test_override_outside_watched_paths_fails() {
+        let mut config = test_config();
+        config.media.overrides = vec![PathOverride {
+            path: PathBuf::from("/elsewhere"),
+            ..Default::default()
+        }];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_misplaced_override_field_fails() {
+        let mut config = test_config();
+        config.media.overrides = vec![PathOverride {
+            path: PathBuf::from("/tmp/media/TV"),
+            photo_prefix: Some("IMG".to_string()),
+            ..Default::default()
+        }];
+        assert!(config.validate().is_err());
+
+        config.media.overrides[0].photo_prefix = None;
+        config.media.overrides[0].allowed_extensions = Some(vec!["mkv".to_string()]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_extension_size_rule_beats_class() {
+        let rules = vec![
+            SizeRule {
+                class: Some(MediaClass::Video),
+                min_bytes: Some(1024),
+                ..Default::default()
+            },
+            SizeRule {
+                extensions: vec!["MP4".to_string()],
+                min_bytes: Some(1),
+                ..Default::default()
+            },
+        ];
+
+        assert_eq!(
+            SizeRule::for_extension(&rules, "mp4").unwrap().min_bytes,
+            Some(1)
+        );
+        assert_eq!(
+            SizeRule::for_extension(&rules, "mkv").unwrap().min_bytes,
+            Some(1024)
+        );
+        assert!(SizeRule::for_extension(&rules, "srt").is_none());
+    }
+
+    #[test]
+    fn test_bad_size_rules_fail() {
+        let mut config = test_config();
+        config.media.scanner.size_rules = vec![SizeRule {
+            min_bytes: Some(1),
+            ..Default::default()
+        }];
+        assert!(config.validate().is_err());
+
+        config.media.scanner.size_rules[0].class = Some(MediaClass::Audio);
+        config.media.scanner.size_rules[0].max_bytes = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_entries_parse_and_validate() {
+        let check: CheckConfig = toml::from_str(
+            r#"
+            name = "clamav"
+            action = "warn"
+            socket = "/run/clamav/clamd.ctl"
+            "#,
+        )
+        .unwrap();
+        assert!(check.enabled);
+        assert_eq!(check.action, Some(CheckAction::Warn));
+        assert_eq!(
+            check.params.get("socket").and_then(|v| v.as_str()),
+            Some("/run/clamav/clamd.ctl")
+        );
+
+        let mut config = test_config();
+        config.media.scanner.checks = vec![check.clone()];
+        assert!(config.validate().is_ok());
+
+        config.media.scanner.checks.push(check.clone());
+        assert!(config.validate().is_err());
+
+        config.media.scanner.checks = vec![CheckConfig {
+            name: "antivirus".to_string(),
+            ..check
+        }];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_command_entries_validate() {
+        let command: CommandConfig = toml::from_str(
+            r#"
+            name = "mediainfo"
+            command = ["/opt/homed/hooks/mediainfo-check"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(command.timeout_secs, 60);
+        assert!(command.sandbox);
+
+        let mut config = test_config();
+        config.media.scanner.commands = vec![command.clone()];
+        assert!(config.validate().is_ok());
+
+        config.media.scanner.commands.push(command.clone());
+        assert!(config.validate().is_err());
+
+        config.media.scanner.commands = vec![CommandConfig {
+            command: Vec::new(),
+            ..command
+        }];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_notifiers_and_routes_validate() {
+        let alerts: AlertsConfig = toml::from_str(
+            r#"
+            enabled = true
+            url = "https://ntfy.example.com"
+            topic = "homed"
+
+            [[notifiers]]
+            name = "mail"
+            type = "smtp"
+            from = "homed@example.com"
+            to = ["admin@example.com"]
+
+            [[routes]]
+            kinds = ["malware"]
+            notifiers = ["ntfy", "mail"]
+            "#,
+        )
+        .unwrap();
+        let names: Vec<String> = alerts.all_notifiers().into_iter().map(|n| n.name).collect();
+        assert_eq!(names, ["ntfy", "mail"]);
+        match &alerts.notifiers[0].backend {
+            NotifierBackend::Smtp(smtp) => {
+                assert_eq!((smtp.host.as_str(), smtp.port), ("localhost", 25))
+            }
+            other => panic!("expected smtp, got {other:?}"),
+        }
+
+        let mut config = test_config();
+        config.alerts = alerts.clone();
+        assert!(config.validate().is_ok());
+
+        config.alerts.routes[0].notifiers.push("matrix".to_string());
+        assert!(config.validate().is_err());
+
+        config.alerts = alerts.clone();
+        config.alerts.notifiers[0].name = "ntfy".to_string();
+        assert!(config.validate().is_err());
+
+        config.alerts = alerts;
+        config.alerts.routes[0].min_priority = 6;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_quiet_hours_and_digest_validate() {
+        let mut config = test_config();
+        config.alerts.quiet_hours = Some(QuietHoursConfig {
+            start: "23:00".to_string(),
+            end: "07:00".to_string(),
+        });
+        config.alerts.digest_at = Some("08:00".to_string());
+        assert!(config.validate().is_ok());
+
+        config.alerts.quiet_hours = Some(QuietHoursConfig {
+            start: "23:00".to_string(),
+            end: "23:00".to_string(),
+        });
+        assert!(config.validate().is_err());
+
+        config.alerts.quiet_hours = None;
+        config.alerts.digest_at = Some("8am".to_string());
+        assert!(config.validate().is_err());
+
+        config.alerts.digest_at = None;
+        config.alerts.urgent_priority = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_zero_startup_scan_rate_fails() {
+        let mut config = test_config();
+        config.media.watcher.startup_scan_rate = 0;
+        assert!(config.validate().is_err());
+    }
 }
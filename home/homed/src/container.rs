@@ -0,0 +1,571 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::checks::ScanRejection;
+
+const EBML_HEADER: u32 = 0x1a45_dfa3;
+const EBML_DOC_TYPE: u32 = 0x4282;
+const MKV_SEGMENT: u32 = 0x1853_8067;
+const MKV_INFO: u32 = 0x1549_a966;
+const MKV_TIMESTAMP_SCALE: u32 = 0x2a_d7b1;
+const MKV_DURATION: u32 = 0x4489;
+const MKV_TRACKS: u32 = 0x1654_ae6b;
+const MKV_TRACK_ENTRY: u32 = 0xae;
+const MKV_CLUSTER: u32 = 0x1f43_b675;
+
+/// Anything longer than two days is a broken header, not a movie.
+const MAX_DURATION_SECS: f64 = 48.0 * 3600.0;
+
+/// Metadata elements/boxes are read into memory, cap them.
+const MAX_METADATA_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Walks the Matroska element tree or ISO-BMFF box tree of a video file and
+/// checks that it has tracks, a plausible duration and isn't cut short.
+/// Returns the declared duration in seconds when there is one. Other
+/// extensions pass. Blocking.
+pub fn check_container(path: &Path) -> Result<Option<f64>, ScanRejection> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    let mut file = File::open(path)?;
+    let result = match extension.as_str() {
+        "mkv" | "webm" => check_matroska(&mut file),
+        "mp4" | "m4v" | "m4a" | "mov" => check_isobmff(&mut file),
+        _ => return Ok(None),
+    };
+
+    result.map_err(|e| match e {
+        ContainerFault::Corrupt(details) => ScanRejection::CorruptContainer(details),
+        ContainerFault::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            ScanRejection::CorruptContainer("unexpected end of file".to_string())
+        }
+        ContainerFault::Io(e) => ScanRejection::IoError(e),
+    })
+}
+
+enum ContainerFault {
+    Corrupt(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for ContainerFault {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn corrupt<T>(details: impl Into<String>) -> Result<T, ContainerFault> {
+    Err(ContainerFault::Corrupt(details.into()))
+}
+
+fn check_duration(seconds: f64) -> Result<(), ContainerFault> {
+    if !seconds.is_finite() || seconds <= 0.0 || seconds > MAX_DURATION_SECS {
+        return corrupt(format!("implausible duration: {seconds:.1}s"));
+    }
+    Ok(())
+}
+
+// Matroska
+
+struct ElementHeader {
+    id: u32,
+    /// `None` for the "unknown size" marker used by live streams.
+    size: Option<u64>,
+}
+
+/// Element ID: a vint that keeps its length marker, at most 4 bytes.
+fn read_element_id(reader: &mut impl Read) -> std::io::Result<Option<u32>> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 4 {
+        return Ok(None);
+    }
+
+    let mut id = first[0] as u32;
+    for _ in 1..len {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        id = (id << 8) | byte[0] as u32;
+    }
+    Ok(Some(id))
+}
+
+/// Element data size: a vint without its marker, all ones means unknown.
+fn read_element_size(reader: &mut impl Read) -> std::io::Result<Option<Option<u64>>> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        return Ok(None);
+    }
+
+    let mut value = (first[0] as u64) & (0xff >> len);
+    let mut all_ones = value == (0xff >> len);
+    for _ in 1..len {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value = (value << 8) | byte[0] as u64;
+        all_ones &= byte[0] == 0xff;
+    }
+    Ok(Some(if all_ones { None } else { Some(value) }))
+}
+
+fn read_element_header(reader: &mut impl Read) -> Result<ElementHeader, ContainerFault> {
+    let Some(id) = read_element_id(reader)? else {
+        return corrupt("invalid EBML element ID");
+    };
+    let Some(size) = read_element_size(reader)? else {
+        return corrupt(format!("invalid size for EBML element {id:#x}"));
+    };
+    Ok(ElementHeader { id, size })
+}
+
+fn read_element_body(
+    reader: &mut impl Read,
+    header: &ElementHeader,
+) -> Result<Vec<u8>, ContainerFault> {
+    match header.size {
+        Some(size) if size <= MAX_METADATA_SIZE => {
+            let mut body = vec![0u8; size as usize];
+            reader.read_exact(&mut body)?;
+            Ok(body)
+        }
+        _ => corrupt(format!("oversized EBML element {:#x}", header.id)),
+    }
+}
+
+/// Iterates the direct children of an in-memory master element.
+fn ebml_children(mut body: &[u8]) -> Result<Vec<(u32, &[u8])>, ContainerFault> {
+    let mut children = Vec::new();
+    while !body.is_empty() {
+        let header = read_element_header(&mut body)?;
+        let Some(size) = header.size.filter(|&s| s <= body.len() as u64) else {
+            return corrupt(format!("EBML element {:#x} overruns its parent", header.id));
+        };
+        let (data, rest) = body.split_at(size as usize);
+        children.push((header.id, data));
+        body = rest;
+    }
+    Ok(children)
+}
+
+fn ebml_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+fn ebml_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes(data.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn check_matroska<R: Read + Seek>(reader: &mut R) -> Result<Option<f64>, ContainerFault> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let header = read_element_header(reader)?;
+    if header.id != EBML_HEADER {
+        return corrupt("missing EBML header");
+    }
+    let body = read_element_body(reader, &header)?;
+    let doc_type = ebml_children(&body)?
+        .into_iter()
+        .find(|(id, _)| *id == EBML_DOC_TYPE)
+        .map(|(_, data)| {
+            String::from_utf8_lossy(data)
+                .trim_end_matches('\0')
+                .to_string()
+        });
+    if !matches!(doc_type.as_deref(), Some("matroska" | "webm")) {
+        return corrupt(format!("unexpected DocType {doc_type:?}"));
+    }
+
+    let segment = read_element_header(reader)?;
+    if segment.id != MKV_SEGMENT {
+        return corrupt("missing Segment element");
+    }
+    let segment_start = reader.stream_position()?;
+    let segment_end = match segment.size {
+        Some(size) if segment_start + size > file_len => {
+            return corrupt(format!(
+                "truncated: Segment declares {} bytes, file has {}",
+                size,
+                file_len - segment_start
+            ));
+        }
+        Some(size) => segment_start + size,
+        None => file_len,
+    };
+
+    let mut tracks = None;
+    let mut duration = None;
+    let mut timestamp_scale = 1_000_000;
+    let mut has_cluster = false;
+
+    while reader.stream_position()? < segment_end {
+        let offset = reader.stream_position()?;
+        let element = read_element_header(reader)?;
+        let data_start = reader.stream_position()?;
+
+        match element.id {
+            MKV_INFO => {
+                for (id, data) in ebml_children(&read_element_body(reader, &element)?)? {
+                    match id {
+                        MKV_TIMESTAMP_SCALE => timestamp_scale = ebml_uint(data),
+                        MKV_DURATION => duration = ebml_float(data),
+                        _ => {}
+                    }
+                }
+            }
+            MKV_TRACKS => {
+                let entries = ebml_children(&read_element_body(reader, &element)?)?
+                    .iter()
+                    .filter(|(id, _)| *id == MKV_TRACK_ENTRY)
+                    .count();
+                tracks = Some(entries);
+            }
+            MKV_CLUSTER => has_cluster = true,
+            _ => {}
+        }
+
+        match element.size {
+            Some(size) if data_start + size > segment_end => {
+                return corrupt(format!(
+                    "truncated: element {:#x} at offset {offset} runs past end of file",
+                    element.id
+                ));
+            }
+            Some(size) => {
+                reader.seek(SeekFrom::Start(data_start + size))?;
+            }
+            // Unknown-sized clusters can only be walked by parsing blocks,
+            // everything we need comes before them
+            None => break,
+        }
+    }
+
+    match tracks {
+        None => return corrupt("no Tracks element"),
+        Some(0) => return corrupt("no tracks"),
+        Some(_) => {}
+    }
+    if !has_cluster {
+        return corrupt("no media clusters");
+    }
+    let duration = duration.map(|d| d * timestamp_scale as f64 / 1e9);
+    if let Some(duration) = duration {
+        check_duration(duration)?;
+    }
+
+    Ok(duration)
+}
+
+// ISO base media file format (MP4/MOV)
+
+struct BoxHeader {
+    kind: [u8; 4],
+    /// Size including the header, `None` when the box runs to end of file.
+    size: Option<u64>,
+    header_len: u64,
+}
+
+fn read_box_header(reader: &mut impl Read) -> std::io::Result<BoxHeader> {
+    let mut fixed = [0u8; 8];
+    reader.read_exact(&mut fixed)?;
+    let size = u32::from_be_bytes(fixed[..4].try_into().unwrap()) as u64;
+    let kind = fixed[4..].try_into().unwrap();
+
+    Ok(match size {
+        0 => BoxHeader {
+            kind,
+            size: None,
+            header_len: 8,
+        },
+        1 => {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            BoxHeader {
+                kind,
+                size: Some(u64::from_be_bytes(large)),
+                header_len: 16,
+            }
+        }
+        size => BoxHeader {
+            kind,
+            size: Some(size),
+            header_len: 8,
+        },
+    })
+}
+
+fn box_name(kind: &[u8; 4]) -> String {
+    String::from_utf8_lossy(kind).into_owned()
+}
+
+/// Box type and body.
+type BoxChild<'a> = ([u8; 4], &'a [u8]);
+
+/// Iterates the direct children of an in-memory box body.
+fn isobmff_children(mut body: &[u8]) -> Result<Vec<BoxChild<'_>>, ContainerFault> {
+    let mut children = Vec::new();
+    while body.len() >= 8 {
+        let header = read_box_header(&mut &body[..])?;
+        let size = header.size.unwrap_or(body.len() as u64);
+        if size < header.header_len || size > body.len() as u64 {
+            return corrupt(format!(
+                "box '{}' overruns its parent",
+                box_name(&header.kind)
+            ));
+        }
+        children.push((
+            header.kind,
+            &body[header.header_len as usize..size as usize],
+        ));
+        body = &body[size as usize..];
+    }
+    Ok(children)
+}
+
+/// Movie duration in seconds from an `mvhd` box body.
+fn mvhd_duration(body: &[u8]) -> Option<f64> {
+    let (timescale, duration) = match *body.first()? {
+        0 => (
+            u32::from_be_bytes(body.get(12..16)?.try_into().ok()?),
+            u32::from_be_bytes(body.get(16..20)?.try_into().ok()?) as u64,
+        ),
+        1 => (
+            u32::from_be_bytes(body.get(20..24)?.try_into().ok()?),
+            u64::from_be_bytes(body.get(24..32)?.try_into().ok()?),
+        ),
+        _ => return None,
+    };
+    if timescale == 0 {
+        return None;
+    }
+    Some(duration as f64 / timescale as f64)
+}
+
+fn check_isobmff<R: Read + Seek>(reader: &mut R) -> Result<Option<f64>, ContainerFault> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut moov = None;
+    let mut has_mdat = false;
+
+    let mut offset = 0;
+    while offset < file_len {
+        let header = read_box_header(reader)?;
+        let size = header.size.unwrap_or(file_len - offset);
+        if size < header.header_len {
+            return corrupt(format!("invalid size for box '{}'", box_name(&header.kind)));
+        }
+        // A largesize near u64::MAX must not wrap the offset back to 0
+        let Some(end) = offset.checked_add(size).filter(|&end| end <= file_len) else {
+            return corrupt(format!(
+                "truncated: box '{}' declares {} bytes, {} left in file",
+                box_name(&header.kind),
+                size,
+                file_len - offset
+            ));
+        };
+
+        match &header.kind {
+            b"mdat" => has_mdat = true,
+            b"moov" => {
+                let body_len = size - header.header_len;
+                if body_len > MAX_METADATA_SIZE {
+                    return corrupt("oversized moov box");
+                }
+                let mut body = vec![0u8; body_len as usize];
+                reader.read_exact(&mut body)?;
+                moov = Some(body);
+            }
+            _ => {}
+        }
+
+        offset = end;
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+
+    let Some(moov) = moov else {
+        return corrupt("missing moov box");
+    };
+    if !has_mdat {
+        return corrupt("missing mdat box");
+    }
+
+    let children = isobmff_children(&moov)?;
+    let tracks = children.iter().filter(|(kind, _)| kind == b"trak").count();
+    if tracks == 0 {
+        return corrupt("no tracks");
+    }
+
+    // Fragmented files keep their duration in the fragments
+    let fragmented = children.iter().any(|(kind, _)| kind == b"mvex");
+    match children.iter().find(|(kind, _)| kind == b"mvhd") {
+        Some((_, body)) => match mvhd_duration(body) {
+            Some(duration) if fragmented && duration == 0.0 => Ok(None),
+            Some(duration) => {
+                check_duration(duration)?;
+                Ok(Some(duration))
+            }
+            None => corrupt("unreadable mvhd box"),
+        },
+        None => corrupt("missing mvhd box"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ebml(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut element = id.to_vec();
+        element.push(0x01); // 8-byte size
+        element.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        element.extend_from_slice(body);
+        element
+    }
+
+    fn matroska(duration_ms: f64, tracks: usize, truncate: usize) -> Vec<u8> {
+        let header = ebml(&[0x1a, 0x45, 0xdf, 0xa3], &ebml(&[0x42, 0x82], b"matroska"));
+
+        let mut info = ebml(&[0x2a, 0xd7, 0xb1], &[0x0f, 0x42, 0x40]);
+        info.extend(ebml(&[0x44, 0x89], &duration_ms.to_be_bytes()));
+        let mut segment = ebml(&[0x15, 0x49, 0xa9, 0x66], &info);
+
+        let entries: Vec<u8> = (0..tracks)
+            .flat_map(|_| ebml(&[0xae], &ebml(&[0xd7], &[1])))
+            .collect();
+        segment.extend(ebml(&[0x16, 0x54, 0xae, 0x6b], &entries));
+        segment.extend(ebml(&[0x1f, 0x43, 0xb6, 0x75], &[0u8; 256]));
+
+        let mut file = header;
+        file.extend(ebml(&[0x18, 0x53, 0x80, 0x67], &segment));
+        file.truncate(file.len() - truncate);
+        file
+    }
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut b = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(body);
+        b
+    }
+
+    fn mp4(duration_secs: u32, tracks: usize, truncate: usize) -> Vec<u8> {
+        let mut mvhd = vec![0u8; 12];
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&(duration_secs * 1000).to_be_bytes());
+        mvhd.extend_from_slice(&[0u8; 80]);
+
+        let mut moov = mp4_box(b"mvhd", &mvhd);
+        for _ in 0..tracks {
+            moov.extend(mp4_box(b"trak", &mp4_box(b"tkhd", &[0u8; 84])));
+        }
+
+        let mut file = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2mp41");
+        file.extend(mp4_box(b"moov", &moov));
+        file.extend(mp4_box(b"mdat", &[0u8; 4096]));
+        file.truncate(file.len() - truncate);
+        file
+    }
+
+    fn check(name: &str, contents: &[u8]) -> Result<Option<f64>, ScanRejection> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        check_container(&path)
+    }
+
+    #[test]
+    fn test_valid_matroska_passes() {
+        let duration = check("movie.mkv", &matroska(5_400_000.0, 2, 0)).unwrap();
+        assert_eq!(duration, Some(5400.0));
+    }
+
+    #[test]
+    fn test_truncated_matroska_rejected() {
+        let result = check("movie.mkv", &matroska(5_400_000.0, 2, 100));
+        assert!(matches!(
+            result,
+            Err(ScanRejection::CorruptContainer(details)) if details.starts_with("truncated")
+        ));
+    }
+
+    #[test]
+    fn test_matroska_without_tracks_rejected() {
+        let result = check("movie.mkv", &matroska(5_400_000.0, 0, 0));
+        assert!(matches!(result, Err(ScanRejection::CorruptContainer(_))));
+    }
+
+    #[test]
+    fn test_matroska_implausible_duration_rejected() {
+        let result = check("movie.mkv", &matroska(-1.0, 1, 0));
+        assert!(matches!(result, Err(ScanRejection::CorruptContainer(_))));
+    }
+
+    #[test]
+    fn test_ebml_header_followed_by_junk_rejected() {
+        let mut file = ebml(&[0x1a, 0x45, 0xdf, 0xa3], &ebml(&[0x42, 0x82], b"matroska"));
+        file.extend_from_slice(&[0x42; 4096]);
+        assert!(matches!(
+            check("movie.mkv", &file),
+            Err(ScanRejection::CorruptContainer(_))
+        ));
+    }
+
+    #[test]
+    fn test_valid_mp4_passes() {
+        assert_eq!(check("movie.mp4", &mp4(5400, 2, 0)).unwrap(), Some(5400.0));
+    }
+
+    #[test]
+    fn test_truncated_mp4_rejected() {
+        let result = check("movie.mp4", &mp4(5400, 2, 1000));
+        assert!(matches!(
+            result,
+            Err(ScanRejection::CorruptContainer(details)) if details.starts_with("truncated")
+        ));
+    }
+
+    #[test]
+    fn test_mp4_huge_largesize_rejected() {
+        let mut file = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2mp41");
+        let offset = file.len() as u64;
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(b"free");
+        file.extend_from_slice(&(u64::MAX - offset + 1).to_be_bytes());
+        file.extend_from_slice(&[0u8; 64]);
+
+        let result = check("movie.mp4", &file);
+        assert!(matches!(
+            result,
+            Err(ScanRejection::CorruptContainer(details)) if details.starts_with("truncated")
+        ));
+    }
+
+    #[test]
+    fn test_mp4_without_tracks_rejected() {
+        let result = check("movie.mp4", &mp4(5400, 0, 0));
+        assert!(matches!(result, Err(ScanRejection::CorruptContainer(_))));
+    }
+
+    #[test]
+    fn test_mp4_zero_duration_rejected() {
+        let result = check("movie.mp4", &mp4(0, 1, 0));
+        assert!(matches!(result, Err(ScanRejection::CorruptContainer(_))));
+    }
+
+    #[test]
+    fn test_other_extensions_skipped() {
+        assert_eq!(check("song.flac", b"fLaC").unwrap(), None);
+    }
+}
@@ -0,0 +1,335 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+use tracing::debug;
+
+use crate::checks::{self, ScanRejection};
+
+const EPUB_MIMETYPE: &[u8] = b"application/epub+zip";
+const EPUB_SCRIPT_EXTS: &[&str] = &["js", "mjs"];
+const EPUB_MARKUP_EXTS: &[&str] = &["html", "xhtml", "htm", "svg", "xml", "opf", "ncx"];
+
+/// Markup members bigger than this are only checked up to the limit.
+const MAX_MARKUP_READ: u64 = 4 * 1024 * 1024;
+
+/// PDFs are scanned in memory, bigger ones only get the header check.
+const MAX_PDF_SCAN: u64 = 256 * 1024 * 1024;
+/// Cap on inflated data per stream, against compression bombs.
+const MAX_INFLATED_STREAM: u64 = 16 * 1024 * 1024;
+
+/// EPUB and PDF structure and active content checks. Other extensions
+/// pass. Blocking.
+pub fn check_document(path: &Path, size: u64) -> Result<(), ScanRejection> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "epub" => check_epub(path),
+        "pdf" => check_pdf(path, size),
+        _ => Ok(()),
+    }
+}
+
+fn check_epub(path: &Path) -> Result<(), ScanRejection> {
+    let invalid = |details: &str| ScanRejection::InvalidEpub(details.to_string());
+    let mut archive = zip::ZipArchive::new(File::open(path)?)
+        .map_err(|e| ScanRejection::InvalidEpub(e.to_string()))?;
+
+    // The OCF spec requires an uncompressed `mimetype` as the first entry
+    {
+        let mut first = archive
+            .by_index(0)
+            .map_err(|_| invalid("mimetype must be the first entry"))?;
+        if first.name() != "mimetype" || first.header_start() != 0 {
+            return Err(invalid("mimetype must be the first entry"));
+        }
+        if first.compression() != zip::CompressionMethod::Stored {
+            return Err(invalid("mimetype entry is compressed"));
+        }
+        let mut mimetype = Vec::new();
+        first.by_ref().take(64).read_to_end(&mut mimetype)?;
+        if mimetype != EPUB_MIMETYPE {
+            return Err(invalid("wrong mimetype"));
+        }
+    }
+
+    if archive.index_for_name("META-INF/container.xml").is_none() {
+        return Err(invalid("missing META-INF/container.xml"));
+    }
+
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| ScanRejection::InvalidEpub(e.to_string()))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let ext = Path::new(&name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        if checks::check_executable_extension(Path::new(&name)).is_err()
+            || EPUB_SCRIPT_EXTS.contains(&ext.as_str())
+        {
+            return Err(ScanRejection::EpubScript { member: name });
+        }
+
+        if EPUB_MARKUP_EXTS.contains(&ext.as_str()) {
+            let mut markup = Vec::new();
+            entry
+                .by_ref()
+                .take(MAX_MARKUP_READ)
+                .read_to_end(&mut markup)?;
+            if contains_ignore_case(&markup, b"<script") {
+                return Err(ScanRejection::EpubScript { member: name });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn contains_ignore_case(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|w| w.eq_ignore_ascii_case(needle))
+}
+
+fn check_pdf(path: &Path, size: u64) -> Result<(), ScanRejection> {
+    let invalid = |details: &str| ScanRejection::InvalidPdf(details.to_string());
+
+    let mut head = Vec::new();
+    File::open(path)?.take(1024).read_to_end(&mut head)?;
+    if !head.windows(5).any(|w| w == b"%PDF-") {
+        return Err(invalid("missing %PDF header"));
+    }
+
+    if size > MAX_PDF_SCAN {
+        debug!(path = %path.display(), size, "PDF too large for active content scan, skipping");
+        return Ok(());
+    }
+
+    let contents = std::fs::read(path)?;
+    let tail = &contents[contents.len().saturating_sub(2048)..];
+    if !tail.windows(5).any(|w| w == b"%%EOF") {
+        return Err(invalid("missing %%EOF marker, file is truncated"));
+    }
+
+    // Stream bodies are binary, only the dictionaries around them are
+    // scanned. Object streams hide dictionaries in compressed bodies
+    for (text, stream) in pdf_segments(&contents) {
+        check_pdf_names(text)?;
+        let Some(body) = stream else {
+            continue;
+        };
+        let dict = &text[text.len().saturating_sub(1024)..];
+        if find(dict, b"/ObjStm").is_some() {
+            let mut inflated = Vec::new();
+            let _ = ZlibDecoder::new(body)
+                .take(MAX_INFLATED_STREAM)
+                .read_to_end(&mut inflated);
+            check_pdf_names(&inflated)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Looks for name objects that run code, launch programs or carry files.
+fn check_pdf_names(data: &[u8]) -> Result<(), ScanRejection> {
+    for name in pdf_names(data) {
+        match name.as_slice() {
+            b"JavaScript" | b"JS" => return Err(ScanRejection::PdfJavaScript),
+            b"Launch" => return Err(ScanRejection::PdfLaunchAction),
+            b"EmbeddedFile" | b"EmbeddedFiles" => return Err(ScanRejection::PdfEmbeddedFile),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Every `/Name` token with `#xx` escapes decoded, so `/J#61vaScript`
+/// can't slip through.
+fn pdf_names(data: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    const DELIMITERS: &[u8] = b"()<>[]{}/%";
+
+    data.iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'/')
+        .map(move |(start, _)| {
+            let raw = data[start + 1..]
+                .iter()
+                .take_while(|b| !b.is_ascii_whitespace() && !DELIMITERS.contains(b) && **b != 0)
+                .copied()
+                .collect::<Vec<u8>>();
+
+            let mut name = Vec::with_capacity(raw.len());
+            let mut i = 0;
+            while i < raw.len() {
+                let escaped = (raw[i] == b'#')
+                    .then(|| raw.get(i + 1..i + 3))
+                    .flatten()
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match escaped {
+                    Some(byte) => {
+                        name.push(byte);
+                        i += 3;
+                    }
+                    None => {
+                        name.push(raw[i]);
+                        i += 1;
+                    }
+                }
+            }
+            name
+        })
+}
+
+/// Splits a PDF into the text before each stream and the stream body
+/// between the `stream` and `endstream` keywords.
+fn pdf_segments(data: &[u8]) -> Vec<(&[u8], Option<&[u8]>)> {
+    let mut segments = Vec::new();
+    let mut rest = data;
+
+    while let Some(start) = find(rest, b"stream") {
+        let text = &rest[..start];
+        let mut body = &rest[start + b"stream".len()..];
+        body = body.strip_prefix(b"\r").unwrap_or(body);
+        body = body.strip_prefix(b"\n").unwrap_or(body);
+        let end = find(body, b"endstream").unwrap_or(body.len());
+        segments.push((text, Some(&body[..end])));
+        rest = body.get(end + b"endstream".len()..).unwrap_or_default();
+    }
+    segments.push((rest, None));
+
+    segments
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn write_epub(path: &Path, mimetype_first: bool, chapter: &[u8]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+        let stored =
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        if !mimetype_first {
+            writer.start_file("META-INF/container.xml", stored).unwrap();
+            writer.write_all(b"<container/>").unwrap();
+        }
+        writer.start_file("mimetype", stored).unwrap();
+        writer.write_all(EPUB_MIMETYPE).unwrap();
+        if mimetype_first {
+            writer.start_file("META-INF/container.xml", stored).unwrap();
+            writer.write_all(b"<container/>").unwrap();
+        }
+        writer.start_file("OEBPS/chapter1.xhtml", stored).unwrap();
+        writer.write_all(chapter).unwrap();
+        writer.finish().unwrap();
+    }
+
+    fn check(name: &str, write: impl FnOnce(&Path)) -> Result<(), ScanRejection> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        write(&path);
+        let size = std::fs::metadata(&path).unwrap().len();
+        check_document(&path, size)
+    }
+
+    #[test]
+    fn test_valid_epub_passes() {
+        let result = check("book.epub", |p| {
+            write_epub(p, true, b"<html><p>Once</p></html>")
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_epub_mimetype_not_first_rejected() {
+        let result = check("book.epub", |p| write_epub(p, false, b"<html></html>"));
+        assert!(matches!(result, Err(ScanRejection::InvalidEpub(_))));
+    }
+
+    #[test]
+    fn test_epub_script_rejected() {
+        let result = check("book.epub", |p| {
+            write_epub(p, true, b"<html><SCRIPT>fetch('x')</SCRIPT></html>")
+        });
+        assert!(matches!(
+            result,
+            Err(ScanRejection::EpubScript { member }) if member == "OEBPS/chapter1.xhtml"
+        ));
+    }
+
+    fn pdf(body: &[u8]) -> Vec<u8> {
+        let mut file = b"%PDF-1.7\n1 0 obj\n".to_vec();
+        file.extend_from_slice(body);
+        file.extend_from_slice(b"\nendobj\ntrailer\n<< /Root 1 0 R >>\n%%EOF\n");
+        file
+    }
+
+    #[test]
+    fn test_clean_pdf_passes() {
+        let result = check("book.pdf", |p| {
+            std::fs::write(p, pdf(b"<< /Type /Catalog /Pages 2 0 R >>")).unwrap()
+        });
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_pdf_active_content_rejected() {
+        let js = check("a.pdf", |p| {
+            std::fs::write(
+                p,
+                pdf(b"<< /OpenAction << /S /J#61vaScript /JS (app.alert(1)) >> >>"),
+            )
+            .unwrap()
+        });
+        assert!(matches!(js, Err(ScanRejection::PdfJavaScript)));
+
+        let launch = check("b.pdf", |p| {
+            std::fs::write(p, pdf(b"<< /S /Launch /F (cmd.exe) >>")).unwrap()
+        });
+        assert!(matches!(launch, Err(ScanRejection::PdfLaunchAction)));
+    }
+
+    #[test]
+    fn test_pdf_embedded_file_in_object_stream_rejected() {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder
+            .write_all(b"<< /Names << /EmbeddedFiles 3 0 R >> >>")
+            .unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut body = b"<< /Type /ObjStm /Filter /FlateDecode >>\nstream\n".to_vec();
+        body.extend(compressed);
+        body.extend_from_slice(b"\nendstream");
+
+        let result = check("book.pdf", |p| std::fs::write(p, pdf(&body)).unwrap());
+        assert!(matches!(result, Err(ScanRejection::PdfEmbeddedFile)));
+    }
+
+    #[test]
+    fn test_truncated_pdf_rejected() {
+        let result = check("book.pdf", |p| {
+            std::fs::write(p, b"%PDF-1.7\n1 0 obj\n<< /Type /Catalog").unwrap()
+        });
+        assert!(matches!(result, Err(ScanRejection::InvalidPdf(_))));
+    }
+}
@@ -0,0 +1,411 @@
+use std::ffi::{CString, OsString};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use notify::event::{CreateKind, DataChange, Flag, ModifyKind, RenameMode};
+use notify::{Event, EventKind};
+use thiserror::Error;
+
+const EVENT_MASK: u64 =
+    libc::FAN_CREATE | libc::FAN_MOVED_TO | libc::FAN_MODIFY | libc::FAN_CLOSE_WRITE;
+const METADATA_LEN: usize = std::mem::size_of::<libc::fanotify_event_metadata>();
+const INFO_HEADER_LEN: usize = 4;
+const FSID_LEN: usize = 8;
+const FILE_HANDLE_HEADER_LEN: usize = 8;
+/// `MAX_HANDLE_SZ` from linux/fcntl.h
+const MAX_HANDLE_LEN: usize = 128;
+
+#[derive(Debug, Error)]
+pub enum FanotifyError {
+    #[error(
+        "fanotify needs CAP_SYS_ADMIN and CAP_DAC_READ_SEARCH; run homed as root \
+         or grant them via AmbientCapabilities in the service unit"
+    )]
+    PermissionDenied,
+
+    #[error("kernel does not support fanotify directory events (Linux 5.9 or newer required)")]
+    Unsupported,
+
+    #[error(
+        "cannot resolve file handles under {path} ({source}); \
+         open_by_handle_at needs CAP_DAC_READ_SEARCH"
+    )]
+    HandleResolution {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// A decoded fanotify record before its directory handle is resolved.
+#[derive(Debug, PartialEq)]
+enum RawEvent {
+    Overflow,
+    Entry {
+        mask: u64,
+        fsid: [i32; 2],
+        handle: Vec<u8>,
+        name: OsString,
+    },
+}
+
+/// Watches whole filesystems with one fanotify mark each and reports
+/// events below the configured roots.
+///
+/// Unlike recursive inotify this does not need a watch per directory, so
+/// it isn't bound by `max_user_watches` on very large libraries.
+pub struct FanotifyWatcher {
+    fd: OwnedFd,
+    roots: Vec<PathBuf>,
+    /// Directory fds used to resolve file handles, keyed by filesystem id.
+    mounts: Vec<([i32; 2], OwnedFd)>,
+}
+
+impl FanotifyWatcher {
+    pub fn new(roots: &[PathBuf]) -> Result<Self, FanotifyError> {
+        let raw = unsafe {
+            libc::fanotify_init(
+                libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_REPORT_DFID_NAME,
+                (libc::O_RDONLY | libc::O_CLOEXEC) as libc::c_uint,
+            )
+        };
+        if raw < 0 {
+            return Err(init_error(std::io::Error::last_os_error()));
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        let mut mounts: Vec<([i32; 2], OwnedFd)> = Vec::new();
+        for root in roots {
+            let c_path = CString::new(root.as_os_str().as_bytes())
+                .map_err(|e| FanotifyError::IoError(e.into()))?;
+
+            let marked = unsafe {
+                libc::fanotify_mark(
+                    fd.as_raw_fd(),
+                    libc::FAN_MARK_ADD | libc::FAN_MARK_FILESYSTEM,
+                    EVENT_MASK,
+                    libc::AT_FDCWD,
+                    c_path.as_ptr(),
+                )
+            };
+            if marked < 0 {
+                return Err(init_error(std::io::Error::last_os_error()));
+            }
+
+            let fsid = filesystem_id(&c_path)?;
+            if mounts.iter().all(|(id, _)| *id != fsid) {
+                let dir: OwnedFd = std::fs::File::open(root)?.into();
+                // Without this every event would be dropped silently
+                probe_handles(&c_path, &dir).map_err(|source| FanotifyError::HandleResolution {
+                    path: root.clone(),
+                    source,
+                })?;
+                mounts.push((fsid, dir));
+            }
+        }
+
+        Ok(Self {
+            fd,
+            roots: roots.to_vec(),
+            mounts,
+        })
+    }
+
+    /// Waits up to `timeout_ms` for events and returns those under the roots.
+    pub fn read_events(&self, timeout_ms: i32) -> Result<Vec<Event>, FanotifyError> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(Vec::new());
+            }
+            return Err(err.into());
+        }
+        if ready == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buf = vec![0u8; 64 * 1024];
+        let n = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if n < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let events = parse_events(&buf[..n as usize])
+            .into_iter()
+            .filter_map(|raw| self.resolve(raw))
+            .collect();
+
+        Ok(events)
+    }
+
+    fn resolve(&self, raw: RawEvent) -> Option<Event> {
+        let (mask, fsid, handle, name) = match raw {
+            RawEvent::Overflow => {
+                return Some(Event::new(EventKind::Other).set_flag(Flag::Rescan));
+            }
+            RawEvent::Entry {
+                mask,
+                fsid,
+                handle,
+                name,
+            } => (mask, fsid, handle, name),
+        };
+
+        let (_, mount_fd) = self.mounts.iter().find(|(id, _)| *id == fsid)?;
+        let path = resolve_handle(mount_fd, &handle)?.join(name);
+        if !self.roots.iter().any(|root| path.starts_with(root)) {
+            return None;
+        }
+
+        let kind = if mask & libc::FAN_CREATE != 0 {
+            EventKind::Create(CreateKind::File)
+        } else if mask & libc::FAN_MOVED_TO != 0 {
+            EventKind::Modify(ModifyKind::Name(RenameMode::To))
+        } else {
+            EventKind::Modify(ModifyKind::Data(DataChange::Any))
+        };
+
+        Some(Event::new(kind).add_path(path))
+    }
+}
+
+fn init_error(err: std::io::Error) -> FanotifyError {
+    match err.raw_os_error() {
+        Some(libc::EPERM) => FanotifyError::PermissionDenied,
+        Some(libc::EINVAL) | Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP) => {
+            FanotifyError::Unsupported
+        }
+        _ => FanotifyError::IoError(err),
+    }
+}
+
+fn filesystem_id(path: &CString) -> Result<[i32; 2], FanotifyError> {
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // fsid_t keeps its two ints private, but the layout is fixed by the kernel ABI
+    Ok(unsafe { std::ptr::read_unaligned(&stat.f_fsid as *const _ as *const [i32; 2]) })
+}
+
+/// Turns a directory file handle back into a path via `/proc/self/fd`.
+fn resolve_handle(mount_fd: &OwnedFd, handle: &[u8]) -> Option<PathBuf> {
+    let dir_fd = open_handle(mount_fd, handle).ok()?;
+    std::fs::read_link(Path::new("/proc/self/fd").join(dir_fd.as_raw_fd().to_string())).ok()
+}
+
+fn open_handle(mount_fd: &OwnedFd, handle: &[u8]) -> std::io::Result<OwnedFd> {
+    let raw = unsafe {
+        libc::syscall(
+            libc::SYS_open_by_handle_at,
+            mount_fd.as_raw_fd(),
+            handle.as_ptr(),
+            libc::O_PATH | libc::O_CLOEXEC,
+        )
+    };
+    if raw < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(raw as libc::c_int) })
+}
+
+/// Round-trips the handle of `path` through `open_by_handle_at`, the way
+/// every event's directory is resolved.
+fn probe_handles(path: &CString, mount_fd: &OwnedFd) -> std::io::Result<()> {
+    let mut handle = vec![0u8; FILE_HANDLE_HEADER_LEN + MAX_HANDLE_LEN];
+    handle[..4].copy_from_slice(&(MAX_HANDLE_LEN as u32).to_ne_bytes());
+    let mut mount_id: libc::c_int = 0;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_name_to_handle_at,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            handle.as_mut_ptr(),
+            &mut mount_id,
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    open_handle(mount_fd, &handle).map(drop)
+}
+
+fn parse_events(buf: &[u8]) -> Vec<RawEvent> {
+    let mut events = Vec::new();
+    let mut offset = 0;
+
+    while offset + METADATA_LEN <= buf.len() {
+        let meta: libc::fanotify_event_metadata =
+            unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+        let event_len = meta.event_len as usize;
+        if event_len < METADATA_LEN || offset + event_len > buf.len() {
+            break;
+        }
+
+        if meta.mask & libc::FAN_Q_OVERFLOW != 0 {
+            events.push(RawEvent::Overflow);
+        } else if let Some(event) = parse_dfid_name(
+            meta.mask,
+            &buf[offset + meta.metadata_len as usize..offset + event_len],
+        ) {
+            events.push(event);
+        }
+
+        offset += event_len;
+    }
+
+    events
+}
+
+/// Extracts the `DFID_NAME` info record: fsid, directory file handle and
+/// the entry name relative to that directory.
+fn parse_dfid_name(mask: u64, mut info: &[u8]) -> Option<RawEvent> {
+    while info.len() >= INFO_HEADER_LEN {
+        let info_type = info[0];
+        let len = u16::from_ne_bytes([info[2], info[3]]) as usize;
+        if len < INFO_HEADER_LEN || len > info.len() {
+            return None;
+        }
+        let record = &info[INFO_HEADER_LEN..len];
+        info = &info[len..];
+
+        if info_type != libc::FAN_EVENT_INFO_TYPE_DFID_NAME {
+            continue;
+        }
+
+        let fsid = [
+            i32::from_ne_bytes(record.get(0..4)?.try_into().ok()?),
+            i32::from_ne_bytes(record.get(4..8)?.try_into().ok()?),
+        ];
+        let handle_start = FSID_LEN;
+        let handle_bytes = u32::from_ne_bytes(
+            record
+                .get(handle_start..handle_start + 4)?
+                .try_into()
+                .ok()?,
+        ) as usize;
+        let handle_end = handle_start + FILE_HANDLE_HEADER_LEN + handle_bytes;
+        let handle = record.get(handle_start..handle_end)?.to_vec();
+
+        let name_bytes = record.get(handle_end..)?;
+        let name_len = name_bytes.iter().position(|&b| b == 0)?;
+        let name = OsString::from_vec(name_bytes[..name_len].to_vec());
+
+        return Some(RawEvent::Entry {
+            mask,
+            fsid,
+            handle,
+            name,
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(event_len: u32, mask: u64) -> Vec<u8> {
+        let meta = libc::fanotify_event_metadata {
+            event_len,
+            vers: libc::FANOTIFY_METADATA_VERSION,
+            reserved: 0,
+            metadata_len: METADATA_LEN as u16,
+            mask,
+            fd: libc::FAN_NOFD,
+            pid: 1,
+        };
+        let ptr = &meta as *const _ as *const u8;
+        unsafe { std::slice::from_raw_parts(ptr, METADATA_LEN) }.to_vec()
+    }
+
+    fn dfid_name_record(fsid: [i32; 2], handle: &[u8], name: &str) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&fsid[0].to_ne_bytes());
+        body.extend_from_slice(&fsid[1].to_ne_bytes());
+        body.extend_from_slice(&(handle.len() as u32).to_ne_bytes());
+        body.extend_from_slice(&1i32.to_ne_bytes());
+        body.extend_from_slice(handle);
+        body.extend_from_slice(name.as_bytes());
+        body.push(0);
+        while (body.len() + INFO_HEADER_LEN) % 4 != 0 {
+            body.push(0);
+        }
+
+        let mut record = vec![libc::FAN_EVENT_INFO_TYPE_DFID_NAME, 0];
+        record.extend_from_slice(&((body.len() + INFO_HEADER_LEN) as u16).to_ne_bytes());
+        record.extend_from_slice(&body);
+        record
+    }
+
+    #[test]
+    fn test_parse_overflow() {
+        let buf = metadata(METADATA_LEN as u32, libc::FAN_Q_OVERFLOW);
+        assert_eq!(parse_events(&buf), vec![RawEvent::Overflow]);
+    }
+
+    #[test]
+    fn test_parse_dfid_name_event() {
+        let record = dfid_name_record([7, 9], &[0xAA, 0xBB, 0xCC, 0xDD], "episode.mkv");
+        let mut buf = metadata((METADATA_LEN + record.len()) as u32, libc::FAN_CLOSE_WRITE);
+        buf.extend_from_slice(&record);
+        buf.extend_from_slice(&metadata(METADATA_LEN as u32, libc::FAN_Q_OVERFLOW));
+
+        let events = parse_events(&buf);
+
+        assert_eq!(events.len(), 2);
+        let RawEvent::Entry {
+            mask,
+            fsid,
+            handle,
+            name,
+        } = &events[0]
+        else {
+            panic!("expected entry event");
+        };
+        assert_eq!(*mask, libc::FAN_CLOSE_WRITE);
+        assert_eq!(*fsid, [7, 9]);
+        assert_eq!(handle.len(), FILE_HANDLE_HEADER_LEN + 4);
+        assert_eq!(name, "episode.mkv");
+        assert_eq!(events[1], RawEvent::Overflow);
+    }
+
+    #[test]
+    fn test_probe_handles() {
+        let dir = tempfile::tempdir().unwrap();
+        let c_path = CString::new(dir.path().as_os_str().as_bytes()).unwrap();
+        let mount_fd: OwnedFd = std::fs::File::open(dir.path()).unwrap().into();
+        match probe_handles(&c_path, &mount_fd) {
+            Ok(()) => {}
+            // Without CAP_DAC_READ_SEARCH, or on filesystems without handles
+            Err(e) => assert!(
+                matches!(e.raw_os_error(), Some(libc::EPERM | libc::EOPNOTSUPP)),
+                "{e}"
+            ),
+        }
+    }
+
+    #[test]
+    fn test_truncated_buffer_is_ignored() {
+        let buf = metadata(200, libc::FAN_CLOSE_WRITE);
+        assert!(parse_events(&buf).is_empty());
+    }
+}
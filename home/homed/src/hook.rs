@@ -0,0 +1,316 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::process::Command;
+use tracing::warn;
+
+use crate::checks::ScanRejection;
+use crate::config::{CommandConfig, FailPolicy};
+
+#[derive(Debug, Error)]
+pub enum HookError {
+    #[error("failed to run: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+
+    #[error("exited with {0}")]
+    UnexpectedExit(String),
+
+    #[error("invalid JSON verdict: {0}")]
+    InvalidOutput(#[from] serde_json::Error),
+}
+
+/// What a command decided about a file.
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", tag = "verdict", content = "reason")]
+pub enum Outcome {
+    Pass,
+    Quarantine(String),
+    Delete(String),
+    Warn(String),
+}
+
+/// Runs a configured command on a file and maps its answer to a rejection.
+///
+/// When the command can't give a verdict (crash, timeout, unknown exit
+/// code) the configured policy decides, as for clamd.
+pub async fn check_command(config: &CommandConfig, path: &Path) -> Result<Outcome, ScanRejection> {
+    match run_command(config, path).await {
+        Ok(outcome) => Ok(outcome),
+        Err(e) => match config.fail_policy {
+            FailPolicy::Open => {
+                warn!(
+                    command = config.name,
+                    path = %path.display(),
+                    error = %e,
+                    "check command failed, letting file through"
+                );
+                Ok(Outcome::Pass)
+            }
+            FailPolicy::Closed => Err(ScanRejection::CommandFailed {
+                name: config.name.clone(),
+                error: e.to_string(),
+            }),
+        },
+    }
+}
+
+/// Runs the command with the file path as its last argument.
+///
+/// Exit code 0 passes the file, 1 quarantines and 2 deletes it, with the
+/// first line of stdout as the reason. A JSON object on stdout such as
+/// `{"verdict": "quarantine", "reason": "no video stream"}` wins over the
+/// exit code.
+pub async fn run_command(config: &CommandConfig, path: &Path) -> Result<Outcome, HookError> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let mut command = Command::new(&config.command[0]);
+    command
+        .args(&config.command[1..])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    if config.sandbox {
+        sandbox(command.as_std_mut(), path)?;
+    }
+
+    let output = tokio::time::timeout(timeout, command.output())
+        .await
+        .map_err(|_| HookError::Timeout(timeout))??;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stdout = stdout.trim();
+    if stdout.starts_with('{') {
+        return Ok(serde_json::from_str(stdout)?);
+    }
+
+    let reason = || {
+        let line = stdout.lines().next().unwrap_or("").trim();
+        if line.is_empty() {
+            format!("exit code {}", output.status.code().unwrap_or_default())
+        } else {
+            line.to_string()
+        }
+    };
+    match output.status.code() {
+        Some(0) => Ok(Outcome::Pass),
+        Some(1) => Ok(Outcome::Quarantine(reason())),
+        Some(2) => Ok(Outcome::Delete(reason())),
+        _ => Err(HookError::UnexpectedExit(output.status.to_string())),
+    }
+}
+
+/// Checks that sandboxed commands can start, by setting up the sandbox in a
+/// child that exits right after. Blocking.
+pub fn probe_sandbox() -> Result<(), HookError> {
+    let exe = std::env::current_exe()?;
+    let mut command = std::process::Command::new(&exe);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    sandbox(&mut command, &exe)?;
+    // SAFETY: _exit(2) is async-signal-safe
+    unsafe {
+        command.pre_exec(|| libc::_exit(0));
+    }
+
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(HookError::UnexpectedExit(status.to_string()))
+    }
+}
+
+/// Runs the child in fresh mount and network namespaces: it has no network
+/// at all, the whole filesystem is read-only and the file under scan is
+/// bind-mounted read-only as well. Needs CAP_SYS_ADMIN and Linux 5.12;
+/// without them the command fails to start.
+fn sandbox(command: &mut std::process::Command, path: &Path) -> Result<(), HookError> {
+    // Allocate before fork, pre_exec may only make async-signal-safe calls
+    let target = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| HookError::IoError(std::io::Error::other(e)))?;
+    let root = c"/";
+
+    let sys = |ret: libc::c_int| {
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    };
+
+    // SAFETY: the closure only calls unshare(2), mount(2) and
+    // mount_setattr(2) on memory allocated before the fork or on its stack.
+    unsafe {
+        command.pre_exec(move || {
+            let none = std::ptr::null();
+            sys(libc::unshare(libc::CLONE_NEWNS | libc::CLONE_NEWNET))?;
+            sys(libc::mount(
+                none,
+                root.as_ptr(),
+                none,
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+            // A MS_REMOUNT only reaches the top mount, this covers every
+            // mount below `/` such as /tmp or the library itself
+            let read_only = libc::mount_attr {
+                attr_set: libc::MOUNT_ATTR_RDONLY,
+                attr_clr: 0,
+                propagation: 0,
+                userns_fd: 0,
+            };
+            let ret = libc::syscall(
+                libc::SYS_mount_setattr,
+                libc::AT_FDCWD,
+                root.as_ptr(),
+                libc::AT_RECURSIVE,
+                &read_only,
+                std::mem::size_of::<libc::mount_attr>(),
+            );
+            sys(ret as libc::c_int)?;
+            sys(libc::mount(
+                target.as_ptr(),
+                target.as_ptr(),
+                none,
+                libc::MS_BIND,
+                std::ptr::null(),
+            ))?;
+            sys(libc::mount(
+                none,
+                target.as_ptr(),
+                none,
+                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                std::ptr::null(),
+            ))
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    fn script(dir: &Path, body: &str) -> PathBuf {
+        let path = dir.join("check.sh");
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn command_config(script: &Path) -> CommandConfig {
+        CommandConfig {
+            name: "test".to_string(),
+            command: vec![script.to_string_lossy().into_owned()],
+            timeout_secs: 5,
+            sandbox: false,
+            fail_policy: FailPolicy::Closed,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exit_codes_map_to_outcomes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("movie.mkv");
+        std::fs::write(&file, b"video").unwrap();
+
+        let config = command_config(&script(
+            dir.path(),
+            r#"case "$1" in *.mkv) echo "no video stream"; exit 1;; esac"#,
+        ));
+        assert_eq!(
+            run_command(&config, &file).await.unwrap(),
+            Outcome::Quarantine("no video stream".to_string())
+        );
+        assert_eq!(
+            run_command(&config, &dir.path().join("movie.mp4"))
+                .await
+                .unwrap(),
+            Outcome::Pass
+        );
+
+        let config = command_config(&script(dir.path(), "exit 2"));
+        assert_eq!(
+            run_command(&config, &file).await.unwrap(),
+            Outcome::Delete("exit code 2".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_verdict_wins_over_exit_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = command_config(&script(
+            dir.path(),
+            r#"echo '{"verdict": "warn", "reason": "odd encoder"}'; exit 1"#,
+        ));
+        assert_eq!(
+            run_command(&config, Path::new("/x.mkv")).await.unwrap(),
+            Outcome::Warn("odd encoder".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_is_read_only() {
+        if let Err(e) = probe_sandbox() {
+            eprintln!("skipping, no sandbox: {e}");
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("movie.mkv");
+        std::fs::write(&file, b"video").unwrap();
+        let outside = dir.path().join("outside");
+
+        let mut config = command_config(&script(
+            dir.path(),
+            &format!(
+                r#"if echo x > "{}" 2>/dev/null; then echo "wrote outside"; exit 1; fi
+                if echo x >> "$1" 2>/dev/null; then echo "wrote file"; exit 1; fi
+                exit 0"#,
+                outside.display()
+            ),
+        ));
+        config.sandbox = true;
+        assert_eq!(run_command(&config, &file).await.unwrap(), Outcome::Pass);
+        assert!(!outside.exists());
+        assert_eq!(std::fs::read(&file).unwrap(), b"video");
+
+        config.sandbox = false;
+        assert_eq!(
+            run_command(&config, &file).await.unwrap(),
+            Outcome::Quarantine("wrote outside".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failures_follow_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = command_config(&script(dir.path(), "sleep 10"));
+        config.timeout_secs = 1;
+        let rejection = check_command(&config, Path::new("/x.mkv"))
+            .await
+            .unwrap_err();
+        assert!(matches!(rejection, ScanRejection::CommandFailed { .. }));
+
+        let mut config = command_config(&script(dir.path(), "exit 7"));
+        config.fail_policy = FailPolicy::Open;
+        assert_eq!(
+            check_command(&config, Path::new("/x.mkv")).await.unwrap(),
+            Outcome::Pass
+        );
+    }
+}
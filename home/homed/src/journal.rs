@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use thiserror::Error;
+
+pub type SharedJournal = Arc<Mutex<StateJournal>>;
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// Size and modification time of a file when the pipeline finished with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime: i64,
+}
+
+impl FileStamp {
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        Self {
+            size: metadata.len(),
+            mtime,
+        }
+    }
+}
+
+/// Append-only record of files the pipeline already finished with.
+///
+/// Each line is `size\tmtime\tpath`; later lines override earlier ones.
+/// The file is compacted on open, dropping entries for paths that no
+/// longer exist so moved-away photos don't accumulate forever.
+#[derive(Debug)]
+pub struct StateJournal {
+    entries: HashMap<PathBuf, FileStamp>,
+    file: File,
+}
+
+impl StateJournal {
+    /// Loads and compacts the journal. Blocking, call from a blocking thread.
+    pub fn open(path: &Path) -> Result<Self, JournalError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut entries = HashMap::new();
+        if let Ok(file) = File::open(path) {
+            let mut reader = BufReader::new(file);
+            let mut line = Vec::new();
+            while reader.read_until(b'\n', &mut line)? > 0 {
+                if let Some((path, stamp)) = parse_line(&line) {
+                    entries.insert(path, stamp);
+                }
+                line.clear();
+            }
+        }
+
+        entries.retain(|path, _| path.exists());
+
+        let tmp_path = path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        for (entry_path, stamp) in &entries {
+            tmp.write_all(&format_line(entry_path, *stamp))?;
+        }
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+
+        Ok(Self { entries, file })
+    }
+
+    pub fn is_known(&self, path: &Path, stamp: FileStamp) -> bool {
+        self.entries.get(path) == Some(&stamp)
+    }
+
+    pub fn record(&mut self, path: &Path, stamp: FileStamp) -> Result<(), JournalError> {
+        if self.is_known(path, stamp) {
+            return Ok(());
+        }
+        self.file.write_all(&format_line(path, stamp))?;
+        self.entries.insert(path.to_path_buf(), stamp);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+fn format_line(path: &Path, stamp: FileStamp) -> Vec<u8> {
+    let mut line = format!("{}\t{}\t", stamp.size, stamp.mtime).into_bytes();
+    line.extend_from_slice(path.as_os_str().as_bytes());
+    line.push(b'\n');
+    line
+}
+
+fn parse_line(line: &[u8]) -> Option<(PathBuf, FileStamp)> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let mut fields = line.splitn(3, |&b| b == b'\t');
+    let size = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let mtime = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let path = std::ffi::OsStr::from_bytes(fields.next()?);
+    if path.is_empty() {
+        return None;
+    }
+
+    Some((PathBuf::from(path), FileStamp { size, mtime }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const STAMP: FileStamp = FileStamp {
+        size: 1024,
+        mtime: 1_700_000_000,
+    };
+
+    #[test]
+    fn test_record_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join("state/media.journal");
+        let file = dir.path().join("movie.mkv");
+        std::fs::write(&file, "x").unwrap();
+
+        let mut journal = StateJournal::open(&journal_path).unwrap();
+        assert!(!journal.is_known(&file, STAMP));
+        journal.record(&file, STAMP).unwrap();
+        drop(journal);
+
+        let journal = StateJournal::open(&journal_path).unwrap();
+        assert!(journal.is_known(&file, STAMP));
+    }
+
+    #[test]
+    fn test_changed_stamp_is_not_known() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("movie.mkv");
+        std::fs::write(&file, "x").unwrap();
+
+        let mut journal = StateJournal::open(&dir.path().join("j")).unwrap();
+        journal.record(&file, STAMP).unwrap();
+        let changed = FileStamp {
+            size: 2048,
+            ..STAMP
+        };
+        assert!(!journal.is_known(&file, changed));
+    }
+
+    #[test]
+    fn test_missing_paths_compacted_on_open() {
+        let dir = tempdir().unwrap();
+        let journal_path = dir.path().join("j");
+        let file = dir.path().join("photo.jpg");
+        std::fs::write(&file, "x").unwrap();
+
+        let mut journal = StateJournal::open(&journal_path).unwrap();
+        journal.record(&file, STAMP).unwrap();
+        drop(journal);
+        std::fs::remove_file(&file).unwrap();
+
+        let journal = StateJournal::open(&journal_path).unwrap();
+        assert_eq!(journal.len(), 0);
+    }
+
+    #[test]
+    fn test_malformed_lines_ignored() {
+        assert!(parse_line(b"garbage\n").is_none());
+        assert!(parse_line(b"10\t20\t\n").is_none());
+        let (path, stamp) = parse_line(b"10\t20\t/a/b c.mkv\n").unwrap();
+        assert_eq!(path, PathBuf::from("/a/b c.mkv"));
+        assert_eq!(
+            stamp,
+            FileStamp {
+                size: 10,
+                mtime: 20
+            }
+        );
+    }
+}
@@ -1,90 +1,139 @@
 mod alerts;
+mod api;
+mod archive;
+mod arr;
+mod audio;
+mod batch;
 mod checks;
+mod clamav;
 mod config;
+mod container;
+mod document;
+mod fanotify;
+mod hook;
+mod journal;
 mod metadata;
 mod nextcloud;
+mod notify;
 mod organizer;
+mod outbox;
+mod quarantine;
+mod release;
+mod reputation;
 mod scanner;
+mod schedule;
+#[cfg(test)]
+mod stub_http;
+mod subtitle;
 mod watcher;
-
-use std::time::Duration;
+mod yara;
 
 use config::Config;
+use journal::SharedJournal;
+use quarantine::QuarantineStore;
 use tokio::sync::{broadcast, mpsc};
 use tokio::time::Instant;
 use tracing::{error, info, warn};
-use watcher::FileEvent;
+use watcher::{FileEvent, Pipeline};
 
-use alerts::send_batch_alert;
+use alerts::{Alert, Dispatcher, send_batch_alert, send_immediate_alert};
+use batch::Batcher;
 
-const BATCH_QUIET_PERIOD: Duration = Duration::from_secs(5);
+const CONFIG_PATH: &str = "/opt/homed/config.toml";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("quarantine") {
+        let config = Config::load(CONFIG_PATH)?;
+        let store = QuarantineStore::open(&config.media.scanner.quarantine_dir)?;
+        return quarantine::run_command(&store, &args[1..]);
+    }
+
     info!("homed starting up");
 
-    let config = Config::load("/opt/homed/config.toml")?;
+    let config = Config::load(CONFIG_PATH)?;
 
-    let http_client = reqwest::Client::new();
-    let alerts_config = config.alerts.clone();
+    let mut dispatcher = Dispatcher::new(config.alerts.clone(), notify::http_client());
 
     let (shutdown_tx, _) = broadcast::channel(1);
     let (output_tx, mut output_rx) = mpsc::channel::<FileEvent>(100);
 
-    let photos_handles = spawn_photos_pipeline(&config, &shutdown_tx, output_tx.clone());
-    let media_handles = spawn_media_pipeline(&config, &shutdown_tx, output_tx);
+    // Opened here so files are only journaled once they leave the pipeline
+    let photos_journal = watcher::open_journal(&config.photos.watcher).await?;
+    let media_journal = watcher::open_journal(&config.media.watcher).await?;
+
+    let photos_handles = spawn_photos_pipeline(
+        &config,
+        photos_journal.clone(),
+        &shutdown_tx,
+        output_tx.clone(),
+    );
+    let media_handles =
+        spawn_media_pipeline(&config, media_journal.clone(), &shutdown_tx, output_tx);
+
+    let api_handle = config.alerts.api.clone().map(|api| {
+        let quarantine_dir = config.media.scanner.quarantine_dir.clone();
+        let shutdown_rx = shutdown_tx.subscribe();
+        tokio::spawn(async move {
+            let store = match QuarantineStore::open(&quarantine_dir) {
+                Ok(store) => store,
+                Err(e) => {
+                    error!(error = %e, "API failed to open quarantine");
+                    return;
+                }
+            };
+            if let Err(e) = api::run_api(api, store, shutdown_rx).await {
+                error!(error = %e, "API failed");
+            }
+        })
+    });
 
     info!("pipelines running");
 
-    let mut organized_count = 0usize;
-    let mut unsorted_count = 0usize;
-    let mut failed_count = 0usize;
-    let mut last_event_time: Option<Instant> = None;
+    let mut batcher = Batcher::new(&config.alerts);
 
     loop {
-        let timeout = last_event_time
-            .map(|t| BATCH_QUIET_PERIOD.saturating_sub(t.elapsed()))
-            .unwrap_or(BATCH_QUIET_PERIOD);
+        let flush_at = batcher.next_flush();
+        let retry_at = dispatcher.next_retry();
 
         tokio::select! {
             Some(event) = output_rx.recv() => {
                 log_event(&event);
-                match &event {
-                    FileEvent::Organized { .. } => organized_count += 1,
-                    FileEvent::Unsorted { .. } => unsorted_count += 1,
-                    FileEvent::Failed { .. } => failed_count += 1,
-                    _ => {}
+                let journal = match event.pipeline() {
+                    Pipeline::Photos => photos_journal.as_ref(),
+                    Pipeline::Media => media_journal.as_ref(),
+                };
+                if let Some(journal) = journal {
+                    watcher::record_processed(journal, &event).await;
+                }
+                batcher.record(&event);
+                // Security relevant events don't wait for the batch, unless
+                // quiet hours or the digest hold back all but urgent ones
+                if let Some(alert) = Alert::for_event(&event) {
+                    if batcher.holds_alerts() && !dispatcher.is_urgent(&alert) {
+                        info!(kind = alert.kind.name(), "alert held for the summary");
+                        batcher.hold(alert);
+                    } else {
+                        send_immediate_alert(&mut dispatcher, &alert).await;
+                    }
                 }
-                last_event_time = Some(Instant::now());
             }
-            _ = tokio::time::sleep(timeout), if last_event_time.is_some() => {
-                if last_event_time.map(|t| t.elapsed() >= BATCH_QUIET_PERIOD).unwrap_or(false) {
-                    send_batch_alert(
-                        &http_client,
-                        &alerts_config,
-                        organized_count,
-                        unsorted_count,
-                        failed_count,
-                    ).await;
-                    organized_count = 0;
-                    unsorted_count = 0;
-                    failed_count = 0;
-                    last_event_time = None;
+            _ = tokio::time::sleep_until(flush_at.unwrap_or_else(Instant::now)),
+                if flush_at.is_some() => {
+                if let Some(batch) = batcher.flush() {
+                    send_batch_alert(&mut dispatcher, &batch).await;
                 }
             }
+            _ = tokio::time::sleep_until(retry_at.unwrap_or_else(Instant::now)),
+                if retry_at.is_some() => {
+                dispatcher.retry().await;
+            }
             _ = tokio::signal::ctrl_c() => {
                 info!("received shutdown signal, draining pipelines");
-                if organized_count > 0 || unsorted_count > 0 || failed_count > 0 {
-                    send_batch_alert(
-                        &http_client,
-                        &alerts_config,
-                        organized_count,
-                        unsorted_count,
-                        failed_count,
-                    ).await;
-                }
+                send_batch_alert(&mut dispatcher, &batcher.take()).await;
                 shutdown_tx.send(()).ok();
                 break;
             }
@@ -93,7 +142,11 @@ async fn main() -> anyhow::Result<()> {
 
     let shutdown_timeout = std::time::Duration::from_secs(30);
     let all_handles = async {
-        for handle in photos_handles.into_iter().chain(media_handles) {
+        for handle in photos_handles
+            .into_iter()
+            .chain(media_handles)
+            .chain(api_handle)
+        {
             let _ = handle.await;
         }
     };
@@ -112,29 +165,44 @@ async fn main() -> anyhow::Result<()> {
 
 fn spawn_photos_pipeline(
     config: &Config,
+    journal: Option<SharedJournal>,
     shutdown_tx: &broadcast::Sender<()>,
     output_tx: mpsc::Sender<FileEvent>,
 ) -> Vec<tokio::task::JoinHandle<()>> {
+    let overrides = &config.photos.overrides;
     let (watcher_tx, watcher_rx) = mpsc::channel(100);
     let (metadata_tx, metadata_rx) = mpsc::channel(100);
     let (organizer_tx, organizer_rx) = mpsc::channel(100);
 
     let watcher_handle = tokio::spawn({
         let config = config.photos.watcher.clone();
+        let overrides = overrides.clone();
         let shutdown_rx = shutdown_tx.subscribe();
         async move {
-            if let Err(e) = watcher::run_watcher(config, watcher_tx, shutdown_rx).await {
+            if let Err(e) = watcher::run_watcher(
+                Pipeline::Photos,
+                config,
+                overrides,
+                journal,
+                watcher_tx,
+                shutdown_rx,
+            )
+            .await
+            {
                 error!(error = %e, "photos watcher failed");
             }
         }
     });
 
     let metadata_handle = tokio::spawn({
+        // Shared with the media scanner so `homed quarantine` sees both
+        let quarantine_dir = config.media.scanner.quarantine_dir.clone();
         let config = config.photos.organizer.clone();
         let shutdown_rx = shutdown_tx.subscribe();
         async move {
             if let Err(e) =
-                metadata::run_metadata(config, watcher_rx, metadata_tx, shutdown_rx).await
+                metadata::run_metadata(config, quarantine_dir, watcher_rx, metadata_tx, shutdown_rx)
+                    .await
             {
                 error!(error = %e, "photos metadata failed");
             }
@@ -143,10 +211,12 @@ fn spawn_photos_pipeline(
 
     let organizer_handle = tokio::spawn({
         let config = config.photos.organizer.clone();
+        let overrides = overrides.clone();
         let shutdown_rx = shutdown_tx.subscribe();
         async move {
             if let Err(e) =
-                organizer::run_organizer(config, metadata_rx, organizer_tx, shutdown_rx).await
+                organizer::run_organizer(config, overrides, metadata_rx, organizer_tx, shutdown_rx)
+                    .await
             {
                 error!(error = %e, "photos organizer failed");
             }
@@ -175,26 +245,43 @@ fn spawn_photos_pipeline(
 
 fn spawn_media_pipeline(
     config: &Config,
+    journal: Option<SharedJournal>,
     shutdown_tx: &broadcast::Sender<()>,
     output_tx: mpsc::Sender<FileEvent>,
 ) -> Vec<tokio::task::JoinHandle<()>> {
+    let overrides = &config.media.overrides;
     let (watcher_tx, watcher_rx) = mpsc::channel(100);
 
     let watcher_handle = tokio::spawn({
         let config = config.media.watcher.clone();
+        let overrides = overrides.clone();
         let shutdown_rx = shutdown_tx.subscribe();
         async move {
-            if let Err(e) = watcher::run_watcher(config, watcher_tx, shutdown_rx).await {
+            if let Err(e) = watcher::run_watcher(
+                Pipeline::Media,
+                config,
+                overrides,
+                journal,
+                watcher_tx,
+                shutdown_rx,
+            )
+            .await
+            {
                 error!(error = %e, "media watcher failed");
             }
         }
     });
 
     let scanner_handle = tokio::spawn({
+        let roots = config.media.watcher.paths.clone();
         let config = config.media.scanner.clone();
+        let overrides = overrides.clone();
         let shutdown_rx = shutdown_tx.subscribe();
         async move {
-            if let Err(e) = scanner::run_scanner(config, watcher_rx, output_tx, shutdown_rx).await {
+            if let Err(e) =
+                scanner::run_scanner(config, overrides, roots, watcher_rx, output_tx, shutdown_rx)
+                    .await
+            {
                 error!(error = %e, "media scanner failed");
             }
         }
@@ -205,20 +292,24 @@ fn spawn_media_pipeline(
 
 fn log_event(event: &FileEvent) {
     match event {
-        FileEvent::Detected { path, size } => {
+        FileEvent::Detected { path, size, .. } => {
             info!(path = %path.display(), size, "file detected");
         }
-        FileEvent::Scanned { path, clean } => {
+        FileEvent::DetectedBatch { dir, files, .. } => {
+            info!(dir = %dir.display(), files = files.len(), "directory batch detected");
+        }
+        FileEvent::Scanned { path, clean, .. } => {
             if *clean {
                 info!(path = %path.display(), "scan passed");
             } else {
-                warn!(path = %path.display(), "malware detected");
+                warn!(path = %path.display(), "malware detected but not quarantined");
             }
         }
         FileEvent::Enriched {
             path,
             media_type,
             datetime,
+            ..
         } => {
             info!(
                 path = %path.display(),
@@ -227,24 +318,36 @@ fn log_event(event: &FileEvent) {
                 "metadata extracted"
             );
         }
-        FileEvent::Organized { old_path, new_path } => {
+        FileEvent::Organized {
+            old_path, new_path, ..
+        } => {
             info!(
                 from = %old_path.display(),
                 to = %new_path.display(),
                 "file organized"
             );
         }
-        FileEvent::Cleaned { path, reason } => {
+        FileEvent::Cleaned { path, reason, .. } => {
             info!(path = %path.display(), reason, "file cleaned");
         }
-        FileEvent::Unsorted { path, media_type } => {
+        FileEvent::Unsorted {
+            path, media_type, ..
+        } => {
             info!(
                 path = %path.display(),
                 media_type = ?media_type,
                 "no valid date, moving to unsorted"
             );
         }
-        FileEvent::Failed { path, error } => {
+        FileEvent::Quarantined {
+            path, id, reason, ..
+        } => {
+            warn!(path = %path.display(), id, reason, "quarantined");
+        }
+        FileEvent::Rescan { reason, .. } => {
+            warn!(reason, "watcher rescan triggered");
+        }
+        FileEvent::Failed { path, error, .. } => {
             warn!(path = %path.display(), error, "processing failed");
         }
     }
@@ -1,12 +1,46 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
 
 use chrono::{DateTime, Datelike, FixedOffset, NaiveDateTime, Offset, TimeZone, Utc};
 use nom_exif::{EntryValue, ExifIter, ExifTag, MediaParser, MediaSource, TrackInfo, TrackInfoTag};
 use thiserror::Error;
+use tokio::io::AsyncReadExt;
 use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
+use crate::checks::{self, ScanRejection};
 use crate::config::OrganizerConfig;
-use crate::watcher::{FileEvent, MediaType};
+use crate::quarantine::{QuarantineError, QuarantineStore};
+use crate::watcher::{FileEvent, MediaType, Pipeline};
+
+/// infer plus camera RAW formats it doesn't know. NEF, ARW and DNG are
+/// TIFF-based and detected as `tif` already.
+static PHOTO_INFER: LazyLock<infer::Infer> = LazyLock::new(|| {
+    let mut infer = infer::Infer::new();
+    infer.add("image/x-canon-cr3", "cr3", |buf| {
+        buf.len() > 11 && &buf[4..12] == b"ftypcrx "
+    });
+    infer.add("image/x-fuji-raf", "raf", |buf| {
+        buf.starts_with(b"FUJIFILMCCD-RAW")
+    });
+    infer.add("image/x-olympus-orf", "orf", |buf| {
+        [b"IIRO", b"IIRS", b"MMOR"]
+            .iter()
+            .any(|m| buf.starts_with(*m))
+    });
+    infer.add("image/x-panasonic-rw2", "rw2", |buf| {
+        buf.starts_with(b"IIU\0")
+    });
+    infer
+});
+
+/// What a photo upload turned out to be after looking at its content.
+#[derive(Debug)]
+enum TypeCheck {
+    Media(MediaType),
+    Unsupported(String),
+    Dangerous(ScanRejection),
+}
 
 fn classify_media_type(path: &Path, config: &OrganizerConfig) -> Option<MediaType> {
     let extension = path.extension().and_then(|ext| ext.to_str())?;
@@ -30,6 +64,65 @@ fn classify_media_type(path: &Path, config: &OrganizerConfig) -> Option<MediaTyp
     }
 }
 
+/// Classifies by extension, then lets the magic bytes overrule it: a
+/// `.jpg` that is really a video is handled as a video, executables are
+/// flagged whatever they are called.
+fn verify_type(path: &Path, extension_type: Option<MediaType>, bytes: &[u8]) -> TypeCheck {
+    if let Err(rejection) = checks::check_executable_extension(path) {
+        return TypeCheck::Dangerous(rejection);
+    }
+
+    if bytes.starts_with(b"#!") {
+        return TypeCheck::Dangerous(ScanRejection::ExecutableContent("script".to_string()));
+    }
+
+    let Some(kind) = PHOTO_INFER.get(bytes) else {
+        // Not every format has a signature, trust the extension then
+        return match extension_type {
+            Some(media_type) => TypeCheck::Media(media_type),
+            None => TypeCheck::Unsupported("Unsupported media type".to_string()),
+        };
+    };
+
+    let content_type = match kind.matcher_type() {
+        infer::MatcherType::Image | infer::MatcherType::Custom => MediaType::Photo,
+        infer::MatcherType::Video => MediaType::Video,
+        infer::MatcherType::App => {
+            return TypeCheck::Dangerous(ScanRejection::ExecutableContent(
+                kind.extension().to_string(),
+            ));
+        }
+        _ => {
+            return TypeCheck::Unsupported(format!(
+                "content is .{}, not a photo or video",
+                kind.extension()
+            ));
+        }
+    };
+
+    match extension_type {
+        Some(media_type) => {
+            if media_type != content_type {
+                info!(
+                    path = %path.display(),
+                    detected = kind.extension(),
+                    "content contradicts extension, reclassified as {content_type:?}"
+                );
+            }
+            TypeCheck::Media(content_type)
+        }
+        None => TypeCheck::Unsupported("Unsupported media type".to_string()),
+    }
+}
+
+async fn read_head(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = vec![0u8; checks::SNIFF_LEN];
+    let n = file.read(&mut buf).await?;
+    buf.truncate(n);
+    Ok(buf)
+}
+
 fn extract_photo_datetime(path: &Path) -> Option<DateTime<FixedOffset>> {
     let mut parser = MediaParser::new();
 
@@ -126,46 +219,90 @@ async fn extract_best_datetime(
 }
 
 #[derive(Debug, Error)]
-pub enum MetadataError {}
+pub enum MetadataError {
+    #[error("Failed to open quarantine: {0}")]
+    QuarantineError(#[from] QuarantineError),
+}
 
 /// Classifies files as photo/video and extracts timestamps.
 ///
-/// Non-media files are rejected with a Failed event. Files without
-/// any extractable datetime are also rejected since we can't name them.
+/// The content is checked before anything else: executables are
+/// quarantined and other non-media files are rejected with a Failed event.
+/// Files without any extractable datetime are also rejected since we can't
+/// name them.
 pub async fn run_metadata(
     config: OrganizerConfig,
+    quarantine_dir: PathBuf,
     mut rx: mpsc::Receiver<FileEvent>,
     tx: mpsc::Sender<FileEvent>,
     mut shutdown: tokio::sync::broadcast::Receiver<()>,
 ) -> Result<(), MetadataError> {
+    let quarantine = QuarantineStore::open(&quarantine_dir)?;
+
     loop {
         let event = tokio::select! {
             Some(event) = rx.recv() => event,
             _ = shutdown.recv() => break,
             else => break,
         };
-        let path = match event {
-            FileEvent::Detected { path, .. } => path,
+        let (pipeline, path, size) = match event {
+            FileEvent::Detected {
+                pipeline,
+                path,
+                size,
+            } => (pipeline, path, size),
             other => {
                 let _ = tx.send(other).await;
                 continue;
             }
         };
 
-        let Some(media_type) = classify_media_type(&path, &config) else {
-            let _ = tx
-                .send(FileEvent::Failed {
-                    path,
-                    error: "Unsupported media type".to_string(),
-                })
-                .await;
-            continue;
+        let extension_type = classify_media_type(&path, &config);
+        // A restored upload was judged by hand, only the extension counts
+        let checked = if is_released(&quarantine, &path, size).await {
+            match extension_type {
+                Some(media_type) => TypeCheck::Media(media_type),
+                None => TypeCheck::Unsupported("Unsupported media type".to_string()),
+            }
+        } else {
+            match read_head(&path).await {
+                Ok(head) => verify_type(&path, extension_type, &head),
+                Err(e) => {
+                    let _ = tx
+                        .send(FileEvent::Failed {
+                            pipeline,
+                            path,
+                            error: e.to_string(),
+                        })
+                        .await;
+                    continue;
+                }
+            }
+        };
+
+        let media_type = match checked {
+            TypeCheck::Media(media_type) => media_type,
+            TypeCheck::Unsupported(error) => {
+                let _ = tx
+                    .send(FileEvent::Failed {
+                        pipeline,
+                        path,
+                        error,
+                    })
+                    .await;
+                continue;
+            }
+            TypeCheck::Dangerous(rejection) => {
+                quarantine_upload(&quarantine, pipeline, &path, &rejection, &tx).await;
+                continue;
+            }
         };
 
         match extract_best_datetime(&path, media_type, config.min_valid_year).await {
             Some(datetime) => {
                 let _ = tx
                     .send(FileEvent::Enriched {
+                        pipeline,
                         path,
                         media_type,
                         datetime,
@@ -173,7 +310,13 @@ pub async fn run_metadata(
                     .await;
             }
             None => {
-                let _ = tx.send(FileEvent::Unsorted { path, media_type }).await;
+                let _ = tx
+                    .send(FileEvent::Unsorted {
+                        pipeline,
+                        path,
+                        media_type,
+                    })
+                    .await;
             }
         }
     }
@@ -181,11 +324,142 @@ pub async fn run_metadata(
     Ok(())
 }
 
+/// Like the scanner's check: consumes the release `homed quarantine restore`
+/// left for this upload, so it isn't quarantined again.
+async fn is_released(store: &QuarantineStore, path: &Path, size: u64) -> bool {
+    let store = store.clone();
+    let owned_path = path.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || store.take_released(&owned_path, size))
+        .await
+        .expect("quarantine task panicked");
+
+    match result {
+        Ok(true) => {
+            info!(path = %path.display(), "restored from quarantine, skipping content checks");
+            true
+        }
+        Ok(false) => false,
+        Err(e) => {
+            error!(error = %e, "failed to read released quarantine items");
+            false
+        }
+    }
+}
+
+/// Like the scanner's quarantine: reports `Quarantined`, or `Failed` when
+/// the upload couldn't be moved.
+async fn quarantine_upload(
+    store: &QuarantineStore,
+    pipeline: Pipeline,
+    path: &Path,
+    rejection: &ScanRejection,
+    tx: &mpsc::Sender<FileEvent>,
+) {
+    let store = store.clone();
+    let owned_path = path.to_path_buf();
+    let reason = rejection.to_string();
+    let result = tokio::task::spawn_blocking(move || store.quarantine(&owned_path, &reason))
+        .await
+        .expect("quarantine task panicked");
+
+    let path = path.to_path_buf();
+    let event = match result {
+        Ok(record) => {
+            warn!(
+                path = %path.display(),
+                id = record.id,
+                reason = %rejection,
+                "photo upload quarantined"
+            );
+            FileEvent::Quarantined {
+                pipeline,
+                path,
+                id: record.id,
+                reason: rejection.to_string(),
+                malware: rejection.is_malware(),
+            }
+        }
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "failed to quarantine file");
+            FileEvent::Failed {
+                pipeline,
+                path,
+                error: format!("{rejection} (quarantine failed: {e})"),
+            }
+        }
+    };
+    let _ = tx.send(event).await;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::path::PathBuf;
 
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0, 0x18];
+        bytes.extend_from_slice(b"ftyp");
+        bytes.extend_from_slice(brand);
+        bytes.extend_from_slice(&[0; 12]);
+        bytes
+    }
+
+    #[test]
+    fn test_photo_content_verified() {
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10, b'J', b'F', b'I', b'F'];
+        let photo = Some(MediaType::Photo);
+        assert!(matches!(
+            verify_type(Path::new("a.jpg"), photo, &jpeg),
+            TypeCheck::Media(MediaType::Photo)
+        ));
+        assert!(matches!(
+            verify_type(Path::new("a.heic"), photo, &ftyp(b"heic")),
+            TypeCheck::Media(MediaType::Photo)
+        ));
+        assert!(matches!(
+            verify_type(Path::new("a.cr3"), photo, &ftyp(b"crx ")),
+            TypeCheck::Media(MediaType::Photo)
+        ));
+    }
+
+    #[test]
+    fn test_video_named_jpg_reclassified() {
+        assert!(matches!(
+            verify_type(Path::new("a.jpg"), Some(MediaType::Photo), &ftyp(b"qt  ")),
+            TypeCheck::Media(MediaType::Video)
+        ));
+    }
+
+    #[test]
+    fn test_executables_flagged() {
+        let pe = b"MZ\x90\x00\x03\x00\x00\x00";
+        assert!(matches!(
+            verify_type(Path::new("IMG_0001.jpg"), Some(MediaType::Photo), pe),
+            TypeCheck::Dangerous(ScanRejection::ExecutableContent(_))
+        ));
+        assert!(matches!(
+            verify_type(
+                Path::new("IMG_0001.jpg"),
+                Some(MediaType::Photo),
+                b"#!/bin/sh\n"
+            ),
+            TypeCheck::Dangerous(ScanRejection::ExecutableContent(_))
+        ));
+        assert!(matches!(
+            verify_type(Path::new("setup.exe"), None, pe),
+            TypeCheck::Dangerous(ScanRejection::ExecutableExtension(_))
+        ));
+    }
+
+    #[test]
+    fn test_non_media_content_unsupported() {
+        let zip = b"PK\x03\x04\x14\x00\x00\x00";
+        assert!(matches!(
+            verify_type(Path::new("a.jpg"), Some(MediaType::Photo), zip),
+            TypeCheck::Unsupported(_)
+        ));
+    }
+
     #[test]
     fn test_filename_with_full_timestamp() {
         let path = PathBuf::from("/photos/IMG_20260211_143022.jpg");
@@ -267,4 +541,63 @@ mod tests {
         let result = extract_best_datetime(&path, MediaType::Photo, 2000).await;
         assert!(result.is_none());
     }
+
+    #[tokio::test]
+    async fn test_restored_upload_not_quarantined_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploads = dir.path().join("uploads");
+        std::fs::create_dir_all(&uploads).unwrap();
+        let quarantine_dir = dir.path().join("quarantine");
+        let config = OrganizerConfig {
+            enabled: true,
+            photos_dir: dir.path().join("photos"),
+            photo_prefix: "IMG".to_string(),
+            video_prefix: "VID".to_string(),
+            photo_extensions: vec!["jpg".to_string()],
+            video_extensions: vec!["mp4".to_string()],
+            file_owner: None,
+            file_group: None,
+            min_valid_year: 2000,
+            unsorted_dir: None,
+        };
+
+        let (in_tx, in_rx) = mpsc::channel(4);
+        let (out_tx, mut out_rx) = mpsc::channel(4);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel(1);
+        let stage = tokio::spawn(run_metadata(
+            config,
+            quarantine_dir.clone(),
+            in_rx,
+            out_tx,
+            shutdown_rx,
+        ));
+
+        // A photo that looks like an executable, quarantined and then
+        // restored by hand
+        let path = uploads.join("IMG_20260211_143022.jpg");
+        std::fs::write(&path, b"MZ\x90\x00 not really a program").unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+        let detected = FileEvent::Detected {
+            pipeline: Pipeline::Photos,
+            path: path.clone(),
+            size,
+        };
+        in_tx.send(detected.clone()).await.unwrap();
+        let Some(FileEvent::Quarantined { id, .. }) = out_rx.recv().await else {
+            panic!("expected the upload to be quarantined");
+        };
+        let store = QuarantineStore::open(&quarantine_dir).unwrap();
+        store.restore(&id, None).unwrap();
+
+        in_tx.send(detected).await.unwrap();
+        let event = out_rx.recv().await.unwrap();
+        assert!(
+            matches!(&event, FileEvent::Enriched { path: p, .. } if *p == path),
+            "{event:?}"
+        );
+        assert!(path.exists());
+
+        shutdown_tx.send(()).unwrap();
+        stage.await.unwrap().unwrap();
+    }
 }
@@ -73,7 +73,10 @@ pub async fn run_nextcloud(
             _ = shutdown.recv() => break,
             else => break,
         };
-        let FileEvent::Organized { old_path, new_path } = &event else {
+        let FileEvent::Organized {
+            old_path, new_path, ..
+        } = &event
+        else {
             let _ = tx.send(event).await;
             continue;
         };
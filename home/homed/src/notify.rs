@@ -0,0 +1,682 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{Value, json};
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::alerts::Message;
+use crate::config::{
+    ActionKind, GotifyConfig, MatrixConfig, NotifierBackend, NtfyConfig, SmtpConfig, WebhookConfig,
+};
+
+/// Gotify priorities (0-10) for alert priorities 1-5. 8 and up pop up on
+/// Android.
+const GOTIFY_PRIORITIES: [u8; 5] = [1, 3, 5, 8, 10];
+/// HTTP notifiers give up after this long, so a server that accepts the
+/// connection and never answers can't hold up the alerts behind it.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("invalid URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("no reply within {0:?}")]
+    Timeout(Duration),
+
+    #[error("unexpected SMTP reply to {step}: {reply}")]
+    SmtpError { step: &'static str, reply: String },
+}
+
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<(), NotifyError>> + Send + 'a>>;
+
+/// A backend that delivers rendered alerts.
+pub trait Notifier: Send + Sync {
+    fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a>;
+}
+
+/// The client shared by the HTTP notifiers.
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .connect_timeout(HTTP_CONNECT_TIMEOUT)
+        .build()
+        .expect("HTTP client with default TLS settings")
+}
+
+pub fn build(backend: NotifierBackend, client: reqwest::Client) -> Box<dyn Notifier> {
+    match backend {
+        NotifierBackend::Ntfy(config) => Box::new(Ntfy { config, client }),
+        NotifierBackend::Gotify(config) => Box::new(Gotify { config, client }),
+        NotifierBackend::Matrix(config) => Box::new(Matrix::new(config, client)),
+        NotifierBackend::Smtp(config) => Box::new(Smtp { config }),
+        NotifierBackend::Webhook(config) => Box::new(Webhook { config, client }),
+    }
+}
+
+fn priority_index(message: &Message) -> usize {
+    usize::from(message.priority.clamp(1, 5)) - 1
+}
+
+/// Publishes JSON to the server root, so titles and tags may hold any
+/// characters.
+struct Ntfy {
+    config: NtfyConfig,
+    client: reqwest::Client,
+}
+
+impl Notifier for Ntfy {
+    fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a> {
+        Box::pin(async move {
+            let mut request = self
+                .client
+                .post(self.config.url.trim_end_matches('/'))
+                .json(&ntfy_payload(&self.config.topic, message));
+            if !self.config.token.is_empty() {
+                request = request.bearer_auth(&self.config.token);
+            }
+            request.send().await?.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+fn ntfy_payload(topic: &str, message: &Message) -> Value {
+    let mut payload = json!({
+        "topic": topic,
+        "title": message.title,
+        "message": message.body,
+        "priority": message.priority,
+        "tags": message.tags,
+    });
+    if let Some(click) = &message.click {
+        payload["click"] = json!(click);
+    }
+    if !message.actions.is_empty() {
+        let actions: Vec<Value> = message
+            .actions
+            .iter()
+            .map(|action| match action.action {
+                ActionKind::View => json!({
+                    "action": "view",
+                    "label": action.label,
+                    "url": action.url,
+                }),
+                ActionKind::Http => json!({
+                    "action": "http",
+                    "label": action.label,
+                    "url": action.url,
+                    "method": action.method,
+                    "headers": action.headers,
+                    "clear": true,
+                }),
+            })
+            .collect();
+        payload["actions"] = json!(actions);
+    }
+    payload
+}
+
+struct Gotify {
+    config: GotifyConfig,
+    client: reqwest::Client,
+}
+
+impl Notifier for Gotify {
+    fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a> {
+        Box::pin(async move {
+            let mut payload = json!({
+                "title": message.title,
+                "message": message.body,
+                "priority": GOTIFY_PRIORITIES[priority_index(message)],
+            });
+            if let Some(click) = &message.click {
+                payload["extras"] = json!({
+                    "client::notification": { "click": { "url": click } }
+                });
+            }
+
+            self.client
+                .post(format!("{}/message", self.config.url.trim_end_matches('/')))
+                .header("X-Gotify-Key", &self.config.token)
+                .json(&payload)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Posts a text message to a room through the client-server API.
+struct Matrix {
+    config: MatrixConfig,
+    client: reqwest::Client,
+    /// Transaction ids must be unique per access token, so they start
+    /// from the clock and count up
+    next_txn: AtomicU64,
+}
+
+impl Matrix {
+    fn new(config: MatrixConfig, client: reqwest::Client) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Self {
+            config,
+            client,
+            next_txn: AtomicU64::new(now as u64),
+        }
+    }
+}
+
+impl Notifier for Matrix {
+    fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a> {
+        Box::pin(async move {
+            let txn = format!("homed-{}", self.next_txn.fetch_add(1, Ordering::Relaxed));
+            let mut url = reqwest::Url::parse(&self.config.homeserver)
+                .map_err(|e| NotifyError::InvalidUrl(format!("{}: {e}", self.config.homeserver)))?;
+            url.path_segments_mut()
+                .map_err(|_| NotifyError::InvalidUrl(self.config.homeserver.clone()))?
+                .pop_if_empty()
+                .extend([
+                    "_matrix",
+                    "client",
+                    "v3",
+                    "rooms",
+                    &self.config.room_id,
+                    "send",
+                    "m.room.message",
+                    &txn,
+                ]);
+
+            let mut text = format!("{}\n{}", message.title, message.body);
+            if let Some(click) = &message.click {
+                text.push('\n');
+                text.push_str(click);
+            }
+
+            self.client
+                .put(url)
+                .bearer_auth(&self.config.access_token)
+                .json(&json!({ "msgtype": "m.text", "body": text }))
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// POSTs the message fields as JSON, with the configured headers.
+struct Webhook {
+    config: WebhookConfig,
+    client: reqwest::Client,
+}
+
+impl Notifier for Webhook {
+    fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a> {
+        Box::pin(async move {
+            let mut request = self.client.post(&self.config.url).json(&json!({
+                "kind": message.kind.name(),
+                "title": message.title,
+                "message": message.body,
+                "priority": message.priority,
+                "tags": message.tags,
+                "click": message.click,
+            }));
+            for (name, value) in &self.config.headers {
+                request = request.header(name, value);
+            }
+            request.send().await?.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+struct Smtp {
+    config: SmtpConfig,
+}
+
+impl Notifier for Smtp {
+    fn send<'a>(&'a self, message: &'a Message) -> SendFuture<'a> {
+        Box::pin(async move {
+            let timeout = Duration::from_secs(self.config.timeout_secs);
+            tokio::time::timeout(timeout, self.deliver(message))
+                .await
+                .map_err(|_| NotifyError::Timeout(timeout))?
+        })
+    }
+}
+
+impl Smtp {
+    async fn deliver(&self, message: &Message) -> Result<(), NotifyError> {
+        let conn = TcpStream::connect((self.config.host.as_str(), self.config.port)).await?;
+        let mut conn = BufReader::new(conn);
+
+        expect_reply(&mut conn, "greeting", b'2').await?;
+        let from = format!("MAIL FROM:<{}>", self.config.from);
+        smtp_command(&mut conn, "EHLO", "EHLO localhost", b'2').await?;
+        smtp_command(&mut conn, "MAIL FROM", &from, b'2').await?;
+        for to in &self.config.to {
+            smtp_command(&mut conn, "RCPT TO", &format!("RCPT TO:<{to}>"), b'2').await?;
+        }
+        smtp_command(&mut conn, "DATA", "DATA", b'3').await?;
+        conn.get_mut()
+            .write_all(email(&self.config, message).as_bytes())
+            .await?;
+        expect_reply(&mut conn, "message", b'2').await?;
+
+        // The message is accepted, so a failed QUIT doesn't matter
+        let _ = smtp_command(&mut conn, "QUIT", "QUIT", b'2').await;
+        Ok(())
+    }
+}
+
+/// Sends one command and expects a reply starting with `class`.
+async fn smtp_command(
+    conn: &mut BufReader<TcpStream>,
+    step: &'static str,
+    command: &str,
+    class: u8,
+) -> Result<(), NotifyError> {
+    conn.get_mut()
+        .write_all(format!("{command}\r\n").as_bytes())
+        .await?;
+    expect_reply(conn, step, class).await
+}
+
+/// Reads a possibly multi-line reply and checks its first digit.
+async fn expect_reply(
+    conn: &mut BufReader<TcpStream>,
+    step: &'static str,
+    class: u8,
+) -> Result<(), NotifyError> {
+    loop {
+        let mut line = String::new();
+        if conn.read_line(&mut line).await? == 0 {
+            return Err(NotifyError::SmtpError {
+                step,
+                reply: "connection closed".to_string(),
+            });
+        }
+        let line = line.trim_end();
+        if line.as_bytes().first() != Some(&class) {
+            return Err(NotifyError::SmtpError {
+                step,
+                reply: line.to_string(),
+            });
+        }
+        // `250-...` continues, `250 ...` ends the reply
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+/// The DATA section: headers, the dot-stuffed body and the final dot.
+fn email(config: &SmtpConfig, message: &Message) -> String {
+    let mut body = message.body.clone();
+    if let Some(click) = &message.click {
+        body.push_str("\n\n");
+        body.push_str(click);
+    }
+
+    let mut data = format!(
+        "From: {}\r\n\
+         To: {}\r\n\
+         Subject: {}\r\n\
+         Date: {}\r\n\
+         X-Priority: {}\r\n\
+         MIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Transfer-Encoding: 8bit\r\n\
+         \r\n",
+        config.from,
+        config.to.join(", "),
+        encode_header(&message.title),
+        chrono::Local::now().to_rfc2822(),
+        5 - priority_index(message),
+    );
+    for line in body.lines() {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data.push_str(".\r\n");
+    data
+}
+
+/// Keeps printable ASCII as is and base64 encodes anything else (file
+/// names, line breaks) as RFC 2047 encoded words.
+fn encode_header(text: &str) -> String {
+    if text.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        return text.to_string();
+    }
+
+    // Encoded words may be 75 characters at most
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in text.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?utf-8?B?{}?=", base64(chunk.as_bytes())));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?utf-8?B?{}?=", base64(chunk.as_bytes())));
+    }
+    words.join("\r\n ")
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AlertAction, AlertKind};
+    use crate::stub_http;
+    use tokio::net::TcpListener;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_http_notifiers_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        // Takes the request and never answers
+        let server = tokio::spawn(async move {
+            let (conn, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+            drop(conn);
+        });
+
+        let notifier = build(
+            NotifierBackend::Ntfy(NtfyConfig {
+                url,
+                topic: "homed".to_string(),
+                token: String::new(),
+            }),
+            http_client(),
+        );
+        let result = notifier.send(&message()).await;
+        assert!(
+            matches!(&result, Err(NotifyError::HttpError(e)) if e.is_timeout()),
+            "{result:?}"
+        );
+        server.abort();
+    }
+
+    fn message() -> Message {
+        Message {
+            kind: AlertKind::Quarantine,
+            title: "Quarantined Codec.exe".to_string(),
+            body: "executable extension blocked: .exe\n/mnt/media/Codec.exe".to_string(),
+            priority: 4,
+            tags: vec!["rotating_light".to_string()],
+            click: None,
+            actions: vec![AlertAction {
+                action: ActionKind::Http,
+                label: "Restore from quarantine".to_string(),
+                url: "https://homed.example.com/quarantine/abc/restore".to_string(),
+                method: "POST".to_string(),
+                headers: [("Authorization".to_string(), "Bearer api".to_string())].into(),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ntfy_publishes_json() {
+        let (url, requests) = stub_http::spawn(|_| Some("{}".to_string())).await;
+        let notifier = build(
+            NotifierBackend::Ntfy(NtfyConfig {
+                url: format!("{url}/"),
+                topic: "homed".to_string(),
+                token: "ntfy-token".to_string(),
+            }),
+            reqwest::Client::new(),
+        );
+        notifier.send(&message()).await.unwrap();
+
+        let request = requests.lock().unwrap().remove(0);
+        assert_eq!(request.line, "POST / HTTP/1.1");
+        assert_eq!(request.headers["authorization"], "Bearer ntfy-token");
+        assert_eq!(
+            request.json(),
+            json!({
+                "topic": "homed",
+                "title": "Quarantined Codec.exe",
+                "message": "executable extension blocked: .exe\n/mnt/media/Codec.exe",
+                "priority": 4,
+                "tags": ["rotating_light"],
+                "actions": [{
+                    "action": "http",
+                    "label": "Restore from quarantine",
+                    "url": "https://homed.example.com/quarantine/abc/restore",
+                    "method": "POST",
+                    "headers": { "Authorization": "Bearer api" },
+                    "clear": true,
+                }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gotify_message() {
+        let (url, requests) = stub_http::spawn(|_| Some("{}".to_string())).await;
+        let notifier = build(
+            NotifierBackend::Gotify(GotifyConfig {
+                url,
+                token: "app-token".to_string(),
+            }),
+            reqwest::Client::new(),
+        );
+        let mut message = message();
+        message.click = Some("https://grafana.example.com".to_string());
+        notifier.send(&message).await.unwrap();
+
+        let request = requests.lock().unwrap().remove(0);
+        assert_eq!(request.line, "POST /message HTTP/1.1");
+        assert_eq!(request.headers["x-gotify-key"], "app-token");
+        assert_eq!(request.json()["title"], "Quarantined Codec.exe");
+        assert_eq!(request.json()["priority"], 8);
+        assert_eq!(
+            request.json()["extras"]["client::notification"]["click"]["url"],
+            "https://grafana.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_matrix_room_message() {
+        let (url, requests) = stub_http::spawn(|_| Some("{}".to_string())).await;
+        let notifier = build(
+            NotifierBackend::Matrix(MatrixConfig {
+                homeserver: format!("{url}/"),
+                room_id: "!family:example.com".to_string(),
+                access_token: "syt_token".to_string(),
+            }),
+            reqwest::Client::new(),
+        );
+        notifier.send(&message()).await.unwrap();
+
+        let request = requests.lock().unwrap().remove(0);
+        assert!(
+            request.line.starts_with(
+                "PUT /_matrix/client/v3/rooms/!family:example.com/send/m.room.message/homed-"
+            ),
+            "{}",
+            request.line
+        );
+        assert_eq!(request.headers["authorization"], "Bearer syt_token");
+        assert_eq!(
+            request.json(),
+            json!({
+                "msgtype": "m.text",
+                "body": "Quarantined Codec.exe\nexecutable extension blocked: .exe\n/mnt/media/Codec.exe",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_fields_and_headers() {
+        let (url, requests) = stub_http::spawn(|_| Some("{}".to_string())).await;
+        let notifier = build(
+            NotifierBackend::Webhook(WebhookConfig {
+                url: format!("{url}/hooks/homed"),
+                headers: [("X-Api-Key".to_string(), "hook-key".to_string())].into(),
+            }),
+            reqwest::Client::new(),
+        );
+        notifier.send(&message()).await.unwrap();
+
+        let request = requests.lock().unwrap().remove(0);
+        assert_eq!(request.line, "POST /hooks/homed HTTP/1.1");
+        assert_eq!(request.headers["x-api-key"], "hook-key");
+        assert_eq!(request.json()["kind"], "quarantine");
+        assert_eq!(request.json()["priority"], 4);
+        assert!(request.json().get("actions").is_none());
+    }
+
+    /// Plays a relay that rejects recipients in `rejected` and returns the
+    /// transcript, message data included.
+    async fn stand_in_smtp(rejected: &'static str) -> (u16, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (conn, _) = listener.accept().await.unwrap();
+            let mut conn = BufReader::new(conn);
+            conn.get_mut()
+                .write_all(b"220 relay ESMTP\r\n")
+                .await
+                .unwrap();
+
+            let mut transcript = String::new();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if conn.read_line(&mut line).await.unwrap() == 0 {
+                    return transcript;
+                }
+                transcript.push_str(&line);
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-relay\r\n250 8BITMIME\r\n"
+                } else if line.starts_with("RCPT") && line.contains(rejected) {
+                    b"550 no such user\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    b"221 bye\r\n"
+                } else {
+                    b"250 ok\r\n"
+                };
+                conn.get_mut().write_all(reply).await.unwrap();
+            }
+        });
+        (port, server)
+    }
+
+    fn smtp_config(port: u16) -> SmtpConfig {
+        SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            from: "homed@example.com".to_string(),
+            to: vec!["admin@example.com".to_string()],
+            timeout_secs: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_smtp_delivery() {
+        let (port, server) = stand_in_smtp("nobody@").await;
+        let mut message = message();
+        message.kind = AlertKind::Malware;
+        message.title = "Malware quarantined: Film é.mkv".to_string();
+        message.body = "malware detected: Eicar\n.hidden".to_string();
+        message.priority = 5;
+
+        build(
+            NotifierBackend::Smtp(smtp_config(port)),
+            reqwest::Client::new(),
+        )
+        .send(&message)
+        .await
+        .unwrap();
+
+        let transcript = server.await.unwrap();
+        assert!(transcript.starts_with(
+            "EHLO localhost\r\nMAIL FROM:<homed@example.com>\r\nRCPT TO:<admin@example.com>\r\nDATA\r\n"
+        ));
+        assert!(transcript.contains("To: admin@example.com\r\n"));
+        assert!(transcript.contains(&format!(
+            "Subject: =?utf-8?B?{}?=\r\n",
+            base64("Malware quarantined: Film é.mkv".as_bytes())
+        )));
+        assert!(transcript.contains("X-Priority: 1\r\n"));
+        assert!(transcript.ends_with("\r\nmalware detected: Eicar\r\n..hidden\r\n.\r\nQUIT\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_smtp_rejected_recipient_fails() {
+        let (port, server) = stand_in_smtp("nobody@").await;
+        let mut config = smtp_config(port);
+        config.to.push("nobody@example.com".to_string());
+
+        let result = build(NotifierBackend::Smtp(config), reqwest::Client::new())
+            .send(&message())
+            .await;
+        match result {
+            Err(NotifyError::SmtpError { step, reply }) => {
+                assert_eq!(step, "RCPT TO");
+                assert_eq!(reply, "550 no such user");
+            }
+            other => panic!("expected SmtpError, got {other:?}"),
+        }
+        drop(server);
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
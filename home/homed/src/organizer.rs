@@ -5,8 +5,8 @@ use thiserror::Error;
 use tokio::sync::mpsc;
 use tracing::warn;
 
-use crate::config::OrganizerConfig;
-use crate::watcher::{FileEvent, MediaType};
+use crate::config::{OrganizerConfig, PathOverride};
+use crate::watcher::{FileEvent, MediaType, Pipeline};
 
 #[derive(Debug, Error)]
 pub enum OrganizerError {
@@ -20,15 +20,10 @@ pub enum OrganizerError {
 /// If that path exists, appends `_1`, `_2`, etc.
 fn build_target_path(
     config: &OrganizerConfig,
-    media_type: MediaType,
+    prefix: &str,
     datetime: &DateTime<FixedOffset>,
     extension: &str,
 ) -> PathBuf {
-    let prefix = match media_type {
-        MediaType::Photo => &config.photo_prefix,
-        MediaType::Video => &config.video_prefix,
-    };
-
     let year = format!("{}", datetime.format("%Y"));
     let month = format!("{}-{:02}", datetime.year(), datetime.month());
     let timestamp = format!("{}", datetime.format("%Y%m%d_%H%M%S"));
@@ -53,6 +48,24 @@ fn build_target_path(
     unreachable!()
 }
 
+/// Filename prefix for a file, preferring a per-path override.
+fn prefix_for<'a>(
+    config: &'a OrganizerConfig,
+    overrides: &'a [PathOverride],
+    path: &Path,
+    media_type: MediaType,
+) -> &'a str {
+    let path_override = PathOverride::for_path(overrides, path);
+    match media_type {
+        MediaType::Photo => path_override
+            .and_then(|o| o.photo_prefix.as_deref())
+            .unwrap_or(&config.photo_prefix),
+        MediaType::Video => path_override
+            .and_then(|o| o.video_prefix.as_deref())
+            .unwrap_or(&config.video_prefix),
+    }
+}
+
 /// Moves a file across filesystems safely by copy -> sync -> delete.
 ///
 /// `tokio::fs::rename` only works within the same filesystem (SSD→SSD).
@@ -143,12 +156,14 @@ async fn apply_ownership(path: &Path, owner: &str, group: &str) {
 /// Handles collisions by appending _1, _2, etc.
 async fn handle_unsorted(
     config: &OrganizerConfig,
+    pipeline: Pipeline,
     path: &Path,
     tx: &mpsc::Sender<FileEvent>,
 ) -> Result<(), OrganizerError> {
     let Some(unsorted_dir) = &config.unsorted_dir else {
         let _ = tx
             .send(FileEvent::Failed {
+                pipeline,
                 path: path.to_path_buf(),
                 error: "No valid date and unsorted_dir not configured".to_string(),
             })
@@ -170,6 +185,7 @@ async fn handle_unsorted(
 
             let _ = tx
                 .send(FileEvent::Organized {
+                    pipeline,
                     old_path: path.to_path_buf(),
                     new_path: target,
                 })
@@ -178,6 +194,7 @@ async fn handle_unsorted(
         Err(e) => {
             let _ = tx
                 .send(FileEvent::Failed {
+                    pipeline,
                     path: path.to_path_buf(),
                     error: format!("Failed to move to unsorted: {}", e),
                 })
@@ -191,6 +208,7 @@ async fn handle_unsorted(
 /// Organizes files into date-based directories with timestamp naming.
 pub async fn run_organizer(
     config: OrganizerConfig,
+    overrides: Vec<PathOverride>,
     mut rx: mpsc::Receiver<FileEvent>,
     tx: mpsc::Sender<FileEvent>,
     mut shutdown: tokio::sync::broadcast::Receiver<()>,
@@ -209,6 +227,7 @@ pub async fn run_organizer(
 
         match event {
             FileEvent::Enriched {
+                pipeline,
                 path,
                 media_type,
                 datetime,
@@ -219,7 +238,8 @@ pub async fn run_organizer(
                     .unwrap_or("bin")
                     .to_ascii_lowercase();
 
-                let target = build_target_path(&config, media_type, &datetime, &extension);
+                let prefix = prefix_for(&config, &overrides, &path, media_type);
+                let target = build_target_path(&config, prefix, &datetime, &extension);
 
                 match move_safe(&path, &target).await {
                     Ok(()) => {
@@ -230,6 +250,7 @@ pub async fn run_organizer(
 
                         let _ = tx
                             .send(FileEvent::Organized {
+                                pipeline,
                                 old_path: path,
                                 new_path: target,
                             })
@@ -238,6 +259,7 @@ pub async fn run_organizer(
                     Err(e) => {
                         let _ = tx
                             .send(FileEvent::Failed {
+                                pipeline,
                                 path,
                                 error: format!("Failed to organize: {}", e),
                             })
@@ -245,8 +267,8 @@ pub async fn run_organizer(
                     }
                 }
             }
-            FileEvent::Unsorted { path, .. } => {
-                let _ = handle_unsorted(&config, &path, &tx).await;
+            FileEvent::Unsorted { pipeline, path, .. } => {
+                let _ = handle_unsorted(&config, pipeline, &path, &tx).await;
             }
             other => {
                 let _ = tx.send(other).await;
@@ -263,6 +285,42 @@ mod tests {
     use std::ffi::OsStr;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_prefix_override_applies_to_path() {
+        let config = OrganizerConfig {
+            enabled: true,
+            photos_dir: PathBuf::from("/photos"),
+            photo_prefix: "IMG".to_string(),
+            video_prefix: "VID".to_string(),
+            photo_extensions: vec![],
+            video_extensions: vec![],
+            file_owner: None,
+            file_group: None,
+            min_valid_year: 2000,
+            unsorted_dir: None,
+        };
+        let overrides = vec![PathOverride {
+            path: PathBuf::from("/uploads/WhatsApp"),
+            photo_prefix: Some("WA".to_string()),
+            ..Default::default()
+        }];
+
+        let whatsapp = Path::new("/uploads/WhatsApp/pic.jpg");
+        let camera = Path::new("/uploads/Camera/pic.jpg");
+        assert_eq!(
+            prefix_for(&config, &overrides, whatsapp, MediaType::Photo),
+            "WA"
+        );
+        assert_eq!(
+            prefix_for(&config, &overrides, whatsapp, MediaType::Video),
+            "VID"
+        );
+        assert_eq!(
+            prefix_for(&config, &overrides, camera, MediaType::Photo),
+            "IMG"
+        );
+    }
+
     #[test]
     fn test_build_unsorted_path_no_collision() {
         let dir = tempdir().unwrap();
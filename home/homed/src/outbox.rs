@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::alerts::Message;
+
+/// The first retry waits this long, doubling with each failure after it.
+const RETRY_INITIAL: Duration = Duration::from_secs(10);
+const RETRY_MAX: Duration = Duration::from_secs(30 * 60);
+/// Alerts kept per notifier; the oldest are dropped beyond this.
+const MAX_QUEUED: usize = 200;
+
+#[derive(Debug, Error)]
+pub enum OutboxError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("invalid queue file: {0}")]
+    ParseError(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Queued {
+    notifier: String,
+    message: Message,
+    /// Unix seconds
+    queued_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    items: VecDeque<Queued>,
+    /// Failed attempts since the notifier last worked
+    failures: u32,
+    retry_at: Option<Instant>,
+    /// Alerts delivered late since the notifier came back, and the longest
+    /// wait among them
+    delayed: usize,
+    longest_delay: Duration,
+}
+
+/// Alerts waiting for delivery, per notifier, with the backoff after
+/// failures. An alert leaves the queue only once its notifier took it.
+#[derive(Debug)]
+pub struct Outbox {
+    path: Option<PathBuf>,
+    queues: BTreeMap<String, Queue>,
+}
+
+impl Outbox {
+    /// Reads the alerts left over from the last run, dropping those for
+    /// notifiers that are gone. Blocking.
+    pub fn open(path: Option<PathBuf>, notifiers: &[&str]) -> Self {
+        let mut outbox = Self {
+            path,
+            queues: notifiers
+                .iter()
+                .map(|name| (name.to_string(), Queue::default()))
+                .collect(),
+        };
+        let Some(path) = &outbox.path else {
+            return outbox;
+        };
+
+        let items = match read_queue(path) {
+            Ok(items) => items,
+            Err(e) => {
+                warn!(
+                    path = %path.display(),
+                    error = %e,
+                    "failed to read alert queue, starting empty"
+                );
+                return outbox;
+            }
+        };
+        let mut loaded = 0;
+        for item in items {
+            match outbox.queues.get_mut(&item.notifier) {
+                Some(queue) => {
+                    queue.items.push_back(item);
+                    // Retried right away and counted as delayed once delivered
+                    queue.failures = 1;
+                    queue.retry_at = Some(Instant::now());
+                    loaded += 1;
+                }
+                None => {
+                    warn!(
+                        notifier = item.notifier,
+                        "dropping queued alert for removed notifier"
+                    );
+                }
+            }
+        }
+        if loaded > 0 {
+            info!(alerts = loaded, "loaded undelivered alerts");
+        }
+
+        outbox
+    }
+
+    /// Queues a message unless the same one is already waiting for this
+    /// notifier. Returns whether it was queued.
+    pub fn push(&mut self, notifier: &str, message: Message) -> bool {
+        let Some(queue) = self.queues.get_mut(notifier) else {
+            return false;
+        };
+        let duplicate = queue.items.iter().any(|item| {
+            item.message.kind == message.kind
+                && item.message.title == message.title
+                && item.message.body == message.body
+        });
+        if duplicate {
+            return false;
+        }
+
+        if queue.items.len() >= MAX_QUEUED {
+            queue.items.pop_front();
+            warn!(notifier, "alert queue full, dropped the oldest alert");
+        }
+        queue.items.push_back(Queued {
+            notifier: notifier.to_string(),
+            message,
+            queued_at: unix_now(),
+        });
+        true
+    }
+
+    /// Whether the notifier is outside its backoff.
+    pub fn is_due(&self, notifier: &str) -> bool {
+        self.queues
+            .get(notifier)
+            .and_then(|queue| queue.retry_at)
+            .is_none_or(|retry_at| retry_at <= Instant::now())
+    }
+
+    /// Notifiers in backoff whose retry time has come.
+    pub fn due(&self) -> Vec<String> {
+        let now = Instant::now();
+        self.queues
+            .iter()
+            .filter(|(_, queue)| queue.retry_at.is_some_and(|retry_at| retry_at <= now))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The earliest pending retry.
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.queues
+            .values()
+            .filter_map(|queue| queue.retry_at)
+            .min()
+    }
+
+    pub fn front(&self, notifier: &str) -> Option<Message> {
+        let item = self.queues.get(notifier)?.items.front()?;
+        Some(item.message.clone())
+    }
+
+    /// Drops the front message after the notifier took it.
+    pub fn delivered(&mut self, notifier: &str) {
+        let Some(queue) = self.queues.get_mut(notifier) else {
+            return;
+        };
+        let Some(item) = queue.items.pop_front() else {
+            return;
+        };
+        if queue.failures > 0 {
+            queue.delayed += 1;
+            let waited = Duration::from_secs(unix_now().saturating_sub(item.queued_at));
+            queue.longest_delay = queue.longest_delay.max(waited);
+        }
+    }
+
+    /// Backs off after a failed attempt and returns the wait.
+    pub fn failed(&mut self, notifier: &str) -> Duration {
+        let Some(queue) = self.queues.get_mut(notifier) else {
+            return RETRY_INITIAL;
+        };
+        let wait = backoff(queue.failures);
+        queue.failures += 1;
+        queue.retry_at = Some(Instant::now() + wait);
+        wait
+    }
+
+    /// How many alerts were delivered late, and by up to how long, once a
+    /// notifier that failed has caught up.
+    pub fn resumed(&self, notifier: &str) -> Option<(usize, Duration)> {
+        let queue = self.queues.get(notifier)?;
+        (queue.items.is_empty() && queue.delayed > 0)
+            .then_some((queue.delayed, queue.longest_delay))
+    }
+
+    /// Clears the backoff once the queue is empty and the note was sent.
+    pub fn recovered(&mut self, notifier: &str) {
+        if let Some(queue) = self.queues.get_mut(notifier) {
+            *queue = Queue::default();
+        }
+    }
+
+    /// Writes the undelivered alerts to the queue file, if there is one.
+    pub async fn save(&self) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        let items: Vec<&Queued> = self.queues.values().flat_map(|q| &q.items).collect();
+        let json = match serde_json::to_vec_pretty(&items) {
+            Ok(json) => json,
+            Err(e) => {
+                warn!(error = %e, "failed to serialize alert queue");
+                return;
+            }
+        };
+
+        let result = tokio::task::spawn_blocking(move || write_queue(&path, &json))
+            .await
+            .expect("alert queue write panicked");
+        if let Err(e) = result {
+            warn!(error = %e, "failed to write alert queue");
+        }
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    RETRY_INITIAL
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(RETRY_MAX)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn read_queue(path: &Path) -> Result<Vec<Queued>, OutboxError> {
+    match std::fs::read(path) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_queue(path: &Path, json: &[u8]) -> Result<(), OutboxError> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, json)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AlertKind;
+
+    fn message(title: &str) -> Message {
+        Message {
+            kind: AlertKind::Summary,
+            title: title.to_string(),
+            body: "Photos: 1 organized".to_string(),
+            priority: 3,
+            tags: Vec::new(),
+            click: None,
+            actions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        assert_eq!(backoff(0), Duration::from_secs(10));
+        assert_eq!(backoff(1), Duration::from_secs(20));
+        assert_eq!(backoff(3), Duration::from_secs(80));
+        assert_eq!(backoff(8), RETRY_MAX);
+        assert_eq!(backoff(u32::MAX), RETRY_MAX);
+    }
+
+    #[tokio::test]
+    async fn test_queue_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("alerts.json");
+
+        let mut outbox = Outbox::open(Some(path.clone()), &["ntfy", "mail"]);
+        assert!(outbox.push("ntfy", message("first")));
+        assert!(!outbox.push("ntfy", message("first")));
+        assert!(outbox.push("ntfy", message("second")));
+        assert!(outbox.push("mail", message("first")));
+        outbox.failed("ntfy");
+        assert!(!outbox.is_due("ntfy"));
+        assert!(outbox.is_due("mail"));
+        outbox.save().await;
+
+        // The mail notifier was removed from the config in between
+        let mut outbox = Outbox::open(Some(path), &["ntfy"]);
+        assert_eq!(outbox.due(), ["ntfy"]);
+        assert_eq!(outbox.front("ntfy").unwrap().title, "first");
+        outbox.delivered("ntfy");
+        assert_eq!(outbox.resumed("ntfy"), None);
+        assert_eq!(outbox.front("ntfy").unwrap().title, "second");
+        outbox.delivered("ntfy");
+        assert!(matches!(outbox.resumed("ntfy"), Some((2, _))));
+        assert_eq!(outbox.front("mail"), None);
+    }
+}
@@ -0,0 +1,569 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tracing::{info, warn};
+
+const RECORD_EXT: &str = "json";
+const PAYLOAD_EXT: &str = "data";
+const RELEASED_FILE: &str = "released";
+
+#[derive(Debug, Error)]
+pub enum QuarantineError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("invalid quarantine record: {0}")]
+    InvalidRecord(#[from] serde_json::Error),
+
+    #[error("no quarantined item with id {0}")]
+    NotFound(String),
+
+    #[error("restore target already exists: {0}")]
+    TargetExists(PathBuf),
+}
+
+/// Metadata kept next to every quarantined file or release directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineRecord {
+    pub id: String,
+    pub original_path: PathBuf,
+    pub reason: String,
+    pub quarantined_at: DateTime<Utc>,
+    pub is_dir: bool,
+    /// Every file and directory of the payload, relative to it. A single
+    /// quarantined file has one entry with an empty path.
+    pub entries: Vec<QuarantineEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// `None` for directories.
+    pub sha256: Option<String>,
+    /// Permission bits before they were stripped.
+    pub mode: u32,
+}
+
+/// Quarantined payloads live as `<id>.data` next to an `<id>.json` record,
+/// so files with the same name never overwrite each other. All methods
+/// are blocking.
+#[derive(Debug, Clone)]
+pub struct QuarantineStore {
+    dir: PathBuf,
+}
+
+impl QuarantineStore {
+    pub fn open(dir: &Path) -> Result<Self, QuarantineError> {
+        fs::create_dir_all(dir)?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.{RECORD_EXT}"))
+    }
+
+    fn payload_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.{PAYLOAD_EXT}"))
+    }
+
+    /// Hashes a file or directory, moves it into the store and strips its
+    /// permissions.
+    pub fn quarantine(
+        &self,
+        path: &Path,
+        reason: &str,
+    ) -> Result<QuarantineRecord, QuarantineError> {
+        self.quarantine_as(path, reason, self.new_id(path))
+    }
+
+    fn quarantine_as(
+        &self,
+        path: &Path,
+        reason: &str,
+        id: String,
+    ) -> Result<QuarantineRecord, QuarantineError> {
+        let is_dir = fs::symlink_metadata(path)?.is_dir();
+        let entries = collect_entries(path)?;
+
+        let record = QuarantineRecord {
+            id: id.clone(),
+            original_path: path.to_path_buf(),
+            reason: reason.to_string(),
+            quarantined_at: Utc::now(),
+            is_dir,
+            entries,
+        };
+
+        // The record is staged before the move and committed after it, so
+        // a failure on either side never leaves a payload without a record
+        let staged = self.stage_record(&record)?;
+        let payload = self.payload_path(&id);
+        if let Err(e) = move_item(path, &payload, is_dir) {
+            let _ = fs::remove_file(&staged);
+            return Err(e.into());
+        }
+        let committed = set_modes(&payload, &record.entries, |entry| {
+            if entry.sha256.is_some() { 0o000 } else { 0o700 }
+        })
+        .and_then(|()| fs::rename(&staged, self.record_path(&id)));
+        if let Err(e) = committed {
+            let _ = fs::remove_file(&staged);
+            if let Err(undo) = set_modes(&payload, &record.entries, |entry| entry.mode)
+                .and_then(|()| move_item(&payload, path, is_dir))
+            {
+                warn!(
+                    id,
+                    path = %path.display(),
+                    error = %undo,
+                    "failed to move quarantined item back after its record failed"
+                );
+            }
+            return Err(e.into());
+        }
+
+        Ok(record)
+    }
+
+    /// Timestamp plus a short hash of the path, bumped until unused.
+    fn new_id(&self, path: &Path) -> String {
+        let now = Utc::now();
+        let mut hasher = Sha256::new();
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update(now.timestamp_nanos_opt().unwrap_or_default().to_le_bytes());
+        let digest = format!("{:x}", hasher.finalize());
+
+        let base = format!("{}-{}", now.format("%Y%m%d-%H%M%S"), &digest[..8]);
+        let mut id = base.clone();
+        let mut n = 1;
+        while self.record_path(&id).exists() || self.payload_path(&id).exists() {
+            id = format!("{base}-{n}");
+            n += 1;
+        }
+        id
+    }
+
+    /// Writes a record next to where it goes, for a rename to commit.
+    fn stage_record(&self, record: &QuarantineRecord) -> Result<PathBuf, QuarantineError> {
+        let tmp = self.dir.join(format!("{}.tmp", record.id));
+        fs::write(&tmp, serde_json::to_vec_pretty(record)?)?;
+        Ok(tmp)
+    }
+
+    /// All records, oldest first.
+    pub fn list(&self) -> Result<Vec<QuarantineRecord>, QuarantineError> {
+        let mut records = Vec::new();
+        for entry in fs::read_dir(&self.dir)?.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(RECORD_EXT) {
+                continue;
+            }
+            match fs::read(&path)
+                .map_err(QuarantineError::from)
+                .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+            {
+                Ok(record) => records.push(record),
+                Err(e) => warn!(path = %path.display(), error = %e, "skipping quarantine record"),
+            }
+        }
+        records.sort_by_key(|record: &QuarantineRecord| record.quarantined_at);
+        Ok(records)
+    }
+
+    pub fn get(&self, id: &str) -> Result<QuarantineRecord, QuarantineError> {
+        let bytes = match fs::read(self.record_path(id)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(QuarantineError::NotFound(id.to_string()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Moves an item back to its original location (or `target`) with its
+    /// permissions restored, and marks it released so the scanner doesn't
+    /// quarantine it again when the watcher sees it reappear.
+    pub fn restore(&self, id: &str, target: Option<&Path>) -> Result<PathBuf, QuarantineError> {
+        let record = self.get(id)?;
+        let target = target.unwrap_or(&record.original_path).to_path_buf();
+        if target.exists() {
+            return Err(QuarantineError::TargetExists(target));
+        }
+
+        let payload = self.payload_path(id);
+        set_modes(&payload, &record.entries, |entry| entry.mode)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_item(&payload, &target, record.is_dir)?;
+
+        let mut released = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(RELEASED_FILE))?;
+        for entry in record.entries.iter().filter(|e| e.sha256.is_some()) {
+            let path = entry_path(&target, &entry.path);
+            writeln!(released, "{}\t{}", entry.size, path.display())?;
+        }
+
+        fs::remove_file(self.record_path(id))?;
+        Ok(target)
+    }
+
+    /// Deletes an item and its record for good.
+    pub fn purge(&self, id: &str) -> Result<QuarantineRecord, QuarantineError> {
+        let record = self.get(id)?;
+        let payload = self.payload_path(id);
+
+        if record.is_dir {
+            fs::remove_dir_all(&payload)?;
+        } else {
+            fs::remove_file(&payload)?;
+        }
+        fs::remove_file(self.record_path(id))?;
+        Ok(record)
+    }
+
+    /// Purges everything quarantined longer than `max_age` ago.
+    pub fn purge_expired(
+        &self,
+        max_age: Duration,
+    ) -> Result<Vec<QuarantineRecord>, QuarantineError> {
+        let cutoff =
+            Utc::now() - chrono::Duration::from_std(max_age).unwrap_or(chrono::TimeDelta::MAX);
+        let mut purged = Vec::new();
+        for record in self.list()? {
+            if record.quarantined_at < cutoff {
+                purged.push(self.purge(&record.id)?);
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Whether `path` was restored by hand and should skip scanning. Each
+    /// release is consumed by the first match.
+    pub fn take_released(&self, path: &Path, size: u64) -> Result<bool, QuarantineError> {
+        let released_path = self.dir.join(RELEASED_FILE);
+        let file = match fs::File::open(&released_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        let wanted = format!("{size}\t{}", path.display());
+        let mut lines: Vec<String> = BufReader::new(file).lines().collect::<Result<_, _>>()?;
+        let Some(index) = lines.iter().position(|line| *line == wanted) else {
+            return Ok(false);
+        };
+        lines.remove(index);
+
+        if lines.is_empty() {
+            fs::remove_file(&released_path)?;
+        } else {
+            let tmp = released_path.with_extension("tmp");
+            fs::write(&tmp, lines.join("\n") + "\n")?;
+            fs::rename(&tmp, &released_path)?;
+        }
+        Ok(true)
+    }
+}
+
+fn collect_entries(root: &Path) -> std::io::Result<Vec<QuarantineEntry>> {
+    let mut entries = Vec::new();
+    let mut stack = vec![PathBuf::new()];
+
+    while let Some(relative) = stack.pop() {
+        let path = entry_path(root, &relative);
+        let metadata = fs::symlink_metadata(&path)?;
+        let mode = metadata.permissions().mode() & 0o7777;
+
+        if metadata.is_dir() {
+            for child in fs::read_dir(&path)?.flatten() {
+                stack.push(relative.join(child.file_name()));
+            }
+            entries.push(QuarantineEntry {
+                path: relative,
+                size: 0,
+                sha256: None,
+                mode,
+            });
+        } else if metadata.is_file() {
+            entries.push(QuarantineEntry {
+                path: relative,
+                size: metadata.len(),
+                sha256: Some(sha256_file(&path)?),
+                mode,
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Renames, falling back to copy and delete for single files on another
+/// filesystem (e.g. photo uploads outside the media disk).
+fn move_item(from: &Path, to: &Path, is_dir: bool) -> std::io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if !is_dir && e.raw_os_error() == Some(libc::EXDEV) => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// `Path::join("")` adds a trailing slash, which breaks single files.
+fn entry_path(root: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(relative)
+    }
+}
+
+/// Applies a mode to every entry, files before their directories so a
+/// stripped directory never blocks access to its children.
+fn set_modes(
+    root: &Path,
+    entries: &[QuarantineEntry],
+    mode: impl Fn(&QuarantineEntry) -> u32,
+) -> std::io::Result<()> {
+    for entry in entries.iter().rev() {
+        fs::set_permissions(
+            entry_path(root, &entry.path),
+            fs::Permissions::from_mode(mode(entry)),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// `homed quarantine <list|show|restore|purge>`.
+pub fn run_command(store: &QuarantineStore, args: &[String]) -> anyhow::Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["list"] => {
+            for record in store.list()? {
+                println!(
+                    "{}  {}  {}  ({})",
+                    record.id,
+                    record.quarantined_at.format("%Y-%m-%d %H:%M"),
+                    record.original_path.display(),
+                    record.reason
+                );
+            }
+        }
+        ["show", id] => {
+            println!("{}", serde_json::to_string_pretty(&store.get(id)?)?);
+        }
+        ["restore", id] => {
+            let target = store.restore(id, None)?;
+            println!("restored {id} to {}", target.display());
+        }
+        ["restore", id, "--to", target] => {
+            let target = store.restore(id, Some(Path::new(target)))?;
+            println!("restored {id} to {}", target.display());
+        }
+        ["purge", "--expired", days] => {
+            let days: u64 = days.parse()?;
+            for record in store.purge_expired(Duration::from_secs(days * 86_400))? {
+                println!("purged {}", record.id);
+            }
+        }
+        ["purge", id] => {
+            store.purge(id)?;
+            println!("purged {id}");
+        }
+        _ => anyhow::bail!(
+            "usage: homed quarantine list | show <id> | restore <id> [--to <path>] | purge <id> | purge --expired <days>"
+        ),
+    }
+
+    Ok(())
+}
+
+/// Logs what an expiry sweep removed.
+pub fn log_purged(purged: &[QuarantineRecord]) {
+    for record in purged {
+        info!(
+            id = record.id,
+            path = %record.original_path.display(),
+            "expired quarantine item purged"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (tempfile::TempDir, QuarantineStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = QuarantineStore::open(&dir.path().join("quarantine")).unwrap();
+        (dir, store)
+    }
+
+    #[test]
+    fn test_same_name_does_not_overwrite() {
+        let (dir, store) = store();
+        let a = dir.path().join("a/movie.mkv");
+        let b = dir.path().join("b/movie.mkv");
+        for path in [&a, &b] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, path.to_string_lossy().as_bytes()).unwrap();
+        }
+
+        let first = store.quarantine(&a, "type mismatch").unwrap();
+        let second = store.quarantine(&b, "type mismatch").unwrap();
+
+        assert_ne!(first.id, second.id);
+        let records = store.list().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].original_path, a);
+        assert_eq!(
+            records[0].entries[0].sha256.as_deref(),
+            Some(
+                sha256_file(&store.payload_path(&first.id))
+                    .unwrap()
+                    .as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn test_permissions_stripped_and_restored() {
+        let (dir, store) = store();
+        let path = dir.path().join("movie.mkv");
+        fs::write(&path, b"data").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let record = store.quarantine(&path, "corrupt").unwrap();
+        let payload = store.payload_path(&record.id);
+        assert_eq!(
+            fs::metadata(&payload).unwrap().permissions().mode() & 0o777,
+            0
+        );
+        assert!(!path.exists());
+
+        store.restore(&record.id, None).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        assert!(matches!(
+            store.get(&record.id),
+            Err(QuarantineError::NotFound(_))
+        ));
+
+        assert!(store.take_released(&path, 4).unwrap());
+        assert!(!store.take_released(&path, 4).unwrap());
+    }
+
+    #[test]
+    fn test_release_directory_roundtrip() {
+        let (dir, store) = store();
+        let release = dir.path().join("Show.S01");
+        fs::create_dir_all(release.join("Subs")).unwrap();
+        fs::write(release.join("episode.mkv"), b"video").unwrap();
+        fs::write(release.join("Subs/episode.srt"), b"subs").unwrap();
+
+        let record = store
+            .quarantine(&release, "release contains executable")
+            .unwrap();
+        assert!(record.is_dir);
+        assert_eq!(record.entries.len(), 4);
+
+        let target = dir.path().join("restored");
+        store.restore(&record.id, Some(&target)).unwrap();
+        assert_eq!(fs::read(target.join("Subs/episode.srt")).unwrap(), b"subs");
+    }
+
+    #[test]
+    fn test_restore_refuses_to_overwrite() {
+        let (dir, store) = store();
+        let path = dir.path().join("movie.mkv");
+        fs::write(&path, b"data").unwrap();
+        let record = store.quarantine(&path, "corrupt").unwrap();
+        fs::write(&path, b"new download").unwrap();
+
+        let result = store.restore(&record.id, None);
+        assert!(matches!(result, Err(QuarantineError::TargetExists(_))));
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let (dir, store) = store();
+        let release = dir.path().join("Movie");
+        fs::create_dir_all(&release).unwrap();
+        fs::write(release.join("movie.mkv"), b"data").unwrap();
+        let record = store.quarantine(&release, "corrupt").unwrap();
+
+        assert!(
+            store
+                .purge_expired(Duration::from_secs(3600))
+                .unwrap()
+                .is_empty()
+        );
+
+        let mut old = store.get(&record.id).unwrap();
+        old.quarantined_at -= chrono::Duration::days(31);
+        let staged = store.stage_record(&old).unwrap();
+        fs::rename(staged, store.record_path(&old.id)).unwrap();
+
+        let purged = store
+            .purge_expired(Duration::from_secs(30 * 86_400))
+            .unwrap();
+        assert_eq!(purged.len(), 1);
+        assert!(!store.payload_path(&record.id).exists());
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failed_record_leaves_item_in_place() {
+        let (dir, store) = store();
+        let file = dir.path().join("movie.mkv");
+        fs::write(&file, b"video").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+
+        // A directory where the record goes makes committing it fail
+        let id = "20260301-120000-abcd1234".to_string();
+        fs::create_dir(store.record_path(&id)).unwrap();
+        fs::write(store.record_path(&id).join("x"), b"").unwrap();
+        assert!(store.quarantine_as(&file, "test", id.clone()).is_err());
+
+        assert_eq!(fs::read(&file).unwrap(), b"video");
+        assert_eq!(
+            fs::metadata(&file).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        assert!(!store.payload_path(&id).exists());
+        assert!(!store.dir.join(format!("{id}.tmp")).exists());
+        assert!(store.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::checks;
+use crate::config::{MediaClass, ReleaseConfig};
+
+/// Bidirectional formatting characters, used to make `gpj.exe` display as
+/// `exe.jpg`.
+const BIDI_CONTROLS: &[char] = &[
+    '\u{202A}', '\u{202B}', '\u{202C}', '\u{202D}', '\u{202E}', '\u{2066}', '\u{2067}', '\u{2068}',
+    '\u{2069}',
+];
+
+/// Something about a release that marks it as fake or malicious.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    DoubleExtension(String),
+    BidiControl(String),
+    Executable(String),
+    LureFile(String),
+    SampleOnly,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DoubleExtension(name) => write!(f, "double extension: {name}"),
+            Self::BidiControl(name) => {
+                write!(f, "bidirectional control character in name: {name:?}")
+            }
+            Self::Executable(name) => write!(f, "executable: {name}"),
+            Self::LureFile(name) => write!(f, "lure file: {name}"),
+            Self::SampleOnly => write!(f, "only sample videos"),
+        }
+    }
+}
+
+/// Runs every release heuristic over the files of `dir`. Names in the
+/// findings are relative to `dir`.
+pub fn check_release(
+    dir: &Path,
+    files: &[(PathBuf, u64)],
+    config: &ReleaseConfig,
+    block_executables: bool,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut videos = 0;
+    let mut samples = 0;
+
+    for (path, _) in files {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        let display = relative.to_string_lossy().into_owned();
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        if display.contains(BIDI_CONTROLS) {
+            findings.push(Finding::BidiControl(display.clone()));
+        }
+
+        let executable = checks::check_executable_extension(path).is_err();
+        if executable && has_media_inner_extension(&name) {
+            findings.push(Finding::DoubleExtension(display));
+            continue;
+        }
+        if executable && block_executables {
+            findings.push(Finding::Executable(display));
+            continue;
+        }
+        // `Password.2019.1080p.mkv` is a film, not a lure
+        let ext = name.rsplit_once('.').map(|(_, e)| e).unwrap_or("");
+        let media = MediaClass::of(ext);
+        if media.is_none()
+            && config
+                .lure_patterns
+                .iter()
+                .any(|p| name.contains(&p.to_lowercase()))
+        {
+            findings.push(Finding::LureFile(display));
+            continue;
+        }
+
+        if media == Some(MediaClass::Video) {
+            videos += 1;
+            if is_sample(relative) {
+                samples += 1;
+            }
+        }
+    }
+
+    if config.reject_sample_only && videos > 0 && samples == videos {
+        findings.push(Finding::SampleOnly);
+    }
+
+    findings
+}
+
+/// Joins findings into one quarantine reason.
+pub fn report(findings: &[Finding]) -> String {
+    let details: Vec<String> = findings.iter().map(Finding::to_string).collect();
+    format!("suspicious release: {}", details.join("; "))
+}
+
+/// `movie.mkv.scr`: a media extension right before the real one.
+fn has_media_inner_extension(name: &str) -> bool {
+    let mut parts = name.rsplit('.');
+    parts.next();
+    match (parts.next(), parts.next()) {
+        (Some(inner), Some(_)) => MediaClass::of(inner).is_some(),
+        _ => false,
+    }
+}
+
+/// A video named like a sample or sitting in a `Sample` directory.
+fn is_sample(relative: &Path) -> bool {
+    relative.iter().any(|part| {
+        part.to_string_lossy()
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| word == "sample")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn release(names: &[&str]) -> (PathBuf, Vec<(PathBuf, u64)>) {
+        let dir = PathBuf::from("/downloads/Movie.2024.1080p");
+        let files = names.iter().map(|n| (dir.join(n), 1024)).collect();
+        (dir, files)
+    }
+
+    fn release_config() -> ReleaseConfig {
+        ReleaseConfig {
+            lure_patterns: vec!["password".to_string(), "codec".to_string()],
+            ..ReleaseConfig::default()
+        }
+    }
+
+    fn findings(names: &[&str]) -> Vec<Finding> {
+        let (dir, files) = release(names);
+        check_release(&dir, &files, &release_config(), true)
+    }
+
+    #[test]
+    fn test_clean_release_has_no_findings() {
+        let found = findings(&[
+            "Movie.2024.1080p.mkv",
+            "Movie.2024.1080p.en.srt",
+            "Sample/movie-sample.mkv",
+        ]);
+        assert!(found.is_empty(), "{found:?}");
+    }
+
+    #[test]
+    fn test_fake_release_reports_everything() {
+        let found = findings(&[
+            "Movie.2024.1080p.mkv.scr",
+            "Codec.exe",
+            "Password.txt",
+            "sample.mkv",
+        ]);
+        assert_eq!(
+            found,
+            vec![
+                Finding::DoubleExtension("Movie.2024.1080p.mkv.scr".to_string()),
+                Finding::Executable("Codec.exe".to_string()),
+                Finding::LureFile("Password.txt".to_string()),
+                Finding::SampleOnly,
+            ]
+        );
+        assert!(report(&found).starts_with("suspicious release: double extension"));
+    }
+
+    #[test]
+    fn test_media_files_are_not_lures() {
+        let found = findings(&["Password.2019.1080p.mkv", "Password.2019.1080p.en.srt"]);
+        assert!(found.is_empty(), "{found:?}");
+
+        let (dir, files) = release(&["Movie.2024.1080p.mkv", "Password.txt"]);
+        assert!(check_release(&dir, &files, &ReleaseConfig::default(), true).is_empty());
+    }
+
+    #[test]
+    fn test_bidi_override_flagged() {
+        let found = findings(&["Movie.2024.1080p.mkv", "Subs\u{202E}trs.exe"]);
+        assert_eq!(
+            found[0],
+            Finding::BidiControl("Subs\u{202E}trs.exe".to_string())
+        );
+        assert_eq!(
+            found[1],
+            Finding::Executable("Subs\u{202E}trs.exe".to_string())
+        );
+    }
+
+    #[test]
+    fn test_sample_only_can_be_allowed() {
+        let (dir, files) = release(&["Movie.Sample.mkv"]);
+        let config = ReleaseConfig {
+            reject_sample_only: false,
+            ..release_config()
+        };
+        assert!(check_release(&dir, &files, &config, true).is_empty());
+        assert_eq!(findings(&["Movie.Sample.mkv"]), vec![Finding::SampleOnly]);
+    }
+}
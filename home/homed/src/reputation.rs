@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::checks::ScanRejection;
+use crate::config::HashListConfig;
+use crate::quarantine::sha256_file;
+
+#[derive(Debug, Error)]
+pub enum ReputationError {
+    #[error("failed to read {path}: {source}")]
+    IoError {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// What a list file looked like when it was last loaded.
+type Stamp = Option<(SystemTime, u64)>;
+
+#[derive(Debug, Default)]
+struct Lists {
+    /// Hash to the name or comment it was listed with
+    blocked: HashMap<String, String>,
+    allowed: HashSet<String>,
+    stamps: Vec<Stamp>,
+}
+
+/// SHA-256 block and allow lists, reloaded when a list file changes.
+#[derive(Debug)]
+pub struct HashLists {
+    config: HashListConfig,
+    lists: Mutex<Lists>,
+}
+
+impl HashLists {
+    /// Reads every configured list. Blocking.
+    pub fn load(config: &HashListConfig) -> Result<Self, ReputationError> {
+        let lists = read_lists(config)?;
+        info!(
+            blocked = lists.blocked.len(),
+            allowed = lists.allowed.len(),
+            "loaded hash lists"
+        );
+
+        Ok(Self {
+            config: config.clone(),
+            lists: Mutex::new(lists),
+        })
+    }
+
+    /// Hashes a file and rejects it if it's blocklisted and not allowlisted.
+    /// Blocking.
+    pub fn check(&self, path: &Path) -> Result<(), ScanRejection> {
+        self.reload_if_changed();
+        let sha256 = sha256_file(path)?;
+
+        let lists = self.lists.lock().expect("hash lists poisoned");
+        match lists.blocked.get(&sha256) {
+            Some(_) if lists.allowed.contains(&sha256) => {
+                info!(path = %path.display(), sha256, "blocklisted hash is allowlisted");
+                Ok(())
+            }
+            Some(label) => Err(ScanRejection::KnownBadHash {
+                sha256,
+                label: label.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Rereads the lists when any file's mtime or size changed. A list that
+    /// can't be read keeps the previous lists active.
+    fn reload_if_changed(&self) {
+        let stamps = stamps(&self.config);
+        if self.lists.lock().expect("hash lists poisoned").stamps == stamps {
+            return;
+        }
+
+        match read_lists(&self.config) {
+            Ok(lists) => {
+                info!(
+                    blocked = lists.blocked.len(),
+                    allowed = lists.allowed.len(),
+                    "reloaded hash lists"
+                );
+                *self.lists.lock().expect("hash lists poisoned") = lists;
+            }
+            Err(e) => {
+                warn!(error = %e, "failed to reload hash lists, keeping the previous ones");
+                self.lists.lock().expect("hash lists poisoned").stamps = stamps;
+            }
+        }
+    }
+}
+
+fn stamps(config: &HashListConfig) -> Vec<Stamp> {
+    config
+        .blocklists
+        .iter()
+        .chain(&config.allowlists)
+        .map(|path| {
+            let meta = std::fs::metadata(path).ok()?;
+            Some((meta.modified().ok()?, meta.len()))
+        })
+        .collect()
+}
+
+fn read_lists(config: &HashListConfig) -> Result<Lists, ReputationError> {
+    // Stamped before reading so a write during the read triggers a reload
+    let stamps = stamps(config);
+    let mut lists = Lists {
+        stamps,
+        ..Lists::default()
+    };
+
+    for path in &config.blocklists {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for (hash, label) in read_list(path)? {
+            let label = label.unwrap_or_else(|| name.clone());
+            lists.blocked.insert(hash, label);
+        }
+    }
+    for path in &config.allowlists {
+        lists
+            .allowed
+            .extend(read_list(path)?.into_iter().map(|(hash, _)| hash));
+    }
+
+    Ok(lists)
+}
+
+/// Parses one list: a hash per line, optionally followed by a name or
+/// comment (so `sha256sum` output works as is). Blank lines and lines
+/// starting with `#` are skipped.
+fn read_list(path: &Path) -> Result<Vec<(String, Option<String>)>, ReputationError> {
+    let content = std::fs::read_to_string(path).map_err(|source| ReputationError::IoError {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    let mut entries = Vec::new();
+    let mut invalid = 0;
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (hash, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            invalid += 1;
+            continue;
+        }
+
+        let label = rest.trim().trim_start_matches(['*', '#']).trim();
+        let label = (!label.is_empty()).then(|| label.to_string());
+        entries.push((hash.to_ascii_lowercase(), label));
+    }
+
+    if invalid > 0 {
+        warn!(path = %path.display(), lines = invalid, "skipped lines without a SHA-256 hash");
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_list_parsing() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("fakes.txt");
+        std::fs::write(
+            &list,
+            format!(
+                "# fake releases\n\n{}  *Codec.exe\n{EMPTY_SHA256} # empty payload\nnot-a-hash\n",
+                HELLO_SHA256.to_uppercase()
+            ),
+        )
+        .unwrap();
+
+        let entries = read_list(&list).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (HELLO_SHA256.to_string(), Some("Codec.exe".to_string())),
+                (EMPTY_SHA256.to_string(), Some("empty payload".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blocklist_allowlist_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("movie.mkv");
+        std::fs::write(&file, b"hello").unwrap();
+        let blocklist = dir.path().join("block.txt");
+        let allowlist = dir.path().join("allow.txt");
+        std::fs::write(&blocklist, format!("{HELLO_SHA256}\n")).unwrap();
+        std::fs::write(&allowlist, "").unwrap();
+
+        let lists = HashLists::load(&HashListConfig {
+            blocklists: vec![blocklist.clone()],
+            allowlists: vec![allowlist.clone()],
+        })
+        .unwrap();
+
+        match lists.check(&file) {
+            Err(ScanRejection::KnownBadHash { sha256, label }) => {
+                assert_eq!(sha256, HELLO_SHA256);
+                assert_eq!(label, "block.txt");
+            }
+            other => panic!("expected KnownBadHash, got {other:?}"),
+        }
+
+        // A different size is enough to notice the change, whatever the mtime
+        std::fs::write(&allowlist, format!("{HELLO_SHA256} false positive\n")).unwrap();
+        assert!(lists.check(&file).is_ok());
+
+        // An unreadable list keeps the previous ones
+        std::fs::remove_file(&allowlist).unwrap();
+        assert!(lists.check(&file).is_ok());
+    }
+
+    #[test]
+    fn test_missing_list_fails_load() {
+        let config = HashListConfig {
+            blocklists: vec![PathBuf::from("/nonexistent/block.txt")],
+            allowlists: Vec::new(),
+        };
+        assert!(HashLists::load(&config).is_err());
+    }
+}
@@ -1,127 +1,445 @@
-use std::path::Path;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use thiserror::Error;
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::mpsc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::checks;
-use crate::config::ScannerConfig;
-use crate::watcher::FileEvent;
+use crate::arr::{self, ArrClient};
+use crate::checks::{self, Check, CheckError, ScanRejection, ScanTarget, Verdict};
+use crate::config::{PathOverride, ScannerConfig};
+use crate::quarantine::{self, QuarantineError, QuarantineStore};
+use crate::release;
+use crate::watcher::{FileEvent, Pipeline};
 
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum ScannerError {
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
+
+    #[error("Failed to set up checks: {0}")]
+    CheckError(#[from] CheckError),
+
+    #[error("Failed to open quarantine: {0}")]
+    QuarantineError(#[from] QuarantineError),
+}
+
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Everything a scan needs besides the file itself.
+struct ScanContext {
+    config: ScannerConfig,
+    overrides: Vec<PathOverride>,
+    checks: Vec<Box<dyn Check>>,
+    quarantine: QuarantineStore,
+    arr: Vec<ArrClient>,
+    /// Canonical watched paths; nothing outside them is ever touched.
+    roots: Vec<PathBuf>,
 }
 
 pub async fn run_scanner(
     config: ScannerConfig,
+    overrides: Vec<PathOverride>,
+    roots: Vec<PathBuf>,
     mut rx: mpsc::Receiver<FileEvent>,
     tx: mpsc::Sender<FileEvent>,
     mut shutdown: tokio::sync::broadcast::Receiver<()>,
 ) -> Result<(), ScannerError> {
-    tokio::fs::create_dir_all(&config.quarantine_dir).await?;
+    let quarantine = QuarantineStore::open(&config.quarantine_dir)?;
+    let checks = load_checks(&config).await?;
+    let http_client = reqwest::Client::new();
+    let arr = config
+        .arr
+        .iter()
+        .map(|c| ArrClient::new(http_client.clone(), c.clone()))
+        .collect();
+    let mut canonical_roots = Vec::with_capacity(roots.len());
+    for root in roots {
+        canonical_roots.push(tokio::fs::canonicalize(&root).await.unwrap_or(root));
+    }
+    let mut ctx = ScanContext {
+        config,
+        overrides,
+        checks,
+        quarantine,
+        arr,
+        roots: canonical_roots,
+    };
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut expiry = tokio::time::interval(EXPIRY_INTERVAL);
 
     loop {
         let event = tokio::select! {
             Some(event) = rx.recv() => event,
-            _ = shutdown.recv() => break,
-            else => break,
-        };
-        let FileEvent::Detected { path, size } = event else {
-            continue;
-        };
-
-        let ext = path
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("")
-            .to_ascii_lowercase();
-
-        if config.delete_junk && is_junk(&ext, &config.junk_extensions) {
-            if let Err(e) = tokio::fs::remove_file(&path).await {
-                error!(path = %path.display(), error = %e, "failed to delete junk file");
-            } else {
-                try_remove_empty_parent(&path).await;
-                let _ = tx
-                    .send(FileEvent::Cleaned {
-                        path,
-                        reason: format!("junk extension: .{ext}"),
-                    })
-                    .await;
+            _ = hangup.recv() => {
+                // Keep the previous checks if e.g. new YARA rules don't compile
+                match load_checks(&ctx.config).await {
+                    Ok(checks) => ctx.checks = checks,
+                    Err(e) => error!(error = %e, "check reload failed, keeping previous checks"),
+                }
+                continue;
             }
-            continue;
-        }
-
-        if config.block_executables {
-            if let Err(rejection) = checks::check_executable_extension(&path) {
-                quarantine_file(&path, &config.quarantine_dir).await;
-                try_remove_empty_parent(&path).await;
-                let _ = tx
-                    .send(FileEvent::Failed {
-                        path,
-                        error: rejection.to_string(),
-                    })
-                    .await;
+            _ = expiry.tick(), if ctx.config.quarantine_expiry_days.is_some() => {
+                purge_expired(&ctx).await;
                 continue;
             }
-        }
+            _ = shutdown.recv() => break,
+            else => break,
+        };
 
-        if let Err(_rejection) = checks::check_extension(&path, &config.allowed_extensions) {
-            if config.delete_junk {
-                if let Err(e) = tokio::fs::remove_file(&path).await {
-                    error!(path = %path.display(), error = %e, "failed to delete file");
-                } else {
-                    try_remove_empty_parent(&path).await;
+        match event {
+            FileEvent::Detected {
+                pipeline,
+                path,
+                size,
+            } => {
+                if is_released(&ctx, &path, size).await {
                     let _ = tx
-                        .send(FileEvent::Cleaned {
+                        .send(FileEvent::Scanned {
+                            pipeline,
                             path,
-                            reason: format!("extension not allowed: .{ext}"),
+                            clean: true,
                         })
                         .await;
+                    continue;
                 }
+                scan_file(&ctx, pipeline, &path, size, &tx).await;
+                try_remove_empty_parent(&path).await;
+            }
+            FileEvent::DetectedBatch {
+                pipeline,
+                dir,
+                files,
+            } => {
+                scan_release(&ctx, pipeline, &dir, files, &tx).await;
             }
-            continue;
+            other => {
+                let _ = tx.send(other).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Scans every file of a release directory before touching its directories,
+/// so cleanup never removes them mid-import. When a release trips any
+/// of the release heuristics, every file of the batch is quarantined.
+/// Directories only go once empty, they may be existing ones like `Season 01`.
+async fn scan_release(
+    ctx: &ScanContext,
+    pipeline: Pipeline,
+    dir: &Path,
+    files: Vec<(PathBuf, u64)>,
+    tx: &mpsc::Sender<FileEvent>,
+) {
+    let config = &ctx.config;
+
+    if let Err(rejection) = checks::check_within_roots(dir, &ctx.roots).await {
+        error!(path = %dir.display(), error = %rejection, "refusing to touch release");
+        let _ = tx
+            .send(FileEvent::Failed {
+                pipeline,
+                path: dir.to_path_buf(),
+                error: rejection.to_string(),
+            })
+            .await;
+        return;
+    }
+
+    let mut pending = Vec::with_capacity(files.len());
+    for (path, size) in files {
+        if is_released(ctx, &path, size).await {
+            let _ = tx
+                .send(FileEvent::Scanned {
+                    pipeline,
+                    path,
+                    clean: true,
+                })
+                .await;
+        } else {
+            pending.push((path, size));
         }
+    }
+    let files = pending;
 
-        // Size and magic byte checks
-        if let Err(rejection) = check_content(&path, size).await {
-            quarantine_file(&path, &config.quarantine_dir).await;
-            try_remove_empty_parent(&path).await;
+    let findings = release::check_release(dir, &files, &config.release, config.block_executables);
+    if !findings.is_empty() {
+        let reason = release::report(&findings);
+        for (path, _) in &files {
+            quarantine_file(ctx, pipeline, path, &reason, false, tx).await;
+        }
+    } else {
+        for (path, size) in &files {
+            scan_file(ctx, pipeline, path, *size, tx).await;
+        }
+    }
+
+    // Deepest first, so `Sample/` goes before the release itself
+    let mut dirs: Vec<&Path> = files
+        .iter()
+        .flat_map(|(path, _)| path.ancestors().skip(1))
+        .filter(|ancestor| ancestor.starts_with(dir))
+        .collect();
+    dirs.sort_by_key(|dir| (std::cmp::Reverse(dir.components().count()), *dir));
+    dirs.dedup();
+    for dir in dirs {
+        if tokio::fs::remove_dir(dir).await.is_ok() {
+            info!(path = %dir.display(), "removed empty directory");
+        }
+    }
+}
+
+async fn scan_file(
+    ctx: &ScanContext,
+    pipeline: Pipeline,
+    path: &Path,
+    size: u64,
+    tx: &mpsc::Sender<FileEvent>,
+) {
+    let config = &ctx.config;
+    let path = path.to_path_buf();
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    // Never follow a path out of the library or open a FIFO. Such paths
+    // are only reported, quarantining them could move the wrong file
+    match checks::check_file_kind(&path, &ctx.roots).await {
+        Ok(()) => {}
+        Err(rejection @ (ScanRejection::OutsideWatchedPaths(_) | ScanRejection::IoError(_))) => {
+            error!(path = %path.display(), error = %rejection, "refusing to touch file");
             let _ = tx
                 .send(FileEvent::Failed {
+                    pipeline,
                     path,
                     error: rejection.to_string(),
                 })
                 .await;
-            continue;
+            return;
+        }
+        Err(rejection) => {
+            quarantine_file(ctx, pipeline, &path, &rejection.to_string(), false, tx).await;
+            return;
         }
+    }
 
-        let _ = tx.send(FileEvent::Scanned { path, clean: true }).await;
+    let allowed = PathOverride::for_path(&ctx.overrides, &path)
+        .and_then(|o| o.allowed_extensions.clone())
+        .unwrap_or_else(|| config.allowed_extensions.clone());
+    let target = ScanTarget {
+        path,
+        size,
+        extension: ext,
+        allowed,
+    };
+
+    for check in &ctx.checks {
+        match check.run(&target).await {
+            Verdict::Pass => {}
+            Verdict::Warn(rejection) => {
+                warn!(
+                    path = %target.path.display(),
+                    check = check.name(),
+                    reason = %rejection,
+                    "check warning"
+                );
+            }
+            Verdict::Skip(_) => return,
+            Verdict::Delete(rejection) => {
+                delete_file(ctx, pipeline, target.path, rejection, tx).await;
+                return;
+            }
+            Verdict::Quarantine(rejection) => {
+                let path = target.path;
+                match &rejection {
+                    ScanRejection::MalwareDetected { signature } => {
+                        warn!(path = %path.display(), signature, "clamd reported malware");
+                    }
+                    ScanRejection::KnownBadHash { sha256, label } => {
+                        warn!(path = %path.display(), sha256, label, "known bad file");
+                    }
+                    _ => {}
+                }
+                let malware = rejection.is_malware();
+                quarantine_file(ctx, pipeline, &path, &rejection.to_string(), malware, tx).await;
+                return;
+            }
+        }
     }
 
-    Ok(())
+    let path = target.path;
+    if config.strip_exec_bits {
+        strip_exec_bits(&path).await;
+    }
+
+    let _ = tx
+        .send(FileEvent::Scanned {
+            pipeline,
+            path,
+            clean: true,
+        })
+        .await;
 }
 
-async fn check_content(path: &Path, size: u64) -> Result<(), checks::ScanRejection> {
-    checks::check_file_size(path, size)?;
-    checks::check_file_type(path).await?;
-    Ok(())
+async fn load_checks(config: &ScannerConfig) -> Result<Vec<Box<dyn Check>>, CheckError> {
+    let config = config.clone();
+    let checks = tokio::task::spawn_blocking(move || checks::build_checks(&config))
+        .await
+        .expect("check setup task panicked")?;
+
+    let names: Vec<&str> = checks.iter().map(|c| c.name()).collect();
+    info!(checks = names.join(", "), "scanner checks ready");
+    Ok(checks)
 }
 
-async fn quarantine_file(path: &Path, quarantine_dir: &Path) {
-    let filename = path
-        .file_name()
-        .unwrap_or(std::ffi::OsStr::new("unknown_file"));
-    let quarantine_path = quarantine_dir.join(filename);
+async fn delete_file(
+    ctx: &ScanContext,
+    pipeline: Pipeline,
+    path: PathBuf,
+    rejection: ScanRejection,
+    tx: &mpsc::Sender<FileEvent>,
+) {
+    if let Err(e) = tokio::fs::remove_file(&path).await {
+        error!(path = %path.display(), error = %e, "failed to delete file");
+        return;
+    }
 
-    if let Err(e) = tokio::fs::rename(path, &quarantine_path).await {
-        error!(path = %path.display(), error = %e, "failed to quarantine file");
+    // Sonarr/Radarr don't track junk like .nfo files
+    if !matches!(rejection, ScanRejection::JunkFile(_)) {
+        notify_arr(ctx, &path);
     }
+    let _ = tx
+        .send(FileEvent::Cleaned {
+            pipeline,
+            path,
+            reason: rejection.to_string(),
+        })
+        .await;
+}
+
+/// Quarantines a file or release and reports the outcome: `Quarantined`,
+/// or, when the move failed, `Failed` (`Scanned { clean: false }` for
+/// malware) since the file is still in place.
+async fn quarantine_file(
+    ctx: &ScanContext,
+    pipeline: Pipeline,
+    path: &Path,
+    reason: &str,
+    malware: bool,
+    tx: &mpsc::Sender<FileEvent>,
+) {
+    let store = ctx.quarantine.clone();
+    let owned_path = path.to_path_buf();
+    let owned_reason = reason.to_string();
+    let result = tokio::task::spawn_blocking(move || store.quarantine(&owned_path, &owned_reason))
+        .await
+        .expect("quarantine task panicked");
+
+    let path = path.to_path_buf();
+    let event = match result {
+        Ok(record) => {
+            warn!(
+                path = %path.display(),
+                id = record.id,
+                reason,
+                "file quarantined"
+            );
+            notify_arr(ctx, &path);
+            FileEvent::Quarantined {
+                pipeline,
+                path,
+                id: record.id,
+                reason: reason.to_string(),
+                malware,
+            }
+        }
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "failed to quarantine file");
+            if malware {
+                FileEvent::Scanned {
+                    pipeline,
+                    path,
+                    clean: false,
+                }
+            } else {
+                FileEvent::Failed {
+                    pipeline,
+                    path,
+                    error: format!("{reason} (quarantine failed: {e})"),
+                }
+            }
+        }
+    };
+    let _ = tx.send(event).await;
 }
 
-fn is_junk(ext: &str, junk_extensions: &[String]) -> bool {
-    junk_extensions.iter().any(|j| j.eq_ignore_ascii_case(ext))
+/// Lets Sonarr/Radarr know a file they may have imported is gone, without
+/// holding up the scan.
+fn notify_arr(ctx: &ScanContext, path: &Path) {
+    if ctx.arr.is_empty() {
+        return;
+    }
+    let clients = ctx.arr.clone();
+    let path = path.to_path_buf();
+    tokio::spawn(async move { arr::notify_removed(&clients, &path).await });
+}
+
+/// Files restored by hand with `homed quarantine restore` skip the checks once.
+async fn is_released(ctx: &ScanContext, path: &Path, size: u64) -> bool {
+    let store = ctx.quarantine.clone();
+    let owned_path = path.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || store.take_released(&owned_path, size))
+        .await
+        .expect("quarantine task panicked");
+
+    match result {
+        Ok(true) => {
+            info!(path = %path.display(), "restored from quarantine, skipping checks");
+            true
+        }
+        Ok(false) => false,
+        Err(e) => {
+            error!(error = %e, "failed to read released quarantine items");
+            false
+        }
+    }
+}
+
+async fn purge_expired(ctx: &ScanContext) {
+    let Some(days) = ctx.config.quarantine_expiry_days else {
+        return;
+    };
+    let store = ctx.quarantine.clone();
+    let max_age = Duration::from_secs(days * 86_400);
+    let result = tokio::task::spawn_blocking(move || store.purge_expired(max_age))
+        .await
+        .expect("quarantine task panicked");
+
+    match result {
+        Ok(purged) => quarantine::log_purged(&purged),
+        Err(e) => error!(error = %e, "quarantine expiry failed"),
+    }
+}
+
+async fn strip_exec_bits(path: &Path) {
+    let Ok(metadata) = tokio::fs::symlink_metadata(path).await else {
+        return;
+    };
+    let mode = metadata.permissions().mode();
+    if !metadata.is_file() || mode & 0o111 == 0 {
+        return;
+    }
+
+    let permissions = std::fs::Permissions::from_mode(mode & !0o111);
+    if let Err(e) = tokio::fs::set_permissions(path, permissions).await {
+        warn!(path = %path.display(), error = %e, "failed to strip exec bits");
+    }
 }
 
 async fn try_remove_empty_parent(path: &Path) {
@@ -131,3 +449,55 @@ async fn try_remove_empty_parent(path: &Path) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan_context(quarantine_dir: &Path, root: &Path) -> ScanContext {
+        let config: ScannerConfig = toml::from_str(&format!(
+            r#"
+            quarantine_dir = "{}"
+            allowed_extensions = ["mkv"]
+            block_executables = true
+            delete_junk = false
+            "#,
+            quarantine_dir.display()
+        ))
+        .unwrap();
+        ScanContext {
+            quarantine: QuarantineStore::open(&config.quarantine_dir).unwrap(),
+            config,
+            overrides: Vec::new(),
+            checks: Vec::new(),
+            arr: Vec::new(),
+            roots: vec![root.canonicalize().unwrap()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_suspicious_release_keeps_existing_files() {
+        let root = tempfile::tempdir().unwrap();
+        let quarantine_dir = tempfile::tempdir().unwrap();
+        let ctx = scan_context(quarantine_dir.path(), root.path());
+
+        let season = root.path().canonicalize().unwrap().join("Show/Season 01");
+        std::fs::create_dir_all(&season).unwrap();
+        let existing = season.join("Show.S01E01.mkv");
+        std::fs::write(&existing, b"episode one").unwrap();
+        let lure = season.join("Show.S01E02.mkv.exe");
+        std::fs::write(&lure, b"MZ").unwrap();
+
+        let (tx, mut rx) = mpsc::channel(8);
+        scan_release(&ctx, Pipeline::Media, &season, vec![(lure.clone(), 2)], &tx).await;
+        drop(tx);
+
+        let Some(FileEvent::Quarantined { path, .. }) = rx.recv().await else {
+            panic!("expected the new file to be quarantined");
+        };
+        assert_eq!(path, lure);
+        assert!(rx.recv().await.is_none());
+        assert!(!lure.exists());
+        assert_eq!(std::fs::read(&existing).unwrap(), b"episode one");
+    }
+}
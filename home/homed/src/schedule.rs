@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use chrono::{Local, NaiveDateTime, NaiveTime};
+
+/// Parses a local wall-clock time like "03:30".
+pub fn parse_time_of_day(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M").ok()
+}
+
+/// Time left until the next local occurrence of `time`.
+pub fn duration_until(time: NaiveTime) -> Duration {
+    duration_until_from(local_now(), time)
+}
+
+pub fn local_now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+/// Whether `now` is in `start..end`, which spans midnight when `end` is
+/// before `start`.
+pub fn is_within_at(now: NaiveTime, start: NaiveTime, end: NaiveTime) -> bool {
+    if start <= end {
+        start <= now && now < end
+    } else {
+        now >= start || now < end
+    }
+}
+
+pub fn duration_until_from(now: NaiveDateTime, time: NaiveTime) -> Duration {
+    let mut next = now.date().and_time(time);
+    if next <= now {
+        next += chrono::Duration::days(1);
+    }
+
+    (next - now).to_std().unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_time_of_day() {
+        assert_eq!(
+            parse_time_of_day("03:30"),
+            NaiveTime::from_hms_opt(3, 30, 0)
+        );
+        assert!(parse_time_of_day("25:00").is_none());
+        assert!(parse_time_of_day("nightly").is_none());
+    }
+
+    #[test]
+    fn test_later_today() {
+        let time = NaiveTime::from_hms_opt(3, 0, 0).unwrap();
+        assert_eq!(
+            duration_until_from(at(1, 0), time),
+            Duration::from_secs(2 * 3600)
+        );
+    }
+
+    #[test]
+    fn test_already_passed_rolls_to_tomorrow() {
+        let time = NaiveTime::from_hms_opt(3, 0, 0).unwrap();
+        assert_eq!(
+            duration_until_from(at(3, 0), time),
+            Duration::from_secs(24 * 3600)
+        );
+        assert_eq!(
+            duration_until_from(at(23, 0), time),
+            Duration::from_secs(4 * 3600)
+        );
+    }
+
+    #[test]
+    fn test_window_spanning_midnight() {
+        let (start, end) = (
+            NaiveTime::from_hms_opt(23, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+        );
+        assert!(is_within_at(at(23, 30).time(), start, end));
+        assert!(is_within_at(at(3, 0).time(), start, end));
+        assert!(!is_within_at(at(7, 0).time(), start, end));
+        assert!(!is_within_at(at(12, 0).time(), start, end));
+        assert!(is_within_at(at(12, 0).time(), end, start));
+    }
+}
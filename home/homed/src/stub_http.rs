@@ -0,0 +1,84 @@
+//! Stand-in HTTP/1.1 server for the tests of clients talking to outside
+//! services.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+pub struct Request {
+    pub line: String,
+    /// Keyed by lowercase name.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Path and query of the request line.
+    pub fn target(&self) -> &str {
+        self.line.split(' ').nth(1).unwrap_or("")
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+pub type Requests = Arc<Mutex<Vec<Request>>>;
+
+/// Serves until the test ends, answering every request with the JSON body
+/// `respond` returns, or 404 for None. Requests are recorded before they
+/// are answered, so they're all there once the client call returns.
+pub async fn spawn(
+    respond: impl Fn(&Request) -> Option<String> + Send + 'static,
+) -> (String, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests: Requests = Arc::default();
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            let mut reader = BufReader::new(conn);
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+
+            let mut headers = HashMap::new();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).await.unwrap();
+                let Some((name, value)) = header.trim_end().split_once(": ") else {
+                    break;
+                };
+                headers.insert(name.to_ascii_lowercase(), value.to_string());
+            }
+            let len = headers
+                .get("content-length")
+                .map_or(0, |len| len.parse().unwrap());
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body).await.unwrap();
+
+            let request = Request {
+                line: line.trim_end().to_string(),
+                headers,
+                body,
+            };
+            let reply = respond(&request);
+            recorded.lock().unwrap().push(request);
+
+            let response = match reply {
+                Some(json) => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{json}",
+                    json.len()
+                ),
+                None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let mut conn = reader.into_inner();
+            conn.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+
+    (url, requests)
+}
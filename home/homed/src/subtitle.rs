@@ -0,0 +1,386 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use tracing::info;
+
+use crate::checks::ScanRejection;
+use crate::config::SubtitleConfig;
+
+/// Characters 0x80-0x9F of Windows-1252; the rest of the upper half is Latin-1.
+/// Undefined slots keep their Latin-1 (C1 control) code point.
+const CP1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+/// ASS/SSA sections that may appear in a script. Anything else is rejected.
+const ASS_SECTIONS: &[&str] = &[
+    "script info",
+    "v4 styles",
+    "v4+ styles",
+    "v4 styles+",
+    "events",
+    "fonts",
+    "graphics",
+    "aegisub project garbage",
+    "aegisub extradata",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1252,
+}
+
+/// Decodes subtitle bytes: UTF-8 and UTF-16 by BOM, anything else that
+/// isn't valid UTF-8 as Windows-1252. `partial` tolerates a multi-byte
+/// sequence cut off at the end, for sniffing the start of a file. `None`
+/// when the text is unusable (broken UTF-16, control characters).
+pub fn decode(bytes: &[u8], partial: bool) -> Option<(String, Encoding)> {
+    let (text, encoding) = if let Some(rest) = bytes.strip_prefix(b"\xef\xbb\xbf") {
+        (String::from_utf8(rest.to_vec()).ok()?, Encoding::Utf8)
+    } else if let Some(rest) = bytes.strip_prefix(b"\xff\xfe") {
+        (decode_utf16(rest, u16::from_le_bytes)?, Encoding::Utf16Le)
+    } else if let Some(rest) = bytes.strip_prefix(b"\xfe\xff") {
+        (decode_utf16(rest, u16::from_be_bytes)?, Encoding::Utf16Be)
+    } else {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => (text.to_string(), Encoding::Utf8),
+            Err(e) if partial && e.error_len().is_none() => (
+                String::from_utf8_lossy(&bytes[..e.valid_up_to()]).into_owned(),
+                Encoding::Utf8,
+            ),
+            Err(_) => (decode_cp1252(bytes), Encoding::Windows1252),
+        }
+    };
+
+    let has_controls = text
+        .chars()
+        .any(|c| (c < ' ' && !matches!(c, '\t' | '\n' | '\r')) || c == '\u{7f}');
+    if has_controls {
+        return None;
+    }
+
+    Some((text, encoding))
+}
+
+fn decode_utf16(bytes: &[u8], to_u16: fn([u8; 2]) -> u16) -> Option<String> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let units = bytes.chunks_exact(2).map(|pair| to_u16([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .ok()
+}
+
+fn decode_cp1252(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            0x80..=0x9f => CP1252_HIGH[(b - 0x80) as usize],
+            _ => b as char,
+        })
+        .collect()
+}
+
+/// Parses the whole subtitle, rejects malformed files and blocked patterns,
+/// and rewrites non-UTF-8 files as UTF-8 when configured. Blocking.
+pub fn check_subtitle(path: &Path, config: &SubtitleConfig) -> Result<(), ScanRejection> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    let bytes = std::fs::read(path)?;
+    let (text, encoding) = decode(&bytes, false).ok_or(ScanRejection::InvalidSubtitleEncoding)?;
+    let text = text.replace("\r\n", "\n");
+
+    let structure = match extension.as_str() {
+        "srt" => validate_srt(&text),
+        "vtt" => validate_vtt(&text),
+        "ass" | "ssa" => validate_ass(&text),
+        _ => Ok(()),
+    };
+    structure.map_err(ScanRejection::MalformedSubtitle)?;
+
+    let lower = text.to_lowercase();
+    if let Some(pattern) = config
+        .reject_patterns
+        .iter()
+        .find(|p| lower.contains(&p.to_lowercase()))
+    {
+        return Err(ScanRejection::SubtitleBlockedPattern(pattern.clone()));
+    }
+
+    if config.convert_to_utf8 && encoding != Encoding::Utf8 {
+        let tmp = path.with_extension(format!("{extension}.tmp"));
+        let mode = std::fs::metadata(path)?.permissions().mode();
+        std::fs::write(&tmp, text.as_bytes())?;
+        std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&tmp, path)?;
+        info!(path = %path.display(), from = ?encoding, "converted subtitle to UTF-8");
+    }
+
+    Ok(())
+}
+
+/// Cue blocks separated by blank lines.
+fn blocks(text: &str) -> impl Iterator<Item = Vec<&str>> {
+    text.split("\n\n")
+        .map(|block| {
+            block
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|lines| !lines.is_empty())
+}
+
+/// `[[hh:]mm:]ss<sep>fraction` as milliseconds.
+fn parse_time(value: &str, separators: &[char]) -> Option<u64> {
+    let (clock, fraction) = value.trim().rsplit_once(separators)?;
+    if fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let millis = format!("{fraction:0<3}")[..3].parse::<u64>().ok()?;
+
+    let mut seconds = 0u64;
+    let parts: Vec<&str> = clock.split(':').collect();
+    if parts.is_empty() || parts.len() > 3 {
+        return None;
+    }
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let value: u64 = part.parse().ok()?;
+        if i > 0 && value >= 60 {
+            return None;
+        }
+        seconds = seconds * 60 + value;
+    }
+    Some(seconds * 1000 + millis)
+}
+
+/// `start --> end [settings]`.
+fn parse_timing(line: &str, separators: &[char]) -> Option<(u64, u64)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    let start = parse_time(start, separators)?;
+    let end = parse_time(end, separators)?;
+    (start <= end).then_some((start, end))
+}
+
+fn validate_srt(text: &str) -> Result<(), String> {
+    let mut cues = 0;
+    for (i, lines) in blocks(text).enumerate() {
+        // The numeric index is optional in practice
+        let timing = if lines[0].trim().chars().all(|c| c.is_ascii_digit()) {
+            lines.get(1)
+        } else {
+            lines.first()
+        };
+        match timing {
+            Some(line) if parse_timing(line, &[',', '.']).is_some() => cues += 1,
+            _ => return Err(format!("cue {}: missing or invalid timing", i + 1)),
+        }
+    }
+
+    if cues == 0 {
+        return Err("no cues".to_string());
+    }
+    Ok(())
+}
+
+fn validate_vtt(text: &str) -> Result<(), String> {
+    let text = text.trim_start_matches('\u{feff}');
+    let header = text.lines().next().unwrap_or("");
+    if header != "WEBVTT" && !header.starts_with("WEBVTT ") && !header.starts_with("WEBVTT\t") {
+        return Err("missing WEBVTT header".to_string());
+    }
+
+    let mut cues = 0;
+    for (i, lines) in blocks(text).enumerate().skip(1) {
+        let first = lines[0];
+        if ["NOTE", "STYLE", "REGION"]
+            .iter()
+            .any(|k| first.starts_with(k))
+        {
+            continue;
+        }
+        // An optional cue identifier precedes the timing line
+        let timing = if first.contains("-->") {
+            Some(first)
+        } else {
+            lines.get(1).copied()
+        };
+        match timing {
+            Some(line) if parse_timing(line, &['.']).is_some() => cues += 1,
+            _ => return Err(format!("block {}: missing or invalid timing", i + 1)),
+        }
+    }
+
+    if cues == 0 {
+        return Err("no cues".to_string());
+    }
+    Ok(())
+}
+
+fn validate_ass(text: &str) -> Result<(), String> {
+    let mut section: Option<String> = None;
+    let mut format: Option<Vec<String>> = None;
+    let mut dialogues = 0;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let n = i + 1;
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.to_ascii_lowercase();
+            if section.is_none() && name != "script info" {
+                return Err("missing [Script Info] section".to_string());
+            }
+            if !ASS_SECTIONS.contains(&name.as_str()) {
+                return Err(format!("line {n}: unknown section [{name}]"));
+            }
+            section = Some(name);
+            continue;
+        }
+
+        match section.as_deref() {
+            None => return Err("missing [Script Info] section".to_string()),
+            Some("events") => {}
+            // Embedded fonts/graphics are uuencoded lines without a key
+            Some("fonts" | "graphics") => continue,
+            Some(_) if line.contains(':') => continue,
+            Some(_) => return Err(format!("line {n}: expected key: value")),
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(format!("line {n}: expected key: value"));
+        };
+        match key {
+            "Format" => {
+                format = Some(value.split(',').map(|f| f.trim().to_string()).collect());
+            }
+            "Dialogue" | "Comment" => {
+                let Some(fields) = &format else {
+                    return Err(format!("line {n}: event before Format line"));
+                };
+                let values: Vec<&str> = value.splitn(fields.len(), ',').collect();
+                if values.len() != fields.len() {
+                    return Err(format!("line {n}: expected {} fields", fields.len()));
+                }
+                for (field, value) in fields.iter().zip(&values) {
+                    if (field == "Start" || field == "End") && parse_time(value, &['.']).is_none() {
+                        return Err(format!("line {n}: invalid {field} time"));
+                    }
+                }
+                if key == "Dialogue" {
+                    dialogues += 1;
+                }
+            }
+            // SSA "Command:"/"Movie:" events run programs or load media
+            other => return Err(format!("line {n}: unsupported event type {other}")),
+        }
+    }
+
+    if dialogues == 0 {
+        return Err("no Dialogue events".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str =
+        "1\n00:00:01,000 --> 00:00:02,500\nHello\n\n2\n00:00:03,000 --> 00:00:04,000\nWorld\n";
+
+    const ASS: &str = "[Script Info]\nScriptType: v4.00+\n\n[V4+ Styles]\nFormat: Name, Fontname\nStyle: Default,Arial\n\n[Events]\nFormat: Layer, Start, End, Style, Text\nDialogue: 0,0:00:01.00,0:00:02.50,Default,Hello, world\n";
+
+    fn config() -> SubtitleConfig {
+        SubtitleConfig {
+            convert_to_utf8: true,
+            reject_patterns: vec!["https://".to_string(), "become VIP member".to_string()],
+        }
+    }
+
+    fn check(name: &str, contents: &[u8]) -> (Result<(), ScanRejection>, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        let result = check_subtitle(&path, &config());
+        (result, std::fs::read(&path).unwrap())
+    }
+
+    #[test]
+    fn test_valid_subtitles_pass() {
+        assert!(check("subs.srt", SRT.as_bytes()).0.is_ok());
+        assert!(check("subs.ass", ASS.as_bytes()).0.is_ok());
+        let vtt =
+            "WEBVTT\n\nNOTE made by hand\n\nintro\n00:01.000 --> 00:02.000 align:start\nHello\n";
+        assert!(check("subs.vtt", vtt.as_bytes()).0.is_ok());
+    }
+
+    #[test]
+    fn test_cp1252_converted_to_utf8() {
+        let latin = b"1\n00:00:01,000 --> 00:00:02,000\n\xc7a va, \x93cher\x94 ami\n";
+        let (result, contents) = check("subs.srt", latin);
+        assert!(result.is_ok());
+        assert_eq!(
+            String::from_utf8(contents).unwrap(),
+            "1\n00:00:01,000 --> 00:00:02,000\nÇa va, “cher” ami\n"
+        );
+    }
+
+    #[test]
+    fn test_utf16_decoded() {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(SRT.encode_utf16().flat_map(|u| u.to_le_bytes()));
+        assert_eq!(decode(&bytes, false).unwrap().1, Encoding::Utf16Le);
+    }
+
+    #[test]
+    fn test_malformed_srt_rejected() {
+        let broken = "1\n00:00:05,000 --> 00:00:02,000\nBackwards\n";
+        assert!(matches!(
+            check("subs.srt", broken.as_bytes()).0,
+            Err(ScanRejection::MalformedSubtitle(_))
+        ));
+    }
+
+    #[test]
+    fn test_ass_command_event_rejected() {
+        let injected = format!("{ASS}Command: 0,0:00:01.00,0:00:02.00,Default,calc.exe\n");
+        assert!(matches!(
+            check("subs.ass", injected.as_bytes()).0,
+            Err(ScanRejection::MalformedSubtitle(_))
+        ));
+    }
+
+    #[test]
+    fn test_ad_pattern_rejected() {
+        let ad = format!(
+            "{SRT}\n3\n00:00:05,000 --> 00:00:06,000\nBecome VIP member at HTTPS://example.com\n"
+        );
+        assert!(matches!(
+            check("subs.srt", ad.as_bytes()).0,
+            Err(ScanRejection::SubtitleBlockedPattern(p)) if p == "https://"
+        ));
+    }
+
+    #[test]
+    fn test_control_characters_rejected() {
+        assert!(decode(b"1\n00:00:01,000\x00\x01 --> ", false).is_none());
+        assert!(decode(&[0xFF, 0xFE, 0x00, 0x80, 0xC0], false).is_none());
+    }
+}
@@ -1,67 +1,152 @@
-use crate::config::WatcherConfig;
+use crate::config::{PathOverride, WatchBackend, WatcherConfig};
+use crate::fanotify::{FanotifyError, FanotifyWatcher};
+use crate::journal::{FileStamp, JournalError, SharedJournal, StateJournal};
+use crate::schedule;
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::mpsc;
-use tokio::time::Instant;
-use tracing::info;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
 
-#[derive(Debug, Clone, Copy)]
+/// How many discovered files the scan thread may queue ahead of emission.
+const SCAN_QUEUE_CAPACITY: usize = 256;
+/// Log scan progress every N files visited.
+const SCAN_PROGRESS_INTERVAL: u64 = 10_000;
+/// fanotify reads are retried after this long, doubling with each failure,
+/// until this many failed in a row and inotify takes over.
+const FANOTIFY_RETRY_INITIAL: Duration = Duration::from_secs(1);
+const FANOTIFY_MAX_ERRORS: u32 = 6;
+/// Rescans after missed events start at most this often.
+const RESCAN_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MediaType {
     Photo,
     Video,
 }
 
-/// Events representing the file lifecycle within the pipeline.
+/// Which pipeline a file went through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pipeline {
+    Photos,
+    Media,
+}
+
+impl std::fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Photos => "Photos",
+            Self::Media => "Media",
+        })
+    }
+}
+
+/// Events representing the file lifecycle within the pipeline. Every event
+/// carries the pipeline it was produced in; stages copy it from the event
+/// they are handling.
 #[derive(Debug, Clone)]
 pub enum FileEvent {
     Detected {
+        pipeline: Pipeline,
         path: PathBuf,
         size: u64,
     },
+    /// Every file of a release directory, subdirectories included, once it
+    /// has been quiet for `batch_quiet_ms`, so a multi-file import can be
+    /// judged as a unit.
+    DetectedBatch {
+        pipeline: Pipeline,
+        dir: PathBuf,
+        files: Vec<(PathBuf, u64)>,
+    },
+    /// `clean: false` means malware was found but could not be quarantined.
     Scanned {
+        pipeline: Pipeline,
         path: PathBuf,
         clean: bool,
     },
     Enriched {
+        pipeline: Pipeline,
         path: PathBuf,
         media_type: MediaType,
         datetime: chrono::DateTime<chrono::FixedOffset>,
     },
     Unsorted {
+        pipeline: Pipeline,
         path: PathBuf,
         media_type: MediaType,
     },
     Organized {
+        pipeline: Pipeline,
         old_path: PathBuf,
         new_path: PathBuf,
     },
     Cleaned {
+        pipeline: Pipeline,
         path: PathBuf,
         reason: String,
     },
+    /// A file or release was moved to quarantine as `id`.
+    Quarantined {
+        pipeline: Pipeline,
+        path: PathBuf,
+        id: String,
+        reason: String,
+        malware: bool,
+    },
+    /// The watcher lost track of filesystem changes and started a rescan.
+    Rescan { pipeline: Pipeline, reason: String },
     Failed {
+        pipeline: Pipeline,
         path: PathBuf,
         error: String,
     },
 }
 
+impl FileEvent {
+    pub fn pipeline(&self) -> Pipeline {
+        match self {
+            Self::Detected { pipeline, .. }
+            | Self::DetectedBatch { pipeline, .. }
+            | Self::Scanned { pipeline, .. }
+            | Self::Enriched { pipeline, .. }
+            | Self::Unsorted { pipeline, .. }
+            | Self::Organized { pipeline, .. }
+            | Self::Cleaned { pipeline, .. }
+            | Self::Quarantined { pipeline, .. }
+            | Self::Rescan { pipeline, .. }
+            | Self::Failed { pipeline, .. } => *pipeline,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum WatcherError {
     #[error("Failed to watch path: {0}")]
     WatchError(#[from] notify::Error),
+
+    #[error("Failed to open state journal: {0}")]
+    JournalError(#[from] JournalError),
 }
 
 /// Orchestrates filesystem watching and event debouncing.
 ///
 /// Uses a dedicated thread to bridge the blocking `notify` crate with the
 /// async runtime to ensure the executor is not stalled by FS events.
+///
+/// The journal from `open_journal` only decides what tree scans skip; files
+/// are recorded in it by `record_processed` once the pipeline is done.
 pub async fn run_watcher(
+    pipeline: Pipeline,
     config: WatcherConfig,
+    overrides: Vec<PathOverride>,
+    journal: Option<SharedJournal>,
     tx: mpsc::Sender<FileEvent>,
     mut shutdown: tokio::sync::broadcast::Receiver<()>,
 ) -> Result<(), WatcherError> {
@@ -72,66 +157,98 @@ pub async fn run_watcher(
 
     // Notify uses blocking threads so spawn a dedicated bridge thread
     // to prevent blocking the Tokio reactor
+    let backend = config.backend;
     std::thread::spawn(move || {
-        let (std_tx, std_rx) = std::sync::mpsc::channel();
-
-        let mut watcher = RecommendedWatcher::new(
-            move |res: Result<Event, notify::Error>| {
-                if let Ok(event) = res {
-                    let _ = std_tx.send(event);
-                }
-            },
-            notify::Config::default(),
-        )
-        .expect("Failed to create watcher");
-
-        for path in &paths_to_watch {
-            watcher
-                .watch(path, RecursiveMode::Recursive)
-                .expect("Failed to watch path");
-        }
-
-        loop {
-            if thread_stop.load(Ordering::Relaxed) {
-                break;
-            }
-            match std_rx.recv_timeout(Duration::from_secs(1)) {
-                Ok(event) => {
-                    if notify_tx.blocking_send(event).is_err() {
-                        break;
+        if backend == WatchBackend::Fanotify {
+            match FanotifyWatcher::new(&paths_to_watch) {
+                Ok(watcher) => {
+                    info!("watching with fanotify");
+                    match run_fanotify_bridge(watcher, &notify_tx, &thread_stop) {
+                        Ok(()) => return,
+                        Err(e) => {
+                            error!(error = %e, "fanotify keeps failing, falling back to inotify");
+                        }
                     }
                 }
-                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
-                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(e) => {
+                    error!(error = %e, "fanotify backend unavailable, falling back to inotify");
+                }
             }
         }
+        run_notify_bridge(paths_to_watch, notify_tx, thread_stop);
     });
 
+    if journal.is_none() {
+        warn!(
+            "no state_file set: a rescan after missed filesystem events \
+             resubmits every file under the watched paths"
+        );
+    }
+
     let debounce_time = Duration::from_millis(config.debounce_ms);
+    let ignore_rules = IgnoreRules::new(&config, &overrides);
+    let batch_quiet = config.batch_quiet_ms.map(Duration::from_millis);
     let mut pending_files: HashMap<PathBuf, Instant> = HashMap::new();
     let mut check_interval = tokio::time::interval(Duration::from_millis(500));
 
-    // Pick up files that arrived while homed was not running
-    let ready_at = Instant::now() - debounce_time;
-    for watch_path in &config.paths {
-        scan_existing_files(
-            watch_path,
-            &config.ignore_extensions,
-            ready_at,
-            &mut pending_files,
-        );
-    }
-    if !pending_files.is_empty() {
-        info!(
-            count = pending_files.len(),
-            "found existing files on startup"
-        );
-    }
+    // Pick up files that arrived while homed was not running. The walk runs on
+    // a blocking thread and is drained at `startup_scan_rate` files per second
+    // so a huge library doesn't flood the downstream stages.
+    let mut tree_scan = Some(spawn_tree_scan(
+        "startup",
+        config.paths.clone(),
+        ignore_rules.clone(),
+        journal.clone(),
+    ));
+    let mut scan_rate =
+        tokio::time::interval(Duration::from_secs(1) / config.startup_scan_rate.max(1));
+    scan_rate.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let reconcile_time = config
+        .reconcile_at
+        .as_deref()
+        .and_then(schedule::parse_time_of_day);
+    let mut next_reconcile = reconcile_time.map(|t| Instant::now() + schedule::duration_until(t));
+    let mut rescans = Rescans::default();
 
     loop {
+        let reconcile_deadline = next_reconcile.unwrap_or_else(Instant::now);
+        let rescan_deadline = rescans.next_due().unwrap_or_else(Instant::now);
+
         tokio::select! {
             // Handle incoming kernel events. We only care about creation/modification
-            Some(event) = notify_rx.recv() => {
+            Some(res) = notify_rx.recv() => {
+                // The kernel queue overflowed or the backend failed, so events
+                // were lost. Walk the tree again to pick up anything missed.
+                let missed = match &res {
+                    Ok(event) if event.need_rescan() => Some("kernel event queue overflow".to_string()),
+                    Ok(_) => None,
+                    Err(e) => Some(e.to_string()),
+                };
+                if let Some(reason) = missed {
+                    if !rescans.request(&reason, Instant::now(), tree_scan.is_some()) {
+                        debug!(
+                            reason,
+                            "watcher missed filesystem events, rescan already scheduled"
+                        );
+                        continue;
+                    }
+                    warn!(reason, "watcher missed filesystem events, rescanning");
+                    tree_scan = Some(spawn_tree_scan(
+                        "rescan",
+                        config.paths.clone(),
+                        ignore_rules.clone(),
+                        journal.clone(),
+                    ));
+                    if tx.send(FileEvent::Rescan { pipeline, reason }).await.is_err() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                let Ok(event) = res else {
+                    continue;
+                };
+
                 if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
                     for path in event.paths {
                         if path.components().any(|c| {
@@ -139,10 +256,8 @@ pub async fn run_watcher(
                         }) {
                             continue;
                         }
-                        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                            if config.ignore_extensions.iter().any(|ie| ie.eq_ignore_ascii_case(ext)) {
-                                continue;
-                            }
+                        if ignore_rules.is_ignored(&path) {
+                            continue;
                         }
                         if path.exists() && path.is_file() {
                             pending_files.insert(path, Instant::now());
@@ -157,9 +272,25 @@ pub async fn run_watcher(
                 let now = Instant::now();
                 let mut ready_paths = Vec::new();
 
-                // Identify files that haven't received a write event since the last interval
+                if let Some(quiet) = batch_quiet {
+                    for (dir, paths) in take_quiet_batches(&mut pending_files, &config.paths, quiet, now) {
+                        if !emit_batch(pipeline, dir, paths, &tx).await {
+                            return Ok(());
+                        }
+                    }
+                }
+
+                // Identify files that haven't received a write event since the last interval.
+                // With batching on, only files directly in a watched root are left here.
                 for (path, last_seen) in &pending_files {
-                    if now.duration_since(*last_seen) >= debounce_time {
+                    if batch_quiet.is_some() && batch_dir(path, &config.paths).is_some() {
+                        continue;
+                    }
+                    let debounce = PathOverride::for_path(&overrides, path)
+                        .and_then(|o| o.debounce_ms)
+                        .map(Duration::from_millis)
+                        .unwrap_or(debounce_time);
+                    if now.duration_since(*last_seen) >= debounce {
                         ready_paths.push(path.clone());
                     }
                 }
@@ -167,23 +298,67 @@ pub async fn run_watcher(
                 for path in ready_paths {
                     pending_files.remove(&path);
 
-                    if let Ok(metadata) = tokio::fs::metadata(&path).await {
-                        let size = metadata.len();
-                        if size == 0 {
+                    if !emit_detected(pipeline, path, &tx).await {
+                        return Ok(());
+                    }
+                }
+            }
+
+            // Rate-limited drain of the active tree scan. Files that are also
+            // being written right now are left to the regular debounce above.
+            // With batching on, files of a release join the pending batches
+            // like fresh ones, so a rescan still judges releases as a whole.
+            _ = scan_rate.tick(), if tree_scan.is_some() => {
+                let Some(scan_rx) = tree_scan.as_mut() else {
+                    continue;
+                };
+                match scan_rx.try_recv() {
+                    Ok(path) => {
+                        if pending_files.contains_key(&path) {
                             continue;
                         }
-                        let event = FileEvent::Detected {
-                            path: path.clone(),
-                            size,
-                        };
-
-                        if tx.send(event).await.is_err() {
+                        if batch_quiet.is_some() && batch_dir(&path, &config.paths).is_some() {
+                            pending_files.insert(path, Instant::now());
+                        } else if !emit_detected(pipeline, path, &tx).await {
                             return Ok(());
                         }
                     }
+                    Err(TryRecvError::Empty) => {}
+                    Err(TryRecvError::Disconnected) => tree_scan = None,
                 }
             }
 
+            // Rescan folding the missed events reported since the last one
+            _ = tokio::time::sleep_until(rescan_deadline),
+                if rescans.next_due().is_some() && tree_scan.is_none() =>
+            {
+                let Some(reason) = rescans.take_due(Instant::now(), false) else {
+                    continue;
+                };
+                warn!(reason, "watcher missed filesystem events, rescanning");
+                tree_scan = Some(spawn_tree_scan(
+                    "rescan",
+                    config.paths.clone(),
+                    ignore_rules.clone(),
+                    journal.clone(),
+                ));
+                if tx.send(FileEvent::Rescan { pipeline, reason }).await.is_err() {
+                    return Ok(());
+                }
+            }
+
+            // Scheduled reconciliation sweep, diffing the tree against the journal
+            _ = tokio::time::sleep_until(reconcile_deadline), if next_reconcile.is_some() => {
+                info!("starting reconciliation sweep");
+                tree_scan = Some(spawn_tree_scan(
+                    "reconcile",
+                    config.paths.clone(),
+                    ignore_rules.clone(),
+                    journal.clone(),
+                ));
+                next_reconcile = reconcile_time.map(|t| Instant::now() + schedule::duration_until(t));
+            }
+
             _ = shutdown.recv() => {
                 stop_flag.store(true, Ordering::Relaxed);
                 info!(pending = pending_files.len(), "watcher shutting down, draining pending files");
@@ -192,6 +367,7 @@ pub async fn run_watcher(
                 for (path, _) in pending_files.drain() {
                     if let Ok(metadata) = tokio::fs::metadata(&path).await {
                         let _ = tx.send(FileEvent::Detected {
+                            pipeline,
                             path: path.clone(),
                             size: metadata.len(),
                         }).await;
@@ -204,14 +380,352 @@ pub async fn run_watcher(
     }
 }
 
-fn scan_existing_files(
-    dir: &PathBuf,
-    ignore_extensions: &[String],
-    timestamp: Instant,
-    pending: &mut HashMap<PathBuf, Instant>,
+/// Decides when missed events lead to a rescan: right away unless one ran
+/// within `RESCAN_WINDOW` or a tree scan is still running. Requests in
+/// between are folded into one rescan, and one alert, once both are over.
+#[derive(Debug, Default)]
+struct Rescans {
+    last: Option<Instant>,
+    /// First reason, time and number of requests waiting for the next rescan
+    pending: Option<(String, Instant, usize)>,
+}
+
+impl Rescans {
+    /// Records missed events. Returns whether the rescan should start now.
+    fn request(&mut self, reason: &str, now: Instant, scanning: bool) -> bool {
+        if !scanning && self.last.is_none_or(|last| now >= last + RESCAN_WINDOW) {
+            self.last = Some(now);
+            return true;
+        }
+        match &mut self.pending {
+            Some((_, _, count)) => *count += 1,
+            None => self.pending = Some((reason.to_string(), now, 1)),
+        }
+        false
+    }
+
+    /// When the waiting rescan may start, if there is one.
+    fn next_due(&self) -> Option<Instant> {
+        let (_, requested, _) = self.pending.as_ref()?;
+        Some(
+            self.last
+                .map_or(*requested, |last| (last + RESCAN_WINDOW).max(*requested)),
+        )
+    }
+
+    /// Takes the waiting rescan once it's due, with its reason.
+    fn take_due(&mut self, now: Instant, scanning: bool) -> Option<String> {
+        if scanning || self.next_due().is_none_or(|due| now < due) {
+            return None;
+        }
+        let (reason, _, count) = self.pending.take()?;
+        self.last = Some(now);
+        Some(if count > 1 {
+            format!("{reason}, reported {count} times")
+        } else {
+            reason
+        })
+    }
+}
+
+pub async fn open_journal(config: &WatcherConfig) -> Result<Option<SharedJournal>, WatcherError> {
+    let Some(state_file) = config.state_file.clone() else {
+        return Ok(None);
+    };
+
+    let journal = tokio::task::spawn_blocking(move || StateJournal::open(&state_file))
+        .await
+        .expect("journal task panicked")?;
+    info!(entries = journal.len(), "loaded state journal");
+
+    Ok(Some(Arc::new(Mutex::new(journal))))
+}
+
+/// Records the file of an event that ends its trip through the pipeline, so
+/// tree scans skip it from now on. Files still queued in a stage when homed
+/// stops are never recorded and get picked up again. Files that were moved
+/// or deleted have nothing left for a scan to find and aren't recorded.
+pub async fn record_processed(journal: &SharedJournal, event: &FileEvent) {
+    let path = match event {
+        FileEvent::Scanned { path, .. }
+        | FileEvent::Quarantined { path, .. }
+        | FileEvent::Cleaned { path, .. }
+        | FileEvent::Unsorted { path, .. }
+        | FileEvent::Organized { old_path: path, .. } => path,
+        _ => return,
+    };
+    let Ok(metadata) = tokio::fs::metadata(path).await else {
+        return;
+    };
+
+    let stamp = FileStamp::from_metadata(&metadata);
+    if let Err(e) = journal.lock().unwrap().record(path, stamp) {
+        warn!(path = %path.display(), error = %e, "failed to record file in state journal");
+    }
+}
+
+fn run_notify_bridge(
+    paths: Vec<PathBuf>,
+    notify_tx: mpsc::Sender<Result<Event, notify::Error>>,
+    stop: Arc<AtomicBool>,
 ) {
+    let (std_tx, std_rx) = std::sync::mpsc::channel();
+
+    let mut watcher = RecommendedWatcher::new(
+        move |res: Result<Event, notify::Error>| {
+            let _ = std_tx.send(res);
+        },
+        notify::Config::default(),
+    )
+    .expect("Failed to create watcher");
+
+    for path in &paths {
+        watcher
+            .watch(path, RecursiveMode::Recursive)
+            .expect("Failed to watch path");
+    }
+
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        match std_rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(event) => {
+                if notify_tx.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+/// Feeds fanotify events into the same channel as the notify backend, so
+/// the debounce loop doesn't care which one is running.
+///
+/// Only the first read error of a streak is passed on, so it triggers one
+/// rescan; reads are retried with backoff after that. Returns the error
+/// once `FANOTIFY_MAX_ERRORS` reads in a row failed.
+fn run_fanotify_bridge(
+    watcher: FanotifyWatcher,
+    notify_tx: &mpsc::Sender<Result<Event, notify::Error>>,
+    stop: &AtomicBool,
+) -> Result<(), FanotifyError> {
+    let mut failures = 0;
+    while !stop.load(Ordering::Relaxed) {
+        let batch = match watcher.read_events(1000) {
+            Ok(events) => {
+                failures = 0;
+                events.into_iter().map(Ok).collect()
+            }
+            Err(e) => {
+                failures += 1;
+                if failures >= FANOTIFY_MAX_ERRORS {
+                    return Err(e);
+                }
+                let wait = FANOTIFY_RETRY_INITIAL * 2u32.pow(failures - 1);
+                warn!(error = %e, retry_in = ?wait, "fanotify read failed");
+                std::thread::sleep(wait);
+                if failures > 1 {
+                    continue;
+                }
+                vec![Err(notify::Error::generic(&e.to_string()))]
+            }
+        };
+        for event in batch {
+            if notify_tx.blocking_send(event).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Extension filter for one watcher, honouring per-path overrides.
+#[derive(Debug, Clone)]
+struct IgnoreRules {
+    default: Vec<String>,
+    overrides: Vec<PathOverride>,
+}
+
+impl IgnoreRules {
+    fn new(config: &WatcherConfig, overrides: &[PathOverride]) -> Self {
+        Self {
+            default: config.ignore_extensions.clone(),
+            overrides: overrides.to_vec(),
+        }
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+            return false;
+        };
+        let ignore = PathOverride::for_path(&self.overrides, path)
+            .and_then(|o| o.ignore_extensions.as_deref())
+            .unwrap_or(&self.default);
+
+        ignore.iter().any(|ie| ie.eq_ignore_ascii_case(ext))
+    }
+}
+
+/// Sends a `Detected` event.
+///
+/// Empty and vanished files are skipped. Returns false once the
+/// downstream stage has gone away.
+async fn emit_detected(pipeline: Pipeline, path: PathBuf, tx: &mpsc::Sender<FileEvent>) -> bool {
+    let Ok(metadata) = tokio::fs::metadata(&path).await else {
+        return true;
+    };
+    let size = metadata.len();
+    if size == 0 {
+        return true;
+    }
+
+    tx.send(FileEvent::Detected {
+        pipeline,
+        path,
+        size,
+    })
+    .await
+    .is_ok()
+}
+
+/// Sends a `DetectedBatch` for the files of one release. Returns false once
+/// the downstream stage has gone away.
+async fn emit_batch(
+    pipeline: Pipeline,
+    dir: PathBuf,
+    paths: Vec<PathBuf>,
+    tx: &mpsc::Sender<FileEvent>,
+) -> bool {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let Ok(metadata) = tokio::fs::metadata(&path).await else {
+            continue;
+        };
+        if metadata.len() == 0 {
+            continue;
+        }
+        files.push((path, metadata.len()));
+    }
+
+    if files.is_empty() {
+        return true;
+    }
+
+    tx.send(FileEvent::DetectedBatch {
+        pipeline,
+        dir,
+        files,
+    })
+    .await
+    .is_ok()
+}
+
+/// The release directory a file is batched under: the first directory below
+/// its watched root, so `Sample/` and `Extras/` are judged with the main
+/// files. None for files sitting directly in a root, which is never treated
+/// as a single release.
+fn batch_dir(path: &Path, roots: &[PathBuf]) -> Option<PathBuf> {
+    let root = roots.iter().find(|root| path.starts_with(root))?;
+    let mut below = path.strip_prefix(root).ok()?.components();
+    let release = below.next()?;
+    below.next()?;
+    Some(root.join(release))
+}
+
+/// Removes and returns the pending files of every release directory whose
+/// most recent write is at least `quiet` old.
+fn take_quiet_batches(
+    pending: &mut HashMap<PathBuf, Instant>,
+    roots: &[PathBuf],
+    quiet: Duration,
+    now: Instant,
+) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let mut last_activity: HashMap<PathBuf, Instant> = HashMap::new();
+    for (path, last_seen) in pending.iter() {
+        if let Some(dir) = batch_dir(path, roots) {
+            let latest = last_activity.entry(dir).or_insert(*last_seen);
+            *latest = (*latest).max(*last_seen);
+        }
+    }
+
+    let mut batches = Vec::new();
+    for (dir, latest) in last_activity {
+        if now.duration_since(latest) < quiet {
+            continue;
+        }
+        let mut paths: Vec<PathBuf> = pending
+            .keys()
+            .filter(|path| batch_dir(path, roots).as_ref() == Some(&dir))
+            .cloned()
+            .collect();
+        paths.sort();
+        for path in &paths {
+            pending.remove(path);
+        }
+        batches.push((dir, paths));
+    }
+
+    batches
+}
+
+#[derive(Debug, Default)]
+struct ScanProgress {
+    visited: u64,
+    queued: u64,
+    known: u64,
+}
+
+/// Walks the watched trees on a blocking thread, streaming files the journal
+/// doesn't already know about. The bounded channel provides back-pressure so
+/// the walk never runs far ahead of emission. Dropping the receiver stops
+/// the walk, which is how a newer scan supersedes an older one.
+fn spawn_tree_scan(
+    label: &'static str,
+    paths: Vec<PathBuf>,
+    ignore_rules: IgnoreRules,
+    journal: Option<SharedJournal>,
+) -> mpsc::Receiver<PathBuf> {
+    let (tx, rx) = mpsc::channel(SCAN_QUEUE_CAPACITY);
+
+    tokio::task::spawn_blocking(move || {
+        let mut progress = ScanProgress::default();
+        for path in &paths {
+            if !scan_existing_files(
+                label,
+                path,
+                &ignore_rules,
+                journal.as_ref(),
+                &tx,
+                &mut progress,
+            ) {
+                return;
+            }
+        }
+        info!(
+            visited = progress.visited,
+            queued = progress.queued,
+            known = progress.known,
+            "{label} scan finished"
+        );
+    });
+
+    rx
+}
+
+/// Returns false if the receiver was dropped and the walk should stop.
+fn scan_existing_files(
+    label: &str,
+    dir: &Path,
+    ignore_rules: &IgnoreRules,
+    journal: Option<&SharedJournal>,
+    tx: &mpsc::Sender<PathBuf>,
+    progress: &mut ScanProgress,
+) -> bool {
     let Ok(entries) = std::fs::read_dir(dir) else {
-        return;
+        return true;
     };
 
     for entry in entries.flatten() {
@@ -223,7 +737,9 @@ fn scan_existing_files(
             if name_str.starts_with('.') {
                 continue;
             }
-            scan_existing_files(&path, ignore_extensions, timestamp, pending);
+            if !scan_existing_files(label, &path, ignore_rules, journal, tx, progress) {
+                return false;
+            }
             continue;
         }
 
@@ -235,15 +751,305 @@ fn scan_existing_files(
             continue;
         }
 
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-            if ignore_extensions
-                .iter()
-                .any(|ie| ie.eq_ignore_ascii_case(ext))
-            {
+        if ignore_rules.is_ignored(&path) {
+            continue;
+        }
+
+        progress.visited += 1;
+        if progress.visited % SCAN_PROGRESS_INTERVAL == 0 {
+            info!(
+                visited = progress.visited,
+                queued = progress.queued,
+                known = progress.known,
+                "{label} scan progress"
+            );
+        }
+
+        if let (Some(journal), Ok(metadata)) = (journal, std::fs::metadata(&path)) {
+            let stamp = FileStamp::from_metadata(&metadata);
+            if journal.lock().unwrap().is_known(&path, stamp) {
+                progress.known += 1;
                 continue;
             }
         }
 
-        pending.insert(path, timestamp);
+        if tx.blocking_send(path).is_err() {
+            return false;
+        }
+        progress.queued += 1;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    async fn collect(mut rx: mpsc::Receiver<PathBuf>) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        while let Some(path) = rx.recv().await {
+            paths.push(path);
+        }
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_ignore_rules_use_path_override() {
+        let rules = IgnoreRules {
+            default: vec!["part".to_string()],
+            overrides: vec![PathOverride {
+                path: PathBuf::from("/photos/WhatsApp"),
+                ignore_extensions: Some(vec!["opus".to_string()]),
+                ..Default::default()
+            }],
+        };
+
+        assert!(rules.is_ignored(Path::new("/photos/Camera/clip.part")));
+        assert!(!rules.is_ignored(Path::new("/photos/Camera/voice.opus")));
+        assert!(rules.is_ignored(Path::new("/photos/WhatsApp/voice.opus")));
+        assert!(!rules.is_ignored(Path::new("/photos/WhatsApp/clip.part")));
+    }
+
+    #[test]
+    fn test_rescans_coalesce_within_window() {
+        let start = Instant::now();
+        let mut rescans = Rescans::default();
+
+        // The startup scan is still running
+        assert!(!rescans.request("overflow", start, true));
+        assert_eq!(rescans.take_due(start, true), None);
+        assert_eq!(rescans.take_due(start, false).as_deref(), Some("overflow"));
+        assert_eq!(rescans.next_due(), None);
+
+        let later = start + Duration::from_secs(5);
+        assert!(!rescans.request("overflow", later, false));
+        assert!(!rescans.request("read error", later, false));
+        assert_eq!(rescans.next_due(), Some(start + RESCAN_WINDOW));
+        assert_eq!(rescans.take_due(later, false), None);
+        assert_eq!(
+            rescans.take_due(start + RESCAN_WINDOW, false).as_deref(),
+            Some("overflow, reported 2 times")
+        );
+
+        let quiet = start + RESCAN_WINDOW * 3;
+        assert!(rescans.request("overflow", quiet, false));
+        assert_eq!(rescans.next_due(), None);
+    }
+
+    #[test]
+    fn test_files_in_root_are_not_batched() {
+        let roots = vec![PathBuf::from("/media/Movies")];
+        assert!(batch_dir(Path::new("/media/Movies/movie.mkv"), &roots).is_none());
+        assert_eq!(
+            batch_dir(Path::new("/media/Movies/Film (2024)/film.mkv"), &roots),
+            Some(PathBuf::from("/media/Movies/Film (2024)"))
+        );
+        assert_eq!(
+            batch_dir(Path::new("/media/Movies/Film (2024)/Sample/s.mkv"), &roots),
+            Some(PathBuf::from("/media/Movies/Film (2024)"))
+        );
+    }
+
+    #[test]
+    fn test_batch_waits_for_whole_directory() {
+        let roots = vec![PathBuf::from("/tv")];
+        let quiet = Duration::from_secs(30);
+        let now = Instant::now();
+        let mut pending = HashMap::new();
+        pending.insert(PathBuf::from("/tv/Show/S01/e01.mkv"), now - quiet * 2);
+        pending.insert(PathBuf::from("/tv/Show/S02/e01.mkv"), now);
+        pending.insert(PathBuf::from("/tv/Other/S01/e01.mkv"), now - quiet);
+        pending.insert(PathBuf::from("/tv/Other/S01/e01.srt"), now - quiet * 2);
+
+        let batches = take_quiet_batches(&mut pending, &roots, quiet, now);
+
+        assert_eq!(
+            batches,
+            vec![(
+                PathBuf::from("/tv/Other"),
+                vec![
+                    PathBuf::from("/tv/Other/S01/e01.mkv"),
+                    PathBuf::from("/tv/Other/S01/e01.srt"),
+                ]
+            )]
+        );
+        assert_eq!(pending.len(), 2);
+    }
+
+    fn release_findings(names: &[&str]) -> Vec<crate::release::Finding> {
+        let roots = vec![PathBuf::from("/downloads")];
+        let quiet = Duration::from_secs(30);
+        let now = Instant::now();
+        let mut pending: HashMap<PathBuf, Instant> = names
+            .iter()
+            .map(|name| (roots[0].join(name), now - quiet))
+            .collect();
+
+        let batches = take_quiet_batches(&mut pending, &roots, quiet, now);
+        assert_eq!(batches.len(), 1);
+        let (dir, paths) = &batches[0];
+        let files: Vec<_> = paths.iter().map(|path| (path.clone(), 1024)).collect();
+        let config = crate::config::ReleaseConfig::default();
+        crate::release::check_release(dir, &files, &config, true)
+    }
+
+    #[test]
+    fn test_release_subdirectories_judged_together() {
+        use crate::release::Finding;
+
+        let found = release_findings(&[
+            "Movie.2024/Movie.2024.mkv",
+            "Movie.2024/Sample/movie-sample.mkv",
+        ]);
+        assert!(found.is_empty(), "{found:?}");
+
+        let found = release_findings(&["Movie.2024/Movie.2024.mkv", "Movie.2024/Extras/Codec.exe"]);
+        assert_eq!(
+            found,
+            vec![Finding::Executable("Extras/Codec.exe".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tree_scan_skips_hidden_and_ignored() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("Show/Season 01")).unwrap();
+        std::fs::create_dir_all(root.join(".cache")).unwrap();
+        std::fs::write(root.join("Show/Season 01/ep1.mkv"), "x").unwrap();
+        std::fs::write(root.join("Show/Season 01/ep2.mkv.part"), "x").unwrap();
+        std::fs::write(root.join("Show/.hidden.mkv"), "x").unwrap();
+        std::fs::write(root.join(".cache/thumb.jpg"), "x").unwrap();
+
+        let rx = spawn_tree_scan(
+            "startup",
+            vec![root.to_path_buf()],
+            IgnoreRules {
+                default: vec!["part".to_string()],
+                overrides: vec![],
+            },
+            None,
+        );
+
+        assert_eq!(collect(rx).await, vec![root.join("Show/Season 01/ep1.mkv")]);
+    }
+
+    #[tokio::test]
+    async fn test_tree_scan_skips_journaled_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("lib");
+        std::fs::create_dir_all(&root).unwrap();
+        let old = root.join("old.mkv");
+        let new = root.join("new.mkv");
+        std::fs::write(&old, "x").unwrap();
+        std::fs::write(&new, "x").unwrap();
+
+        let mut journal = StateJournal::open(&dir.path().join("j")).unwrap();
+        let stamp = FileStamp::from_metadata(&std::fs::metadata(&old).unwrap());
+        journal.record(&old, stamp).unwrap();
+        let journal = Arc::new(Mutex::new(journal));
+
+        let rules = IgnoreRules {
+            default: vec![],
+            overrides: vec![],
+        };
+        let rx = spawn_tree_scan("startup", vec![root.clone()], rules, Some(journal));
+
+        assert_eq!(collect(rx).await, vec![new]);
+    }
+
+    #[tokio::test]
+    async fn test_only_processed_files_are_journaled() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("lib");
+        std::fs::create_dir_all(&root).unwrap();
+        let queued = root.join("queued.mkv");
+        let scanned = root.join("scanned.mkv");
+        std::fs::write(&queued, "x").unwrap();
+        std::fs::write(&scanned, "x").unwrap();
+        let journal = Arc::new(Mutex::new(
+            StateJournal::open(&dir.path().join("j")).unwrap(),
+        ));
+
+        let pipeline = Pipeline::Media;
+        let detected = FileEvent::Detected {
+            pipeline,
+            path: queued.clone(),
+            size: 1,
+        };
+        record_processed(&journal, &detected).await;
+        let done = FileEvent::Scanned {
+            pipeline,
+            path: scanned,
+            clean: true,
+        };
+        record_processed(&journal, &done).await;
+
+        let rules = IgnoreRules {
+            default: vec![],
+            overrides: vec![],
+        };
+        let rx = spawn_tree_scan("startup", vec![root.clone()], rules, Some(journal));
+        assert_eq!(collect(rx).await, vec![queued]);
+    }
+
+    #[tokio::test]
+    async fn test_startup_scan_batches_releases() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("downloads");
+        let release = root.join("Movie.2024");
+        std::fs::create_dir_all(release.join("Sample")).unwrap();
+        std::fs::write(release.join("Movie.2024.mkv"), "x").unwrap();
+        std::fs::write(release.join("Sample/sample.mkv"), "x").unwrap();
+        std::fs::write(root.join("loose.mkv"), "x").unwrap();
+
+        let config = WatcherConfig {
+            paths: vec![root.clone()],
+            debounce_ms: 50,
+            ignore_extensions: Vec::new(),
+            state_file: None,
+            startup_scan_rate: 1000,
+            reconcile_at: None,
+            batch_quiet_ms: Some(100),
+            backend: WatchBackend::Inotify,
+        };
+        let (tx, mut rx) = mpsc::channel(8);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel(1);
+        let watcher = tokio::spawn(run_watcher(
+            Pipeline::Media,
+            config,
+            Vec::new(),
+            None,
+            tx,
+            shutdown_rx,
+        ));
+
+        let mut loose = None;
+        let mut batch = None;
+        while loose.is_none() || batch.is_none() {
+            match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await {
+                Ok(Some(FileEvent::Detected { path, .. })) => loose = Some(path),
+                Ok(Some(FileEvent::DetectedBatch { dir, files, .. })) => batch = Some((dir, files)),
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+        assert_eq!(loose, Some(root.join("loose.mkv")));
+        let (batch_dir, files) = batch.unwrap();
+        assert_eq!(batch_dir, release);
+        let mut paths: Vec<_> = files.into_iter().map(|(path, _)| path).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                release.join("Movie.2024.mkv"),
+                release.join("Sample/sample.mkv")
+            ]
+        );
+
+        shutdown_tx.send(()).unwrap();
+        watcher.await.unwrap().unwrap();
     }
 }
@@ -0,0 +1,249 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use boreal::memory::{FragmentedMemory, MemoryParams, Region, RegionDescription};
+use thiserror::Error;
+use tracing::{debug, info};
+
+use crate::checks::ScanRejection;
+use crate::config::YaraConfig;
+
+const RULE_EXTS: &[&str] = &["yar", "yara"];
+
+#[derive(Debug, Error)]
+pub enum YaraError {
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("failed to compile {path}: {message}")]
+    CompileError { path: PathBuf, message: String },
+}
+
+/// Compiled rule set from the configured rules directory.
+#[derive(Debug, Clone)]
+pub struct YaraRules {
+    scanner: boreal::Scanner,
+    max_file_size: u64,
+}
+
+impl YaraRules {
+    /// Compiles every `.yar`/`.yara` file in the rules directory, each into
+    /// its own namespace so rule names may repeat across files. Blocking.
+    pub fn load(config: &YaraConfig) -> Result<Self, YaraError> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&config.rules_dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| RULE_EXTS.iter().any(|r| r.eq_ignore_ascii_case(e)))
+            })
+            .collect();
+        files.sort();
+
+        let mut compiler = boreal::Compiler::new();
+        for path in &files {
+            let namespace = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            compiler
+                .add_rules_file_in_namespace(path, &namespace)
+                .map_err(|e| YaraError::CompileError {
+                    path: path.clone(),
+                    message: e.to_string(),
+                })?;
+        }
+
+        info!(files = files.len(), dir = %config.rules_dir.display(), "loaded YARA rules");
+
+        Ok(Self {
+            scanner: compiler.finalize(),
+            max_file_size: config.max_file_size_mb * 1024 * 1024,
+        })
+    }
+
+    /// Matches a file against the rules. Files above the size limit are
+    /// read only in part: their first and last half of the limit, which
+    /// is where droppers and appended payloads sit. Blocking.
+    pub fn check(&self, path: &Path, size: u64) -> Result<(), ScanRejection> {
+        let result = if size > self.max_file_size {
+            debug!(path = %path.display(), size, "large file, YARA scans its head and tail");
+            let memory = HeadAndTail::read(path, size, self.max_file_size / 2)?;
+            self.scanner.scan_fragmented(memory)
+        } else {
+            let contents = std::fs::read(path)?;
+            self.scanner.scan_mem(&contents)
+        };
+        // A timeout still returns the rules matched so far
+        let result = result.unwrap_or_else(|(_, partial)| partial);
+
+        let rules: Vec<String> = result
+            .rules
+            .iter()
+            .map(|rule| rule.name.to_string())
+            .collect();
+
+        if rules.is_empty() {
+            Ok(())
+        } else {
+            Err(ScanRejection::YaraMatch { rules })
+        }
+    }
+}
+
+/// The first and last bytes of a file, at their offsets in the file so
+/// rules like `$mz at 0` still hold. `filesize` is undefined for rules
+/// scanning these.
+#[derive(Debug)]
+struct HeadAndTail {
+    regions: [(usize, Vec<u8>); 2],
+    /// Index of the region `next` lists next
+    next: usize,
+}
+
+impl HeadAndTail {
+    fn read(path: &Path, size: u64, len: u64) -> std::io::Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let mut head = vec![0u8; len as usize];
+        file.read_exact(&mut head)?;
+        let tail_start = size - len;
+        file.seek(SeekFrom::Start(tail_start))?;
+        let mut tail = vec![0u8; len as usize];
+        file.read_exact(&mut tail)?;
+
+        Ok(Self {
+            regions: [(0, head), (tail_start as usize, tail)],
+            next: 0,
+        })
+    }
+}
+
+impl FragmentedMemory for HeadAndTail {
+    fn next(&mut self, _params: &MemoryParams) -> Option<RegionDescription> {
+        let (start, mem) = self.regions.get(self.next)?;
+        self.next += 1;
+        Some(RegionDescription {
+            start: *start,
+            length: mem.len(),
+        })
+    }
+
+    fn fetch(&mut self, _params: &MemoryParams) -> Option<Region<'_>> {
+        let (start, mem) = self.regions.get(self.next.checked_sub(1)?)?;
+        Some(Region { start: *start, mem })
+    }
+
+    fn reset(&mut self) {
+        self.next = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LNK_RULE: &str = r#"
+rule windows_shortcut {
+    strings:
+        $header = { 4C 00 00 00 01 14 02 00 }
+    condition:
+        $header at 0
+}
+"#;
+
+    const PDF_JS_RULE: &str = r#"
+rule pdf_javascript {
+    strings:
+        $pdf = "%PDF"
+        $js = "/JavaScript"
+    condition:
+        $pdf at 0 and $js
+}
+"#;
+
+    fn load_rules(dir: &Path) -> YaraRules {
+        YaraRules::load(&YaraConfig {
+            rules_dir: dir.to_path_buf(),
+            max_file_size_mb: 1,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_matching_rules_reported() {
+        let rules_dir = tempfile::tempdir().unwrap();
+        std::fs::write(rules_dir.path().join("lnk.yar"), LNK_RULE).unwrap();
+        std::fs::write(rules_dir.path().join("pdf.yara"), PDF_JS_RULE).unwrap();
+        std::fs::write(rules_dir.path().join("README.md"), "not a rule").unwrap();
+        let rules = load_rules(rules_dir.path());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.pdf");
+        std::fs::write(&path, b"%PDF-1.7 /OpenAction << /S /JavaScript >>").unwrap();
+
+        let result = rules.check(&path, 40);
+        assert!(matches!(
+            result,
+            Err(ScanRejection::YaraMatch { rules }) if rules == vec!["pdf_javascript".to_string()]
+        ));
+    }
+
+    #[test]
+    fn test_clean_file_passes() {
+        let rules_dir = tempfile::tempdir().unwrap();
+        std::fs::write(rules_dir.path().join("lnk.yar"), LNK_RULE).unwrap();
+        let rules = load_rules(rules_dir.path());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.mkv");
+        std::fs::write(&path, b"\x1a\x45\xdf\xa3matroska").unwrap();
+
+        assert!(rules.check(&path, 12).is_ok());
+    }
+
+    #[test]
+    fn test_large_file_head_and_tail_scanned() {
+        let rules_dir = tempfile::tempdir().unwrap();
+        std::fs::write(rules_dir.path().join("lnk.yar"), LNK_RULE).unwrap();
+        std::fs::write(
+            rules_dir.path().join("markers.yar"),
+            r#"
+rule tail_marker { strings: $s = "TAIL-MARKER" condition: $s }
+rule middle_marker { strings: $s = "MIDDLE-MARKER" condition: $s }
+"#,
+        )
+        .unwrap();
+        let rules = load_rules(rules_dir.path());
+
+        // 3 MiB with a 1 MiB limit: the first and last 512 KiB are scanned
+        let mut contents = vec![0u8; 3 * 1024 * 1024];
+        contents[..8].copy_from_slice(&[0x4C, 0x00, 0x00, 0x00, 0x01, 0x14, 0x02, 0x00]);
+        let middle = contents.len() / 2;
+        contents[middle..middle + 13].copy_from_slice(b"MIDDLE-MARKER");
+        let tail = contents.len() - 100;
+        contents[tail..tail + 11].copy_from_slice(b"TAIL-MARKER");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.mkv");
+        std::fs::write(&path, &contents).unwrap();
+
+        let result = rules.check(&path, contents.len() as u64);
+        let Err(ScanRejection::YaraMatch { mut rules }) = result else {
+            panic!("expected matches, got {result:?}");
+        };
+        rules.sort();
+        assert_eq!(rules, ["tail_marker", "windows_shortcut"]);
+    }
+
+    #[test]
+    fn test_invalid_rule_fails_to_load() {
+        let rules_dir = tempfile::tempdir().unwrap();
+        std::fs::write(rules_dir.path().join("bad.yar"), "rule broken {").unwrap();
+
+        let result = YaraRules::load(&YaraConfig {
+            rules_dir: rules_dir.path().to_path_buf(),
+            max_file_size_mb: 1,
+        });
+        assert!(matches!(result, Err(YaraError::CompileError { .. })));
+    }
+}